@@ -0,0 +1,27 @@
+//! Prints a --binary-log file as CSV: `cargo run --example read_binary_log -- matches.bin`.
+
+use std::fs::File;
+use std::io::BufReader;
+use anyhow::Context;
+use solana_shred_perf::export::{shred_type_name, BinaryLogReader};
+
+fn main() -> anyhow::Result<()> {
+    let path = std::env::args().nth(1).context("usage: read_binary_log <file>")?;
+    let file = File::open(&path).with_context(|| format!("failed to open {}", path))?;
+    let reader = BinaryLogReader::new(BufReader::new(file)).with_context(|| format!("failed to read {}", path))?;
+    let sources = reader.sources().to_vec();
+    println!("slot,index,shred_type,source,delay_nanos");
+    for record in reader {
+        let record = record.with_context(|| format!("failed to read {}", path))?;
+        let source = sources.get(record.source).map_or("?", String::as_str);
+        println!(
+            "{},{},{},{},{}",
+            record.slot,
+            record.index,
+            shred_type_name(record.shred_type),
+            source,
+            record.delay_nanos
+        );
+    }
+    Ok(())
+}
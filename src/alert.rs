@@ -0,0 +1,122 @@
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+use crate::stats::{format_delay, rounded_mean, DelayUnit};
+
+// Matches are averaged over buckets at least this long before being compared with the
+// threshold, so one slow shred can't start or end an alert.
+const BUCKET: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Default)]
+struct AlertState {
+    bucket_start: Option<Instant>,
+    sum: i128,
+    count: u64,
+    // When the rolling average last rose above the threshold.
+    above_since: Option<Instant>,
+    firing: bool,
+}
+
+/// Alerts when a candidate's rolling average delay vs the reference stays above a
+/// threshold for a whole window, for --alert-above-ms.
+pub struct DelayAlert {
+    threshold: i64,
+    window: Duration,
+    cmd: Option<String>,
+    source_names: Vec<Arc<str>>,
+    reference_idx: usize,
+    unit: DelayUnit,
+    // Per source; the reference's stays unused.
+    states: Vec<AlertState>,
+}
+
+impl DelayAlert {
+    pub fn new(threshold: i64, window: Duration, cmd: Option<String>, source_names: &[Arc<str>], reference_idx: usize, unit: DelayUnit) -> Self {
+        Self {
+            threshold,
+            window,
+            cmd,
+            source_names: source_names.to_vec(),
+            reference_idx,
+            unit,
+            states: vec![AlertState::default(); source_names.len()],
+        }
+    }
+
+    pub fn add_source(&mut self, name: Arc<str>) {
+        self.source_names.push(name);
+        self.states.push(AlertState::default());
+    }
+
+    /// Adds one of `candidate_idx`'s delays, matched at `time`.
+    pub fn observe(&mut self, candidate_idx: usize, delay: i64, time: Instant) {
+        let (source, reference) = (&self.source_names[candidate_idx], &self.source_names[self.reference_idx]);
+        let (threshold, unit) = (self.threshold, self.unit);
+        let state = &mut self.states[candidate_idx];
+        let start = *state.bucket_start.get_or_insert(time);
+        if time.saturating_duration_since(start) >= BUCKET && state.count > 0 {
+            let avg = rounded_mean(state.sum, state.count as usize);
+            (state.sum, state.count, state.bucket_start) = (0, 0, Some(time));
+            if avg > self.threshold {
+                let since = *state.above_since.get_or_insert(start);
+                if !state.firing && time.saturating_duration_since(since) >= self.window {
+                    state.firing = true;
+                    warn!(
+                        target: "shred_perf::processor",
+                        "ALERT: {} avg delay vs {} has stayed above {} for {:?} (now {})",
+                        source,
+                        reference,
+                        format_delay(threshold, unit),
+                        self.window,
+                        format_delay(avg, unit)
+                    );
+                    self.run_cmd("firing", source, reference, avg);
+                }
+            } else {
+                state.above_since = None;
+                if state.firing {
+                    state.firing = false;
+                    info!(
+                        target: "shred_perf::processor",
+                        "Resolved: {} avg delay vs {} back to {}, within {}",
+                        source,
+                        reference,
+                        format_delay(avg, unit),
+                        format_delay(threshold, unit)
+                    );
+                    self.run_cmd("resolved", source, reference, avg);
+                }
+            }
+        }
+        let state = &mut self.states[candidate_idx];
+        state.sum += delay as i128;
+        state.count += 1;
+    }
+
+    pub fn firing(&self, source_idx: usize) -> bool {
+        self.states[source_idx].firing
+    }
+
+    // Runs --alert-cmd through the shell without waiting on it; a thread reaps it.
+    fn run_cmd(&self, status: &str, source: &str, reference: &str, avg: i64) {
+        let Some(cmd) = &self.cmd else {
+            return;
+        };
+        let spawned = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .env("SHRED_PERF_ALERT", status)
+            .env("SHRED_PERF_SOURCE", source)
+            .env("SHRED_PERF_REFERENCE", reference)
+            .env("SHRED_PERF_AVG_DELAY_NANOS", avg.to_string())
+            .env("SHRED_PERF_THRESHOLD_NANOS", self.threshold.to_string())
+            .spawn();
+        match spawned {
+            Ok(mut child) => {
+                std::thread::spawn(move || child.wait());
+            }
+            Err(e) => error!(target: "shred_perf::processor", "Failed to run --alert-cmd: {}", e),
+        }
+    }
+}
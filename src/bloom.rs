@@ -0,0 +1,109 @@
+use std::collections::hash_map::DefaultHasher;
+use std::f64::consts::LN_2;
+use std::hash::{Hash, Hasher};
+
+// Entries the first generation is sized for, before any rotation has measured the rate.
+const INITIAL_CAPACITY: usize = 1 << 20;
+const MIN_CAPACITY: usize = 1 << 16;
+
+/// A Bloom filter sized for `capacity` entries at a target false-positive rate.
+struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u64,
+    entries: u64,
+}
+
+impl BloomFilter {
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity as f64;
+        let bits = (-capacity * false_positive_rate.ln() / (LN_2 * LN_2)).ceil().max(64.0) as usize;
+        let hashes = (bits as f64 / capacity * LN_2).round().max(1.0) as u64;
+        Self {
+            bits: vec![0; bits.div_ceil(64)],
+            hashes,
+            entries: 0,
+        }
+    }
+
+    fn insert(&mut self, key: &impl Hash) {
+        for bit in positions(key, self.hashes, self.bits.len()) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.entries += 1;
+    }
+
+    fn contains(&self, key: &impl Hash) -> bool {
+        positions(key, self.hashes, self.bits.len()).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    // Chance a key never inserted tests positive, at the current fill.
+    fn false_positive_rate(&self) -> f64 {
+        let bits = self.bits.len() as f64 * 64.0;
+        (1.0 - (-(self.hashes as f64) * self.entries as f64 / bits).exp()).powi(self.hashes as i32)
+    }
+}
+
+// The bits `key` sets in a filter of `words` u64s, by double hashing one 64-bit hash
+// (Kirsch and Mitzenmacher).
+fn positions(key: &impl Hash, hashes: u64, words: usize) -> impl Iterator<Item = usize> {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    let hash = hasher.finish();
+    let (h1, h2) = (hash, hash.rotate_left(32) | 1);
+    let len = words as u64 * 64;
+    (0..hashes).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+}
+
+/// "Seen before" for keys that no longer need an exact entry, for --bloom-dedup. Bloom
+/// filters can't forget, so keys go into the current of two generations and `rotate`
+/// drops the older one; a key is remembered for one to two rotations.
+pub struct RotatingBloom {
+    current: BloomFilter,
+    previous: Option<BloomFilter>,
+    false_positive_rate: f64,
+}
+
+impl RotatingBloom {
+    pub fn new(false_positive_rate: f64) -> Self {
+        Self {
+            current: BloomFilter::new(INITIAL_CAPACITY, false_positive_rate),
+            previous: None,
+            false_positive_rate,
+        }
+    }
+
+    pub fn insert(&mut self, key: &impl Hash) {
+        self.current.insert(key);
+    }
+
+    pub fn contains(&self, key: &impl Hash) -> bool {
+        self.current.contains(key) || self.previous.as_ref().is_some_and(|previous| previous.contains(key))
+    }
+
+    /// Starts a new generation, sized for as many keys as the one it replaces took.
+    pub fn rotate(&mut self) {
+        let capacity = (self.current.entries as usize).max(MIN_CAPACITY);
+        let current = std::mem::replace(&mut self.current, BloomFilter::new(capacity, self.false_positive_rate));
+        self.previous = Some(current);
+    }
+
+    /// The false-positive rate each generation is sized for.
+    pub fn target_rate(&self) -> f64 {
+        self.false_positive_rate
+    }
+
+    /// Chance a key never inserted tests positive in either generation.
+    pub fn false_positive_rate(&self) -> f64 {
+        let previous = self.previous.as_ref().map_or(0.0, BloomFilter::false_positive_rate);
+        1.0 - (1.0 - self.current.false_positive_rate()) * (1.0 - previous)
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.current.entries + self.previous.as_ref().map_or(0, |previous| previous.entries)
+    }
+
+    pub fn bytes(&self) -> usize {
+        let bytes = |filter: &BloomFilter| filter.bits.len() * 8;
+        bytes(&self.current) + self.previous.as_ref().map_or(0, bytes)
+    }
+}
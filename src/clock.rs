@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+// Back-to-back readings taken to find the timer's step, and the known sleep timed with it.
+const READINGS: usize = 10_000;
+const SLEEP: Duration = Duration::from_millis(20);
+// Coarser than this and sub-microsecond delays collapse together.
+const FINE_RESOLUTION: Duration = Duration::from_micros(1);
+// How far `Instant` and the system clock may disagree over the sleep, short of an NTP step.
+const MAX_DRIFT: Duration = Duration::from_millis(2);
+
+/// Checks at startup that `Instant`, which every delay is measured with, is monotonic and
+/// fine-grained enough, logging the clock source and timer resolution it found and
+/// warning about anything that would make delays nonsensical.
+pub fn check() {
+    let mut backwards = 0;
+    let mut resolution: Option<Duration> = None;
+    let mut last = Instant::now();
+    for _ in 0..READINGS {
+        let now = Instant::now();
+        match now.checked_duration_since(last) {
+            None => backwards += 1,
+            Some(step) if !step.is_zero() => resolution = Some(resolution.map_or(step, |min| min.min(step))),
+            Some(_) => {}
+        }
+        last = now;
+    }
+
+    let (start, wall_start) = (Instant::now(), SystemTime::now());
+    std::thread::sleep(SLEEP);
+    let slept = start.elapsed();
+    let wall_slept = wall_start.elapsed().unwrap_or_default();
+
+    let source = clock_source();
+    info!(
+        "Clock: {}, Instant resolution {}, a {:?} sleep measured {:?}",
+        source.as_deref().map_or("source unknown".to_string(), |source| format!("source {}", source)),
+        resolution.map_or("unknown".to_string(), |resolution| format!("{:?}", resolution)),
+        SLEEP,
+        slept
+    );
+    if backwards > 0 {
+        warn!(
+            "Instant went backwards {} time(s) in {} readings; expect negative or huge delays",
+            backwards, READINGS
+        );
+    }
+    match resolution {
+        None => warn!("Instant didn't advance over {} readings; delays will be coarse or zero", READINGS),
+        Some(resolution) if resolution > FINE_RESOLUTION => {
+            warn!("Instant only advances in steps of {:?}; delays shorter than that can't be told apart", resolution)
+        }
+        Some(_) => {}
+    }
+    if slept < SLEEP {
+        warn!("Instant measured a {:?} sleep as {:?}; the monotonic clock runs slow", SLEEP, slept);
+    }
+    if slept.abs_diff(wall_slept) > MAX_DRIFT {
+        warn!(
+            "Instant measured a sleep as {:?} but the system clock as {:?}; the monotonic clock may be unsteady",
+            slept, wall_slept
+        );
+    }
+}
+
+// The kernel's clocksource, e.g. `tsc`, or `kvm-clock` and `hpet` on some VMs.
+#[cfg(target_os = "linux")]
+fn clock_source() -> Option<String> {
+    let source = std::fs::read_to_string("/sys/devices/system/clocksource/clocksource0/current_clocksource").ok()?;
+    Some(source.trim().to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clock_source() -> Option<String> {
+    None
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use anyhow::Context;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Value {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+}
+
+/// Options from a `--config` file, keyed by long flag name (`timeout-secs` or
+/// `timeout_secs`). Repeatable flags such as `source` take a list.
+#[derive(Deserialize)]
+#[serde(transparent)]
+pub struct ConfigFile(BTreeMap<String, Value>);
+
+impl ConfigFile {
+    /// Parses YAML for `.yaml`/`.yml` files and TOML otherwise.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let context = || format!("failed to parse {}", path.display());
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => serde_yaml::from_str(&text).with_context(context),
+            _ => toml::from_str(&text).with_context(context),
+        }
+    }
+
+    /// The file's options as flags, leaving out any the command line set itself. They go
+    /// through the same parser as the command line, so values are validated the same way.
+    pub fn to_args(&self, command: &Command, matches: &ArgMatches) -> anyhow::Result<Vec<OsString>> {
+        let mut args = Vec::new();
+        for (key, value) in &self.0 {
+            let long = key.replace('_', "-");
+            anyhow::ensure!(long != "config", "a config file can't set `config`");
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long.as_str()))
+                .with_context(|| format!("unknown option `{}` in config file", key))?;
+            if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+                continue;
+            }
+            let values = match value {
+                Value::List(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            for value in values {
+                let value = match value {
+                    Value::Bool(true) => {
+                        args.push(format!("--{}", long).into());
+                        continue;
+                    }
+                    Value::Bool(false) => continue,
+                    Value::Integer(value) => value.to_string(),
+                    Value::Float(value) => value.to_string(),
+                    Value::String(value) => value.clone(),
+                    Value::List(_) => anyhow::bail!("`{}` in config file can't be a nested list", key),
+                };
+                args.push(format!("--{}={}", long, value).into());
+            }
+        }
+        Ok(args)
+    }
+}
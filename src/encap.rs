@@ -0,0 +1,51 @@
+use clap::ValueEnum;
+use crate::pcap;
+
+const GENEVE_ETHERNET: u16 = 0x6558;
+const GENEVE_IPV4: u16 = 0x0800;
+const GENEVE_IPV6: u16 = 0x86dd;
+
+/// A tunnel header wrapped around the shred, for --decapsulate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Encapsulation {
+    /// VXLAN (RFC 7348): an 8-byte header, then the mirrored Ethernet frame.
+    Vxlan,
+    /// Geneve (RFC 8926): an 8-byte header plus options, then the mirrored Ethernet
+    /// frame or IP packet.
+    Geneve,
+}
+
+/// How a source's datagrams are unwrapped before they are parsed as shreds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Preprocess {
+    /// Bytes dropped from the front of every datagram, for --strip-prefix-bytes.
+    pub strip_prefix: usize,
+    /// Tunnel decoded after the prefix is stripped.
+    pub encapsulation: Option<Encapsulation>,
+}
+
+impl Preprocess {
+    /// The shred inside `data`, or None if it is too short or not the expected tunnel.
+    pub fn apply<'a>(&self, data: &'a [u8]) -> Option<&'a [u8]> {
+        let data = data.get(self.strip_prefix..)?;
+        match self.encapsulation {
+            None => Some(data),
+            Some(Encapsulation::Vxlan) => {
+                // The I flag marks a valid VNI; everything else in the header is reserved.
+                if *data.first()? & 0x08 == 0 {
+                    return None;
+                }
+                pcap::ethernet_udp_payload(data.get(8..)?)
+            }
+            Some(Encapsulation::Geneve) => {
+                let options_len = (*data.first()? & 0x3f) as usize * 4;
+                let inner = data.get(8 + options_len..)?;
+                match u16::from_be_bytes(data.get(2..4)?.try_into().ok()?) {
+                    GENEVE_ETHERNET => pcap::ethernet_udp_payload(inner),
+                    GENEVE_IPV4 | GENEVE_IPV6 => pcap::ip_udp_payload(inner),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use hdrhistogram::serialization::interval_log::IntervalLogWriterBuilder;
+use hdrhistogram::serialization::V2DeflateSerializer;
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use solana_ledger::shred::{ShredId, ShredType};
+use crate::stats::{self, DelayUnit, StatsSnapshot};
+
+/// Maps monotonic `Instant`s onto unix time so exported timestamps can be
+/// lined up with other systems.
+#[derive(Clone, Copy)]
+pub struct WallClock {
+    instant: Instant,
+    unix_nanos: u64,
+}
+
+impl WallClock {
+    pub fn now() -> Self {
+        let unix_nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self {
+            instant: Instant::now(),
+            unix_nanos,
+        }
+    }
+
+    /// Pins `instant` to an arbitrary unix time, e.g. the start of a packet capture.
+    pub fn anchored(instant: Instant, unix_nanos: u64) -> Self {
+        Self { instant, unix_nanos }
+    }
+
+    /// Inverse of `unix_nanos`.
+    pub fn instant(&self, unix_nanos: u64) -> Instant {
+        if unix_nanos >= self.unix_nanos {
+            self.instant + Duration::from_nanos(unix_nanos - self.unix_nanos)
+        } else {
+            let before = Duration::from_nanos(self.unix_nanos - unix_nanos);
+            self.instant.checked_sub(before).unwrap_or(self.instant)
+        }
+    }
+
+    pub fn unix_nanos(&self, t: Instant) -> u64 {
+        if t >= self.instant {
+            self.unix_nanos + t.duration_since(self.instant).as_nanos() as u64
+        } else {
+            self.unix_nanos - self.instant.duration_since(t).as_nanos() as u64
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MatchRecord {
+    pub shred_id: ShredId,
+    pub source: Arc<str>,
+    pub reference: Arc<str>,
+    pub reference_ts_nanos: u64,
+    pub candidate_ts_nanos: u64,
+    pub delay_nanos: i64,
+}
+
+pub fn shred_type_name(shred_type: ShredType) -> &'static str {
+    match shred_type {
+        ShredType::Data => "data",
+        ShredType::Code => "code",
+    }
+}
+
+/// Writes `hdr` as a single-interval HdrHistogram log covering the run so far, which
+/// the standard HdrHistogram tools can merge and plot.
+pub fn write_hdr_log(path: &Path, hdr: &Histogram<u64>, start_unix_nanos: u64, duration: Duration) -> anyhow::Result<()> {
+    let start = UNIX_EPOCH + Duration::from_nanos(start_unix_nanos);
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut serializer = V2DeflateSerializer::new();
+    IntervalLogWriterBuilder::new()
+        .add_comment("|delay| between matched shreds, in microseconds")
+        .with_start_time(start)
+        .with_base_time(start)
+        .begin_log_with(&mut out, &mut serializer)?
+        .write_histogram(hdr, Duration::ZERO, duration, None)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    out.flush()?;
+    Ok(())
+}
+
+pub struct CsvWriter {
+    out: BufWriter<File>,
+    unit: DelayUnit,
+}
+
+impl CsvWriter {
+    /// Appends to `path`, which must be empty or have the same columns, delay unit
+    /// included, so rows never end up under a header that misdescribes them.
+    pub fn create(path: &Path, unit: DelayUnit) -> io::Result<Self> {
+        let header = format!(
+            "slot,index,shred_type,port0_ts_nanos,port1_ts_nanos,delay_{},source",
+            unit.field_suffix()
+        );
+        let file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        let mut existing = String::new();
+        BufReader::new(&file).read_line(&mut existing)?;
+        let existing = existing.trim_end();
+        if !existing.is_empty() && existing != header {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("existing header `{}` doesn't match `{}`; use another file or its --delay-unit", existing, header),
+            ));
+        }
+        let mut out = BufWriter::new(file);
+        if existing.is_empty() {
+            writeln!(out, "{}", header)?;
+        }
+        Ok(Self { out, unit })
+    }
+
+    pub fn write_match(&mut self, record: &MatchRecord) -> io::Result<()> {
+        writeln!(
+            self.out,
+            "{},{},{},{},{},{},{}",
+            record.shred_id.slot(),
+            record.shred_id.index(),
+            shred_type_name(record.shred_id.shred_type()),
+            record.reference_ts_nanos,
+            record.candidate_ts_nanos,
+            self.unit.value(record.delay_nanos),
+            record.source
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// One JSON object per matched pair. With a rotation size, the file is moved aside to
+/// `<path>.<n>` once it grows past it and a fresh one is started.
+pub struct NdjsonWriter {
+    path: PathBuf,
+    rotate_bytes: Option<u64>,
+    out: BufWriter<File>,
+    written: u64,
+    unit: DelayUnit,
+    // --source-label pairs by source name.
+    labels: HashMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Serialize)]
+struct NdjsonRecord<'a> {
+    slot: u64,
+    index: u32,
+    shred_type: &'static str,
+    source: &'a str,
+    reference: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_labels: Option<&'a BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reference_labels: Option<&'a BTreeMap<String, String>>,
+    reference_ts_nanos: u64,
+    candidate_ts_nanos: u64,
+    #[serde(flatten)]
+    delay: Delay,
+}
+
+// Flattened into a single `delay_<unit>` field.
+#[derive(Serialize)]
+enum Delay {
+    #[serde(rename = "delay_nanos")]
+    Nanos(i64),
+    #[serde(rename = "delay_us")]
+    Micros(f64),
+    #[serde(rename = "delay_ms")]
+    Millis(f64),
+}
+
+impl Delay {
+    fn new(nanos: i64, unit: DelayUnit) -> Self {
+        match unit {
+            DelayUnit::Auto | DelayUnit::Ns => Delay::Nanos(nanos),
+            DelayUnit::Us => Delay::Micros(nanos as f64 / 1e3),
+            DelayUnit::Ms => Delay::Millis(nanos as f64 / 1e6),
+        }
+    }
+}
+
+impl NdjsonWriter {
+    /// `labels` maps source names to the --source-label pairs added to their records.
+    pub fn create(
+        path: &Path,
+        rotate_bytes: Option<u64>,
+        unit: DelayUnit,
+        labels: HashMap<String, BTreeMap<String, String>>,
+    ) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotate_bytes,
+            out: BufWriter::new(file),
+            written,
+            unit,
+            labels,
+        })
+    }
+
+    /// Labels the records of a source added while running.
+    pub fn add_source(&mut self, name: &str, labels: BTreeMap<String, String>) {
+        self.labels.insert(name.to_string(), labels);
+    }
+
+    pub fn write_match(&mut self, record: &MatchRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&NdjsonRecord {
+            slot: record.shred_id.slot(),
+            index: record.shred_id.index(),
+            shred_type: shred_type_name(record.shred_id.shred_type()),
+            source: &record.source,
+            reference: &record.reference,
+            source_labels: self.labels.get(&*record.source),
+            reference_labels: self.labels.get(&*record.reference),
+            reference_ts_nanos: record.reference_ts_nanos,
+            candidate_ts_nanos: record.candidate_ts_nanos,
+            delay: Delay::new(record.delay_nanos, self.unit),
+        })?;
+        line.push(b'\n');
+        self.out.write_all(&line)?;
+        self.written += line.len() as u64;
+        if self.rotate_bytes.is_some_and(|limit| self.written >= limit) {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        let rotated = (1..)
+            .map(|n| PathBuf::from(format!("{}.{}", self.path.display(), n)))
+            .find(|candidate| !candidate.exists())
+            .expect("unbounded range");
+        fs::rename(&self.path, &rotated)?;
+        self.out = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Appends one JSON object per stats report to a file, for --json-stats-out.
+pub struct JsonStatsWriter {
+    out: BufWriter<File>,
+}
+
+impl JsonStatsWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { out: BufWriter::new(file) })
+    }
+
+    /// Writes and flushes `stats`, so each report is on disk as soon as it is made.
+    pub fn write_stats(&mut self, stats: &StatsSnapshot) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, &stats::to_json(stats))?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()
+    }
+}
+
+/// Starts every --binary-log file, followed by the little-endian `BINARY_LOG_VERSION` and
+/// the source names.
+pub const BINARY_LOG_MAGIC: [u8; 4] = *b"SPBL";
+pub const BINARY_LOG_VERSION: u16 = 1;
+
+/// One fixed-width little-endian record per matched pair, for capture at rates where CSV
+/// and NDJSON can't keep up: slot u64, index u32, shred type u8 (0 data, 1 code), source
+/// u8 (its position in the header) and delay i64 nanos.
+pub struct BinaryLogWriter {
+    out: BufWriter<File>,
+    sources: Vec<Arc<str>>,
+}
+
+impl BinaryLogWriter {
+    /// Unlike the other exports this overwrites `path`, since its header names the sources.
+    pub fn create(path: &Path, sources: &[Arc<str>]) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&BINARY_LOG_MAGIC)?;
+        out.write_u16::<LittleEndian>(BINARY_LOG_VERSION)?;
+        out.write_u8(sources.len() as u8)?;
+        for name in sources {
+            let name = &name.as_bytes()[..name.len().min(u8::MAX as usize)];
+            out.write_u8(name.len() as u8)?;
+            out.write_all(name)?;
+        }
+        Ok(Self {
+            out,
+            sources: sources.to_vec(),
+        })
+    }
+
+    pub fn write_match(&mut self, record: &MatchRecord) -> io::Result<()> {
+        // The header can't name sources added later, so their records aren't credited to
+        // another source.
+        let source = self.sources.iter().position(|name| *name == record.source).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("source `{}` isn't in the binary log header", record.source))
+        })?;
+        self.out.write_u64::<LittleEndian>(record.shred_id.slot())?;
+        self.out.write_u32::<LittleEndian>(record.shred_id.index())?;
+        self.out.write_u8(match record.shred_id.shred_type() {
+            ShredType::Data => 0,
+            ShredType::Code => 1,
+        })?;
+        self.out.write_u8(source as u8)?;
+        self.out.write_i64::<LittleEndian>(record.delay_nanos)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinaryLogRecord {
+    pub slot: u64,
+    pub index: u32,
+    pub shred_type: ShredType,
+    pub source: usize,
+    pub delay_nanos: i64,
+}
+
+/// Reads back a --binary-log file, record by record.
+pub struct BinaryLogReader<R> {
+    input: R,
+    sources: Vec<String>,
+}
+
+impl<R: Read> BinaryLogReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if magic != BINARY_LOG_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a binary match log"));
+        }
+        let version = input.read_u16::<LittleEndian>()?;
+        if version != BINARY_LOG_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported binary log version {}", version),
+            ));
+        }
+        let mut sources = Vec::new();
+        for _ in 0..input.read_u8()? {
+            let mut name = vec![0; input.read_u8()? as usize];
+            input.read_exact(&mut name)?;
+            sources.push(String::from_utf8_lossy(&name).into_owned());
+        }
+        Ok(Self { input, sources })
+    }
+
+    /// Source names in the order `BinaryLogRecord::source` indexes them.
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<BinaryLogRecord>> {
+        let slot = match self.input.read_u64::<LittleEndian>() {
+            Ok(slot) => slot,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let index = self.input.read_u32::<LittleEndian>()?;
+        let shred_type = match self.input.read_u8()? {
+            0 => ShredType::Data,
+            1 => ShredType::Code,
+            other => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown shred type {}", other))),
+        };
+        let source = self.input.read_u8()? as usize;
+        let delay_nanos = self.input.read_i64::<LittleEndian>()?;
+        Ok(Some(BinaryLogRecord {
+            slot,
+            index,
+            shred_type,
+            source,
+            delay_nanos,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for BinaryLogReader<R> {
+    type Item = io::Result<BinaryLogRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_log_round_trips() {
+        let path = std::env::temp_dir().join(format!("shred-perf-{}.bin", std::process::id()));
+        let sources: Vec<Arc<str>> = vec!["a".into(), "b".into()];
+        let mut writer = BinaryLogWriter::create(&path, &sources).unwrap();
+        writer
+            .write_match(&MatchRecord {
+                shred_id: ShredId::new(1000, 7, ShredType::Code),
+                source: Arc::clone(&sources[1]),
+                reference: Arc::clone(&sources[0]),
+                reference_ts_nanos: 0,
+                candidate_ts_nanos: 0,
+                delay_nanos: -250_000,
+            })
+            .unwrap();
+        writer.flush().unwrap();
+
+        let reader = BinaryLogReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.sources(), ["a", "b"]);
+        let records = reader.collect::<io::Result<Vec<_>>>().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            records,
+            [BinaryLogRecord {
+                slot: 1000,
+                index: 7,
+                shred_type: ShredType::Code,
+                source: 1,
+                delay_nanos: -250_000,
+            }]
+        );
+    }
+
+    #[test]
+    fn csv_out_only_appends_under_the_same_delay_unit() {
+        let path = std::env::temp_dir().join(format!("shred-perf-{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        CsvWriter::create(&path, DelayUnit::Ns).unwrap().flush().unwrap();
+        // Auto writes nanoseconds too, so it shares the header.
+        CsvWriter::create(&path, DelayUnit::Auto).unwrap().flush().unwrap();
+        let error = CsvWriter::create(&path, DelayUnit::Us).err().unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(text.lines().count(), 1);
+    }
+}
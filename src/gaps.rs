@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use solana_ledger::blockstore::MAX_DATA_SHREDS_PER_SLOT;
+
+/// Which shred indices each source delivered for the most recent slots. Once a slot
+/// leaves the window, every source is charged the indices it lacks below the highest
+/// index any source delivered for that slot and shred type.
+pub struct SlotGaps {
+    window: usize,
+    sources: usize,
+    recent: BTreeMap<u64, SlotIndices>,
+    // Late shreds for a slot already counted are ignored rather than starting it over.
+    completed_through: Option<u64>,
+    completed_slots: u64,
+    missing: Vec<u64>,
+}
+
+struct SlotIndices {
+    last_arrival: Instant,
+    // Per source, one bitset per shred type.
+    received: Vec<[Vec<u64>; 2]>,
+}
+
+/// Indices one slot was missing on each source.
+pub struct CompletedSlot {
+    pub slot: u64,
+    pub missing: Vec<u64>,
+}
+
+impl SlotGaps {
+    pub fn new(sources: usize, window: usize) -> Self {
+        Self {
+            window,
+            sources,
+            recent: BTreeMap::new(),
+            completed_through: None,
+            completed_slots: 0,
+            missing: vec![0; sources],
+        }
+    }
+
+    /// Tracks one more source, from the next slot it delivers to.
+    pub fn add_source(&mut self) {
+        self.sources += 1;
+        self.missing.push(0);
+    }
+
+    /// Marks `index` as delivered by `source_idx`, completing the oldest slot if this
+    /// pushes the window past its size. Indices no slot can hold (coding shreds share
+    /// the data shreds' limit) are ignored, so a junk datagram can't size the bitset.
+    pub fn record(
+        &mut self,
+        source_idx: usize,
+        slot: u64,
+        type_idx: usize,
+        index: u32,
+        time: Instant,
+    ) -> Option<CompletedSlot> {
+        if index as usize >= MAX_DATA_SHREDS_PER_SLOT {
+            return None;
+        }
+        if self.completed_through.is_some_and(|completed| slot <= completed) {
+            return None;
+        }
+        let sources = self.sources;
+        let indices = self.recent.entry(slot).or_insert_with(|| SlotIndices {
+            last_arrival: time,
+            received: vec![[Vec::new(), Vec::new()]; sources],
+        });
+        indices.last_arrival = indices.last_arrival.max(time);
+        // Slots from before `add_source` have no entry for the source.
+        if indices.received.len() <= source_idx {
+            indices.received.resize(source_idx + 1, [Vec::new(), Vec::new()]);
+        }
+        let bits = &mut indices.received[source_idx][type_idx];
+        let word = index as usize / 64;
+        if bits.len() <= word {
+            bits.resize(word + 1, 0);
+        }
+        bits[word] |= 1 << (index % 64);
+
+        if self.recent.len() > self.window {
+            let (slot, indices) = self.recent.pop_first()?;
+            return Some(self.complete(slot, indices));
+        }
+        None
+    }
+
+    /// Completes every slot nothing arrived for within `timeout`, oldest first.
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<CompletedSlot> {
+        let stale: Vec<u64> = self
+            .recent
+            .iter()
+            .filter(|(_, indices)| now.duration_since(indices.last_arrival) >= timeout)
+            .map(|(slot, _)| *slot)
+            .collect();
+        stale
+            .into_iter()
+            .filter_map(|slot| {
+                let indices = self.recent.remove(&slot)?;
+                Some(self.complete(slot, indices))
+            })
+            .collect()
+    }
+
+    /// Completes every slot still in the window, e.g. before the final summary.
+    pub fn finish(&mut self) -> Vec<CompletedSlot> {
+        let recent = std::mem::take(&mut self.recent);
+        recent.into_iter().map(|(slot, indices)| self.complete(slot, indices)).collect()
+    }
+
+    pub fn completed_slots(&self) -> u64 {
+        self.completed_slots
+    }
+
+    /// Total missing indices per source over the completed slots.
+    pub fn missing(&self) -> &[u64] {
+        &self.missing
+    }
+
+    fn complete(&mut self, slot: u64, indices: SlotIndices) -> CompletedSlot {
+        self.completed_through = self.completed_through.max(Some(slot));
+        self.completed_slots += 1;
+        let mut missing = vec![0; self.sources];
+        for type_idx in 0..2 {
+            let expected = indices
+                .received
+                .iter()
+                .filter_map(|bits| highest_index(&bits[type_idx]))
+                .max()
+                .map_or(0, |highest| highest + 1);
+            for (missing, bits) in missing.iter_mut().zip(&indices.received) {
+                let received: u64 = bits[type_idx].iter().map(|word| u64::from(word.count_ones())).sum();
+                *missing += expected - received;
+            }
+        }
+        for (total, missing) in self.missing.iter_mut().zip(&missing) {
+            *total += missing;
+        }
+        CompletedSlot { slot, missing }
+    }
+}
+
+fn highest_index(bits: &[u64]) -> Option<u64> {
+    let (word_idx, word) = bits.iter().enumerate().rev().find(|(_, word)| **word != 0)?;
+    Some(word_idx as u64 * 64 + 63 - u64::from(word.leading_zeros()))
+}
+
+/// How many data shreds each source delivered for the most recent slots, against how
+/// many each slot should hold, for --completeness-report.
+pub struct SlotCompleteness {
+    slots: usize,
+    // From --shreds-per-slot; otherwise one past the highest index any source delivered.
+    expected: Option<u32>,
+    recent: BTreeMap<u64, SlotDeliveries>,
+}
+
+struct SlotDeliveries {
+    highest_index: u32,
+    // Per source; sources added later have no entry until they deliver.
+    delivered: Vec<u32>,
+}
+
+/// One slot's completeness: data shreds delivered per source, out of `expected`.
+pub struct SlotDelivery {
+    pub slot: u64,
+    pub expected: u32,
+    pub delivered: Vec<u32>,
+}
+
+impl SlotCompleteness {
+    pub fn new(slots: usize, expected: Option<u32>) -> Self {
+        Self {
+            slots,
+            expected,
+            recent: BTreeMap::new(),
+        }
+    }
+
+    /// Counts the first copy of data shred `index` of `slot` from `source_idx`, unless
+    /// the index is past what a slot can hold and would inflate the slot's size.
+    pub fn record(&mut self, source_idx: usize, slot: u64, index: u32) {
+        if index as usize >= MAX_DATA_SHREDS_PER_SLOT {
+            return;
+        }
+        let deliveries = self.recent.entry(slot).or_insert_with(|| SlotDeliveries {
+            highest_index: index,
+            delivered: Vec::new(),
+        });
+        deliveries.highest_index = deliveries.highest_index.max(index);
+        if deliveries.delivered.len() <= source_idx {
+            deliveries.delivered.resize(source_idx + 1, 0);
+        }
+        deliveries.delivered[source_idx] += 1;
+    }
+
+    /// Forgets all but the most recent `slots` slots.
+    pub fn trim(&mut self) {
+        while self.recent.len() > self.slots {
+            self.recent.pop_first();
+        }
+    }
+
+    /// The most recent `slots` slots, oldest first, with a count for each of `sources`.
+    pub fn snapshot(&self, sources: usize) -> Vec<SlotDelivery> {
+        let mut slots: Vec<SlotDelivery> = self
+            .recent
+            .iter()
+            .rev()
+            .take(self.slots)
+            .map(|(slot, deliveries)| SlotDelivery {
+                slot: *slot,
+                expected: self.expected.unwrap_or(deliveries.highest_index + 1),
+                delivered: (0..sources).map(|idx| deliveries.delivered.get(idx).copied().unwrap_or(0)).collect(),
+            })
+            .collect();
+        slots.reverse();
+        slots
+    }
+}
+
+/// Packet loss on each source estimated from its own deliveries, for --index-loss. Data
+/// shred indices within a slot are contiguous, so any a source skipped between the lowest
+/// and highest it delivered were most likely dropped on the way to us. A slot is counted
+/// once the source has delivered for `window` newer ones.
+pub struct IndexLoss {
+    window: usize,
+    sources: Vec<SourceLoss>,
+}
+
+#[derive(Default)]
+struct SourceLoss {
+    recent: BTreeMap<u64, IndexSpan>,
+    // Late shreds for a slot already counted are ignored rather than starting it over.
+    completed_through: Option<u64>,
+    totals: LossTotals,
+}
+
+struct IndexSpan {
+    lowest: u32,
+    highest: u32,
+    received: u32,
+}
+
+/// Indices expected between the lowest and highest delivered, and how many of those
+/// never arrived, over one slot or all the slots counted so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LossTotals {
+    pub slots: u64,
+    pub expected: u64,
+    pub missing: u64,
+}
+
+impl IndexLoss {
+    pub fn new(sources: usize, window: usize) -> Self {
+        Self {
+            window,
+            sources: (0..sources).map(|_| SourceLoss::default()).collect(),
+        }
+    }
+
+    pub fn add_source(&mut self) {
+        self.sources.push(SourceLoss::default());
+    }
+
+    /// Counts the first copy of data shred `index` of `slot` from `source_idx`, returning
+    /// the source's estimate for its oldest slot if this pushes that out of the window.
+    pub fn record(&mut self, source_idx: usize, slot: u64, index: u32) -> Option<(u64, LossTotals)> {
+        // A junk index past what a slot can hold would count as a huge gap.
+        if index as usize >= MAX_DATA_SHREDS_PER_SLOT {
+            return None;
+        }
+        let source = &mut self.sources[source_idx];
+        if source.completed_through.is_some_and(|completed| slot <= completed) {
+            return None;
+        }
+        let span = source.recent.entry(slot).or_insert(IndexSpan {
+            lowest: index,
+            highest: index,
+            received: 0,
+        });
+        (span.lowest, span.highest) = (span.lowest.min(index), span.highest.max(index));
+        span.received += 1;
+        if source.recent.len() <= self.window {
+            return None;
+        }
+        let (slot, span) = source.recent.pop_first()?;
+        source.completed_through = Some(slot);
+        let expected = u64::from(span.highest - span.lowest) + 1;
+        let loss = LossTotals {
+            slots: 1,
+            expected,
+            missing: expected.saturating_sub(u64::from(span.received)),
+        };
+        source.totals.slots += 1;
+        source.totals.expected += loss.expected;
+        source.totals.missing += loss.missing;
+        Some((slot, loss))
+    }
+
+    /// `source_idx`'s estimate over every slot counted so far.
+    pub fn totals(&self, source_idx: usize) -> LossTotals {
+        self.sources[source_idx].totals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slot_gaps_ignore_indices_past_the_protocol_limit() {
+        let mut gaps = SlotGaps::new(1, 1);
+        let now = Instant::now();
+        assert!(gaps.record(0, 10, 0, u32::MAX, now).is_none());
+        assert!(gaps.record(0, 10, 0, MAX_DATA_SHREDS_PER_SLOT as u32, now).is_none());
+        assert!(gaps.recent.is_empty());
+
+        gaps.record(0, 10, 0, 1, now);
+        let completed = gaps.finish();
+        assert_eq!((completed[0].slot, completed[0].missing.clone()), (10, vec![1]));
+    }
+
+    #[test]
+    fn index_loss_ignores_indices_past_the_protocol_limit() {
+        let mut loss = IndexLoss::new(1, 1);
+        loss.record(0, 10, 0);
+        loss.record(0, 10, u32::MAX);
+        loss.record(0, 11, 0);
+        assert_eq!(loss.totals(0), LossTotals { slots: 1, expected: 1, missing: 0 });
+    }
+}
@@ -0,0 +1,132 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+use tonic::codegen::{empty_body, http, Body, BoxFuture, BoxStream, Context, Poll, Service, StdError};
+use tonic::server::{Grpc, NamedService, ServerStreamingService};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+use crate::export::{shred_type_name, MatchRecord};
+
+// Written out by hand rather than generated, so building doesn't need protoc; they mirror
+// proto/shred_perf.proto, which clients can generate from.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SubscribeMatchesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MatchEvent {
+    #[prost(uint64, tag = "1")]
+    pub slot: u64,
+    #[prost(uint32, tag = "2")]
+    pub index: u32,
+    #[prost(string, tag = "3")]
+    pub shred_type: String,
+    #[prost(sint64, tag = "4")]
+    pub delay_nanos: i64,
+    #[prost(string, tag = "5")]
+    pub winner: String,
+    #[prost(string, tag = "6")]
+    pub source: String,
+    #[prost(string, tag = "7")]
+    pub reference: String,
+}
+
+impl From<MatchRecord> for MatchEvent {
+    fn from(record: MatchRecord) -> Self {
+        let winner = if record.delay_nanos < 0 { &record.source } else { &record.reference };
+        Self {
+            slot: record.shred_id.slot(),
+            index: record.shred_id.index(),
+            shred_type: shred_type_name(record.shred_id.shred_type()).to_string(),
+            delay_nanos: record.delay_nanos,
+            winner: winner.to_string(),
+            source: record.source.to_string(),
+            reference: record.reference.to_string(),
+        }
+    }
+}
+
+const SUBSCRIBE_MATCHES: &str = "/shred_perf.ShredPerf/SubscribeMatches";
+
+/// The `shred_perf.ShredPerf` service, streaming every match sent on `matches`.
+#[derive(Clone)]
+pub struct ShredPerfService {
+    matches: broadcast::Sender<MatchRecord>,
+}
+
+impl ShredPerfService {
+    pub fn new(matches: broadcast::Sender<MatchRecord>) -> Self {
+        Self { matches }
+    }
+}
+
+impl NamedService for ShredPerfService {
+    const NAME: &'static str = "shred_perf.ShredPerf";
+}
+
+impl<B> Service<http::Request<B>> for ShredPerfService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if req.uri().path() != SUBSCRIBE_MATCHES {
+            return Box::pin(async {
+                Ok(http::Response::builder()
+                    .status(200)
+                    .header("grpc-status", tonic::Code::Unimplemented as i32)
+                    .header("content-type", "application/grpc")
+                    .body(empty_body())
+                    .expect("static response parts are valid"))
+            });
+        }
+        let subscribe = SubscribeMatches(self.matches.clone());
+        Box::pin(async move { Ok(Grpc::new(ProstCodec::default()).server_streaming(subscribe, req).await) })
+    }
+}
+
+struct SubscribeMatches(broadcast::Sender<MatchRecord>);
+
+impl ServerStreamingService<SubscribeMatchesRequest> for SubscribeMatches {
+    type Response = MatchEvent;
+    type ResponseStream = BoxStream<MatchEvent>;
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: Request<SubscribeMatchesRequest>) -> Self::Future {
+        let peer = request.remote_addr();
+        let matches = BroadcastStream::new(self.0.subscribe());
+        Box::pin(async move {
+            info!("gRPC subscriber {:?} connected", peer);
+            // A subscriber too slow to keep up skips ahead instead of holding back the rest.
+            let events = matches.filter_map(move |record| match record {
+                Ok(record) => Some(Ok(MatchEvent::from(record))),
+                Err(e) => {
+                    warn!("gRPC subscriber {:?} fell behind: {}", peer, e);
+                    None
+                }
+            });
+            Ok(Response::new(Box::pin(events) as Self::ResponseStream))
+        })
+    }
+}
+
+pub async fn serve(matches: broadcast::Sender<MatchRecord>, port: u16) -> anyhow::Result<()> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    info!("Serving gRPC match stream on port {}", port);
+    tonic::transport::Server::builder()
+        .add_service(ShredPerfService::new(matches))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
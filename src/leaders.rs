@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use anyhow::Context;
+use solana_pubkey::Pubkey;
+
+/// Slot leaders used to verify shred signatures.
+pub struct LeaderSchedule {
+    leaders: HashMap<u64, Pubkey>,
+}
+
+impl LeaderSchedule {
+    /// Reads `<slot> <leader pubkey>` lines, as printed by `solana leader-schedule`.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut leaders = HashMap::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let context = || format!("{}:{}: expected `<slot> <pubkey>`", path.display(), line_no + 1);
+            let mut fields = line.split_whitespace();
+            let slot = fields.next().and_then(|f| f.parse().ok()).with_context(context)?;
+            let leader = fields.next().and_then(|f| f.parse().ok()).with_context(context)?;
+            leaders.insert(slot, leader);
+        }
+        Ok(Self { leaders })
+    }
+
+    pub fn leader(&self, slot: u64) -> Option<&Pubkey> {
+        self.leaders.get(&slot)
+    }
+
+    pub fn slots(&self) -> usize {
+        self.leaders.len()
+    }
+}
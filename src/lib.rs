@@ -0,0 +1,22 @@
+//! The matching and latency engine behind the `solana-shred-perf` CLI. Feed a
+//! [`Processor`] the shreds each source delivers and read the delays back from
+//! [`Processor::stats`].
+
+mod alert;
+mod bloom;
+pub mod export;
+pub mod gaps;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod leaders;
+pub mod metrics;
+#[cfg(feature = "otlp")]
+pub mod otel;
+pub mod persist;
+mod processor;
+pub mod stats;
+pub mod truth;
+#[cfg(feature = "tui")]
+pub mod tui;
+
+pub use processor::{LogFormat, MatchKeyArg, Processor, ProcessorConfig, StatsFormat, ThresholdStat};
@@ -1,200 +1,2027 @@
-use std::collections::HashMap;
+mod clock;
+mod config;
+mod encap;
+mod merge;
+#[cfg(target_os = "linux")]
+mod mmsg;
+mod pcap;
+mod relay;
+mod selftest;
+mod transport;
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+#[cfg(feature = "tui")]
+use std::io::IsTerminal;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
-use clap::Parser;
-use log::{info, error};
-use solana_ledger::shred::{Shred, ShredId};
+#[cfg(target_os = "linux")]
+use std::time::SystemTime;
+use anyhow::Context;
+use clap::{CommandFactory, Parser, ValueEnum};
+use config::ConfigFile;
+use encap::{Encapsulation, Preprocess};
+use merge::{MergeArgs, Summary};
+use relay::Relay;
+use tracing::{error, info, warn};
+use transport::{Scheme, Tcp, Transport};
+use socket2::{Domain, Protocol, Socket, Type};
+use solana_ledger::shred::{layout, Shred, ShredId, ShredType};
+use solana_shred_perf::export::{BinaryLogWriter, CsvWriter, JsonStatsWriter, NdjsonWriter, WallClock};
+use solana_shred_perf::leaders::LeaderSchedule;
+use solana_shred_perf::metrics::{self, Metrics};
+use solana_shred_perf::persist;
+use solana_shred_perf::stats::DelayUnit;
+use solana_shred_perf::truth::ReferenceShreds;
+#[cfg(feature = "tui")]
+use solana_shred_perf::tui::{Dashboard, LogPane};
+use solana_shred_perf::{LogFormat, MatchKeyArg, Processor, ProcessorConfig, StatsFormat, ThresholdStat};
 use tokio::net::UdpSocket;
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::{AbortHandle, JoinSet};
 use tokio::time;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    /// Read options from a TOML (or `.yaml`) file keyed by long flag name, e.g.
+    /// `source = ["uk:20001", "de:20002"]`. Flags on the command line take precedence.
     #[clap(long)]
-    pub name_0: String,
-    #[clap(long)]
-    pub port_0: u16,
-    #[clap(short, long)]
-    pub name_1: String,
-    #[clap(short, long)]
-    pub port_1: u16,
+    pub config: Option<PathBuf>,
+    /// Re-read the --config file whenever it changes and apply its source list without a
+    /// restart: added sources start listening with empty stats, removed ones stop, and the
+    /// rest keep theirs. Every other option keeps its startup value.
+    #[clap(long, requires = "config")]
+    pub watch_config: bool,
+    /// Shred source to compare, as `name:port` or `name:ip:port` to bind a specific
+    /// interface (default 0.0.0.0; bracket IPv6 addresses). A comma list of ports, as in
+    /// `name:8001,8002`, receives one feed on all of them. Prefix the address with
+    /// `tcp://` or `quic://` (`name:tcp://ip:port`) for a relay that streams length-prefixed
+    /// shreds instead of sending datagrams. Repeat for each feed.
+    #[clap(short, long = "source", value_name = "NAME:[SCHEME://][IP:]PORT[,PORT...]", value_parser = parse_source)]
+    pub sources: Vec<SourceSpec>,
+    /// Source every delay is measured against, by position (0-based) or name. Delays are
+    /// `candidate - reference`, so negative means the candidate was faster.
+    #[clap(long, value_name = "INDEX|NAME", default_value = "0")]
+    pub reference_source: String,
     #[clap(long, default_value = "60")]
     pub timeout_secs: u64,
+    /// How often stats are reported.
+    #[clap(long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    pub stats_interval_secs: u64,
+    /// Report matches, wins and leads for each stats interval only, starting from zero
+    /// every tick, with the lifetime totals alongside.
+    #[clap(long)]
+    pub reset_stats_each_tick: bool,
+    /// Warn when one source receives more than this many times the shreds of another over
+    /// a stats interval, which usually means a wrong port or asymmetric mirroring. 0 turns
+    /// the check off.
+    #[clap(long, value_name = "RATIO", default_value = "10")]
+    pub imbalance_warn_ratio: f64,
+    /// Evict shreds once their slot is this many slots behind the highest slot seen,
+    /// instead of --timeout-secs after they arrived, so the working set follows the chain
+    /// rather than the clock.
+    #[clap(long, value_name = "SLOTS")]
+    pub evict_after_slots: Option<u64>,
+    /// How often shreds older than --timeout-secs are evicted. Defaults to --timeout-secs.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub cleanup_interval_secs: Option<u64>,
+    /// Log every shred cleanup evicts without it ever having matched, with the source
+    /// that had it and how long it waited. Very verbose; for chasing a low match rate.
+    #[clap(long)]
+    pub no_match_expiry_report: bool,
+    /// Stop after this long and print the final summary, as on Ctrl-C.
+    #[clap(long)]
+    pub run_for_secs: Option<u64>,
+    /// Serve Prometheus metrics on this port at `/metrics`.
+    #[clap(long)]
+    pub metrics_port: Option<u16>,
+    /// Push match and receive metrics to this OTLP/HTTP collector endpoint, e.g.
+    /// `http://localhost:4318/v1/metrics`. Needs the `otlp` feature.
+    #[clap(long, value_name = "URL")]
+    pub otlp_endpoint: Option<String>,
+    /// Stream every matched pair to gRPC subscribers on this port (see
+    /// proto/shred_perf.proto). Needs the `grpc` feature.
+    #[clap(long)]
+    pub grpc_port: Option<u16>,
+    /// Append one CSV row per matched pair to this file, which must have been written
+    /// with the same --delay-unit if it exists.
+    #[clap(long)]
+    pub csv_out: Option<PathBuf>,
+    /// Append one JSON object per matched pair to this file.
+    #[clap(long)]
+    pub ndjson_out: Option<PathBuf>,
+    /// Move the --ndjson-out file aside to `<file>.<n>` once it reaches this many MB.
+    #[clap(long, requires = "ndjson_out", value_parser = clap::value_parser!(u64).range(1..))]
+    pub rotate_mb: Option<u64>,
+    /// Append one JSON object per stats report to this file, with the same aggregates
+    /// (counts, match rates, wins, delay percentiles, throughput) whatever --stats-format is.
+    #[clap(long, value_name = "PATH")]
+    pub json_stats_out: Option<PathBuf>,
+    /// Write one packed 22-byte record per matched pair to this file, overwriting it, for
+    /// capture at full rate. Read it back with `cargo run --example read_binary_log`.
+    #[clap(long, value_name = "PATH")]
+    pub binary_log: Option<PathBuf>,
+    /// When --csv-out, --ndjson-out and --binary-log reach the disk: every stats tick
+    /// (fastest), after every match (nothing lost on a crash), or every --flush-every
+    /// matches. Each also flushes whenever its buffer fills.
+    #[clap(long, value_enum, default_value = "per-tick")]
+    pub flush_policy: FlushPolicy,
+    /// Matches between flushes with --flush-policy per-n.
+    #[clap(long, value_name = "N", default_value = "1000", value_parser = clap::value_parser!(u64).range(1..))]
+    pub flush_every: u64,
+    /// Number of most recent matched delays kept for stats.
+    #[clap(long, default_value = "100000", value_parser = clap::value_parser!(u64).range(1..))]
+    pub delay_window: u64,
+    /// Weight of each new match in the moving-average delay shown next to the window
+    /// average. Higher reacts faster.
+    #[clap(long, default_value = "0.01")]
+    pub ewma_alpha: f64,
+    /// Number of SO_REUSEPORT sockets bound per source to spread kernel receive load.
+    #[clap(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    pub rx_sockets: u64,
+    /// Requested SO_RCVBUF size per socket; the kernel may clamp it (see net.core.rmem_max).
+    #[clap(long, default_value = "4194304")]
+    pub rcvbuf_bytes: usize,
+    /// Receive buffer per datagram. One that fills it is counted as truncated rather than
+    /// parsed.
+    #[clap(long, default_value = "2048", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_packet_size: u64,
+    /// Times to retry a failed bind, e.g. while a previous instance still holds the port,
+    /// before giving up.
+    #[clap(long, default_value = "5")]
+    pub bind_retries: u32,
+    /// Wait before the first bind retry; doubles after each further failure.
+    #[clap(long, default_value = "500")]
+    pub bind_retry_delay_ms: u64,
+    /// Receive up to 32 datagrams per syscall with recvmmsg (Linux only). Every
+    /// datagram in a batch is timestamped with the batch's arrival time.
+    #[clap(long)]
+    pub batch_recv: bool,
+    /// Timestamp datagrams with the kernel receive time (SO_TIMESTAMPNS, Linux only)
+    /// instead of when the task got to them. Falls back to userspace time if unsupported.
+    #[clap(long)]
+    pub kernel_timestamps: bool,
+    /// Drop shreds whose signature doesn't verify against the slot leader from
+    /// --leader-schedule, counting them per source instead of matching them.
+    #[clap(long, requires = "leader_schedule")]
+    pub verify_signatures: bool,
+    /// Slot leaders as `<slot> <pubkey>` lines, the format `solana leader-schedule` prints.
+    #[clap(long, requires = "verify_signatures")]
+    pub leader_schedule: Option<PathBuf>,
+    /// Score every source against a trusted record of the shreds, as `<slot> <index>
+    /// <data|code> [<unix nanos>]` lines: how many it delivered, and with the recorded
+    /// receive times, how long after them it did.
+    #[clap(long, value_name = "FILE")]
+    pub reference_shreds: Option<PathBuf>,
+    /// What must agree for two arrivals to count as the same shred.
+    #[clap(long, value_enum, default_value = "shred-id")]
+    pub match_key: MatchKeyArg,
+    /// Only compare shreds of this type.
+    #[clap(long, value_enum)]
+    pub filter_type: Option<ShredTypeArg>,
+    /// Ignore shreds from slots below this one.
+    #[clap(long)]
+    pub slot_min: Option<u64>,
+    /// Ignore shreds from slots above this one.
+    #[clap(long)]
+    pub slot_max: Option<u64>,
+    /// Discard shreds from slots more than this many behind the highest slot seen so far,
+    /// e.g. backfill during replay or catch-up.
+    #[clap(long, value_name = "SLOTS")]
+    pub max_slot_age: Option<u64>,
+    /// Only match 1 in N shreds, picked by slot and index so every source keeps the same
+    /// ones, for statistical estimates on a firehose. Counters still see every datagram.
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub sample_rate: Option<u64>,
+    /// Print the average delay of the most recent N slots on each stats tick.
+    #[clap(long, value_name = "SLOTS")]
+    pub per_slot_report: Option<usize>,
+    /// Print the average delay of each FEC set in the most recent N slots, and the spread
+    /// between each slot's fastest and slowest set.
+    #[clap(long, value_name = "SLOTS")]
+    pub per_fec_set_report: Option<usize>,
+    /// Print the average delay by shred index within the slot, split at these indices,
+    /// e.g. `32,64` for 0-31, 32-63 and 64+, to show whether a feed lags on a slot's tail.
+    #[clap(long, value_name = "INDICES", value_delimiter = ',')]
+    pub index_buckets: Option<Vec<u32>>,
+    /// List the N matched pairs with the largest delay so far on every stats report and
+    /// at shutdown, with their slot, index and which source trailed.
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub top_laggards: Option<u64>,
+    /// Track which shred indices each source delivered for this many recent slots, and
+    /// report how many each missed below the highest index any source delivered.
+    #[clap(long, value_name = "SLOTS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub slot_gaps: Option<u64>,
+    /// On every stats tick, print how many data shreds each source delivered for each of
+    /// the most recent N slots, out of how many the slot holds.
+    #[clap(long, value_name = "SLOTS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub completeness_report: Option<u64>,
+    /// Data shreds every slot should hold, for --completeness-report. Without it, each
+    /// slot is taken to end at the highest index any source delivered.
+    #[clap(long, value_name = "N", requires = "completeness_report", value_parser = clap::value_parser!(u32).range(1..))]
+    pub shreds_per_slot: Option<u32>,
+    /// Estimate each source's packet loss on its own, from the data shred indices it
+    /// skipped between the lowest and highest it delivered per slot. A slot is counted
+    /// once the source has delivered for SLOTS newer ones.
+    #[clap(long, value_name = "SLOTS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub index_loss: Option<u64>,
+    /// Also dedup across all sources combined: the first source to deliver a shred owns
+    /// it, and every later copy, on any source, is counted with how much later it came.
+    #[clap(long)]
+    pub first_seen_wins: bool,
+    /// Also report each source's own pacing: the gap between consecutive shreds it
+    /// delivered (mean, jitter and max), regardless of the other sources.
+    #[clap(long)]
+    pub inter_arrival: bool,
+    /// Also report each source's delays against the reference split by who led: how far
+    /// ahead the source was when it led, and how far behind when the reference did.
+    #[clap(long)]
+    pub compare_mode: bool,
+    /// Also report, per shred, how long after its first delivery on any source a
+    /// majority of the sources had delivered it (time to quorum), and all of them.
+    #[clap(long)]
+    pub agreement_latency: bool,
+    /// Bound memory at high rates: once a shred has matched every other source, drop its
+    /// exact entry and remember it in a Bloom filter with this false-positive rate, only
+    /// to spot repeat copies. A false positive drops a first copy, losing its match.
+    #[clap(long, value_name = "RATE")]
+    pub bloom_dedup: Option<f64>,
+    /// Also report how far out of slot order each source delivers: how many slots behind
+    /// the highest slot it had already delivered each shred was.
+    #[clap(long)]
+    pub reorder_depth: bool,
+    /// Never log individual matches, even at debug level; only the periodic stats.
+    #[clap(short, long)]
+    pub quiet: bool,
+    /// How periodic stats are written. `kv` and `csv` print one line per tick to stdout
+    /// (csv after a header line) instead of logging.
+    #[clap(long, value_enum, default_value = "human")]
+    pub stats_format: StatsFormat,
+    /// Show each stats report on a live terminal dashboard instead of logging it, with a
+    /// sparkline of the delay and the latest log lines; q or Ctrl-C quits. Falls back to
+    /// logging when stdout isn't a terminal. Needs the `tui` feature.
+    #[clap(long, conflicts_with = "stats_format")]
+    pub tui: bool,
+    /// Log output format. `json` emits one object per event with typed fields, on stdout
+    /// unless --stats-format kv or csv is using it, when it goes to stderr.
+    #[clap(long, value_enum, default_value = "pretty")]
+    pub log_format: LogFormat,
+    /// Unit for every delay in stats, match logs and CSV/NDJSON exports. JSON logs
+    /// always use nanoseconds.
+    #[clap(long, value_enum, default_value = "auto", global = true)]
+    pub delay_unit: DelayUnit,
+    /// Upper edges of the delay histogram buckets, in microseconds.
+    #[clap(long, value_delimiter = ',', default_value = "50,100,250,1000,10000")]
+    pub histogram_buckets: Vec<u64>,
+    /// Hold back the percentile lines of the human stats until a delay window has this
+    /// many samples, printing "collecting samples" instead.
+    #[clap(long, default_value = "0")]
+    pub min_samples: usize,
+    /// On shutdown, write the |delay| HdrHistogram (microseconds) to this file as a
+    /// standard HdrHistogram interval log, for merging and plotting runs.
+    #[clap(long)]
+    pub hdr_out: Option<PathBuf>,
+    /// Events buffered between the receive tasks and the processor. Live datagrams that
+    /// arrive while it is full are dropped and counted rather than stalling the socket.
+    #[clap(long, default_value = "4096", value_parser = clap::value_parser!(u64).range(1..))]
+    pub channel_capacity: u64,
+    /// Warn whenever a live source has received nothing for this long, and again each
+    /// time that much more passes.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub silence_warn_secs: Option<u64>,
+    /// Exit with an error if, at shutdown, the delay statistic chosen by --threshold-stat
+    /// exceeds this many milliseconds (candidate minus reference, all shred types), or
+    /// if nothing matched.
+    #[clap(long, value_name = "MS")]
+    pub max_avg_delay_ms: Option<f64>,
+    /// Warn prominently when a source's rolling average delay vs the reference stays above
+    /// this many milliseconds for all of --alert-window-secs, and again once it recovers.
+    #[clap(long, value_name = "MS")]
+    pub alert_above_ms: Option<f64>,
+    /// How long the average must stay above --alert-above-ms before the alert fires.
+    #[clap(long, default_value = "30", requires = "alert_above_ms", value_parser = clap::value_parser!(u64).range(1..))]
+    pub alert_window_secs: u64,
+    /// Run this shell command when an alert fires or resolves, with SHRED_PERF_ALERT
+    /// (`firing` or `resolved`), SHRED_PERF_SOURCE, SHRED_PERF_REFERENCE,
+    /// SHRED_PERF_AVG_DELAY_NANOS and SHRED_PERF_THRESHOLD_NANOS set.
+    #[clap(long, value_name = "CMD", requires = "alert_above_ms")]
+    pub alert_cmd: Option<String>,
+    /// Which delay statistic --max-avg-delay-ms applies to.
+    #[clap(long, value_enum, default_value = "avg")]
+    pub threshold_stat: ThresholdStat,
+    /// Count matches but leave their delays out of the stats for this long after the
+    /// first shred arrives, while buffers and caches warm up.
+    #[clap(long, default_value = "0")]
+    pub warmup_secs: u64,
+    /// Save cumulative counters to this JSON file on every stats tick and at shutdown,
+    /// and resume from it on startup.
+    #[clap(long)]
+    pub state_file: Option<PathBuf>,
+    /// Attach a label to a source's metrics and NDJSON records, as `NAME:KEY=VALUE`, e.g.
+    /// `uk:region=eu-west`. Repeat for more labels or sources.
+    #[clap(long, value_name = "NAME:KEY=VALUE", value_parser = parse_label)]
+    pub source_label: Vec<(String, String, String)>,
+    /// Drop this many bytes from the front of every datagram a source receives before
+    /// parsing it, as `NAME=N`, for feeds that prepend their own framing. Repeat per source.
+    #[clap(long, value_name = "NAME=N", value_parser = parse_strip_prefix)]
+    pub strip_prefix_bytes: Vec<(String, usize)>,
+    /// Unwrap a tunnel from a source's datagrams before parsing them, as `NAME=KIND`
+    /// with KIND `vxlan` or `geneve`; applied after --strip-prefix-bytes. Repeat per source.
+    #[clap(long, value_name = "NAME=KIND", value_parser = parse_decapsulate)]
+    pub decapsulate: Vec<(String, Encapsulation)>,
+    /// Subtract this many microseconds from a source's arrival times before taking delays,
+    /// as `NAME=US`, to cancel out a network path difference measured separately (negative
+    /// to add). Delays, wins and exports use the adjusted times; the stats also show the
+    /// raw mean delay. Repeat per source.
+    #[clap(long, value_name = "NAME=US", value_parser = parse_baseline_offset)]
+    pub baseline_offset_us: Vec<(String, i64)>,
+    /// Receive a source on its own thread pinned to this CPU, as `NAME=CPU`. Repeat per
+    /// source. See --dedicated-thread.
+    #[clap(long, value_name = "NAME=CPU", value_parser = parse_pin)]
+    pub pin_cores: Vec<(String, usize)>,
+    /// Receive this source on a thread of its own, with its own single-threaded runtime,
+    /// instead of the shared tokio worker pool. Tasks there move between worker threads, so
+    /// this is also how --pin-cores keeps a source on one core. Repeat per source.
+    #[clap(long, value_name = "NAME")]
+    pub dedicated_thread: Vec<String>,
+    /// Re-send the first copy of every shred, from whichever source delivered it first,
+    /// to this UDP address; later copies are dropped. Turns the tool into a fastest-wins
+    /// aggregator.
+    #[clap(long, value_name = "ADDR")]
+    pub forward_to: Option<SocketAddr>,
+    /// Check the options and bind every live source's sockets, then exit: 0 if all of
+    /// them bound, 1 otherwise.
+    #[clap(long)]
+    pub dry_run: bool,
+    /// PEM certificate chain `quic://` sources present to connecting relays. Needs the
+    /// `quic` feature.
+    #[clap(long, value_name = "FILE", requires = "quic_key")]
+    pub quic_cert: Option<PathBuf>,
+    /// PEM private key for --quic-cert.
+    #[clap(long, value_name = "FILE", requires = "quic_cert")]
+    pub quic_key: Option<PathBuf>,
+    /// Replay a source from a pcap/pcapng capture instead of listening, as `NAME=FILE`.
+    /// Only UDP datagrams sent to that source's port are used, timestamped with their
+    /// capture time. The run ends once every replay is done, unless live sources remain.
+    #[clap(long, value_name = "NAME=FILE", value_parser = parse_pcap)]
+    pub pcap: Vec<(String, PathBuf)>,
+    /// Send synthetic shreds over loopback to the reference and one other source, the
+    /// latter --self-test-delay-us behind, and compare the measured delay at shutdown.
+    #[clap(long, conflicts_with = "pcap")]
+    pub self_test: bool,
+    /// Delay --self-test introduces between the two copies of each shred.
+    #[clap(long, default_value = "1000")]
+    pub self_test_delay_us: u64,
+    /// Shreds per second --self-test sends to each source; 0 sends as fast as possible.
+    #[clap(long, default_value = "10000")]
+    pub self_test_rate: u64,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Summarize --csv-out and --binary-log files from any number of runs as one: overall
+    /// delay percentiles, and match counts and win rates per source.
+    Merge(MergeArgs),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum FlushPolicy {
+    PerMatch,
+    PerTick,
+    PerN,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ShredTypeArg {
+    Data,
+    Code,
+}
+
+impl From<ShredTypeArg> for ShredType {
+    fn from(arg: ShredTypeArg) -> Self {
+        match arg {
+            ShredTypeArg::Data => ShredType::Data,
+            ShredTypeArg::Code => ShredType::Code,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SourceSpec {
+    name: String,
+    scheme: Scheme,
+    // One per port; all share the source's stats.
+    addrs: Vec<SocketAddr>,
+}
+
+impl SourceSpec {
+    fn ports(&self) -> Vec<u16> {
+        self.addrs.iter().map(SocketAddr::port).collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ListenerConfig {
+    rx_sockets: usize,
+    rcvbuf_bytes: usize,
+    max_packet_size: usize,
+    bind_retries: u32,
+    bind_retry_delay: Duration,
+    #[cfg(target_os = "linux")]
+    batch_recv: bool,
+    #[cfg(target_os = "linux")]
+    kernel_timestamps: bool,
+    // Set with --quic-cert.
+    #[cfg(feature = "quic")]
+    quic: Option<transport::Quic>,
+}
+
+// Turns one source's datagrams into processor events; each receive task holds a clone.
+#[derive(Clone)]
+struct Forwarder {
+    source_idx: usize,
+    name: Arc<str>,
+    sender: mpsc::Sender<ProcessorEvent>,
+    dropped: Arc<AtomicU64>,
+    // Nanoseconds after `epoch` of the latest datagram, for --silence-warn-secs.
+    last_seen: Arc<AtomicU64>,
+    epoch: Instant,
+    // Set with --verify-signatures.
+    leaders: Option<Arc<LeaderSchedule>>,
+    // Set with --forward-to.
+    relay: Option<Arc<Relay>>,
+    // Set with --strip-prefix-bytes and --decapsulate.
+    preprocess: Preprocess,
 }
 
 #[derive(Debug)]
 enum ProcessorEvent {
     ShredReceived {
-        port_id: u8,
-        name: Arc<str>,
+        source_idx: usize,
         shred_id: ShredId,
+        fec_set_index: u32,
         timestamp: Instant,
+        bytes: usize,
+    },
+    ParseFailed {
+        source_idx: usize,
+        bytes: usize,
+    },
+    // Filled the receive buffer, so likely cut short.
+    Truncated {
+        source_idx: usize,
+        bytes: usize,
+    },
+    // Parsed, but the signature didn't verify against the slot leader, or the slot's
+    // leader isn't in the schedule.
+    SignatureRejected {
+        source_idx: usize,
+        bytes: usize,
+        leader_known: bool,
+    },
+    // From --watch-config: start matching a new source, replying with its index and
+    // dropped-datagram counter.
+    AddSource {
+        name: Arc<str>,
+        labels: BTreeMap<String, String>,
+        added: oneshot::Sender<(usize, Arc<AtomicU64>)>,
+    },
+    RemoveSource {
+        source_idx: usize,
+    },
+    // Expire shreds older than the retention as of `now`: the wall clock, or during a
+    // replay the capture timeline.
+    Cleanup {
+        now: Instant,
     },
-    Cleanup,
     StatsTick,
+    Shutdown,
 }
 
-struct ProcessorState {
-    port0_data: HashMap<ShredId, Instant>,
-    port1_data: HashMap<ShredId, Instant>,
-    matched_pairs: usize,
-    delays: Vec<Duration>,
+impl Args {
+    fn load() -> anyhow::Result<Self> {
+        Ok(Args::parse_from(Self::command_line()?))
+    }
+
+    // For --watch-config: like `load`, but a bad file is an error rather than an exit.
+    fn reload() -> anyhow::Result<Self> {
+        Ok(Args::try_parse_from(Self::command_line()?)?)
+    }
+
+    // Config file flags go ahead of the command line's, minus any it sets itself, and the
+    // lot is parsed as one command line.
+    fn command_line() -> anyhow::Result<Vec<OsString>> {
+        let cli: Vec<OsString> = std::env::args_os().collect();
+        let matches = Args::command().ignore_errors(true).get_matches_from(&cli);
+        let Some(path) = matches.get_one::<PathBuf>("config") else {
+            return Ok(cli);
+        };
+        let file_args = ConfigFile::load(path)?.to_args(&Args::command(), &matches)?;
+        Ok(cli[..1].iter().cloned().chain(file_args).chain(cli[1..].iter().cloned()).collect())
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    pretty_env_logger::init();
-    let args = Args::parse();
+    let args = Args::load()?;
+    // RUST_LOG filters by target: `shred_perf::socket`, `shred_perf::processor` and
+    // `shred_perf::stats`, plus the module paths of everything else.
+    let subscriber = tracing_subscriber::fmt().with_env_filter(tracing_subscriber::EnvFilter::from_default_env());
+    // On stderr, as pretty_env_logger did, so --stats-format kv and csv output on stdout
+    // stays clean; JSON logs only take stdout when it isn't carrying those.
+    let writer = match args.log_format {
+        LogFormat::Json if args.stats_format == StatsFormat::Human => BoxMakeWriter::new(std::io::stdout),
+        _ => BoxMakeWriter::new(std::io::stderr),
+    };
+    // With --tui, into the dashboard's log pane while it is open, which can't show colours.
+    #[cfg(feature = "tui")]
+    let log_pane = (args.tui && std::io::stdout().is_terminal()).then(|| LogPane::new(args.log_format == LogFormat::Json));
+    #[cfg(feature = "tui")]
+    let (subscriber, writer) = match &log_pane {
+        Some(log_pane) => (subscriber.with_ansi(false), BoxMakeWriter::new(log_pane.clone())),
+        None => (subscriber, writer),
+    };
+    match args.log_format {
+        LogFormat::Pretty => subscriber.with_writer(writer).init(),
+        LogFormat::Json => subscriber.json().with_writer(writer).init(),
+    }
+    if let Some(Command::Merge(merge)) = &args.command {
+        Summary::load(&merge.files)?.print(args.delay_unit);
+        return Ok(());
+    }
+    anyhow::ensure!(args.sources.len() >= 2, "at least two --source values are required to compare");
+    anyhow::ensure!(
+        args.histogram_buckets.windows(2).all(|w| w[0] < w[1]),
+        "--histogram-buckets must be strictly increasing"
+    );
+    anyhow::ensure!(
+        args.index_buckets.iter().flatten().collect::<Vec<_>>().windows(2).all(|w| w[0] < w[1]),
+        "--index-buckets must be strictly increasing"
+    );
+    anyhow::ensure!(
+        args.slot_min.unwrap_or(0) <= args.slot_max.unwrap_or(u64::MAX),
+        "--slot-min must not be above --slot-max"
+    );
+    anyhow::ensure!(args.alert_above_ms.is_none_or(|ms| ms > 0.0), "--alert-above-ms must be positive");
+    anyhow::ensure!(
+        args.bloom_dedup.is_none_or(|rate| rate > 0.0 && rate < 1.0),
+        "--bloom-dedup must be in (0, 1)"
+    );
+    anyhow::ensure!(
+        args.ewma_alpha > 0.0 && args.ewma_alpha <= 1.0,
+        "--ewma-alpha must be in (0, 1]"
+    );
+    check_listen_addrs(&args)?;
+    let reference_idx = args
+        .reference_source
+        .parse()
+        .ok()
+        .filter(|idx| *idx < args.sources.len())
+        .or_else(|| args.sources.iter().position(|source| source.name == args.reference_source))
+        .with_context(|| format!("--reference-source `{}` is not a source index or name", args.reference_source))?;
+    // The other end of --self-test's delay: the first source that isn't the reference.
+    let self_test_candidate = (0..args.sources.len()).find(|idx| *idx != reference_idx).unwrap_or_default();
+    for (name, _) in &args.pcap {
+        let source = args.sources.iter().find(|source| source.name == *name);
+        anyhow::ensure!(source.is_some(), "--pcap refers to unknown source `{}`", name);
+        anyhow::ensure!(
+            source.is_some_and(|source| source.scheme == Scheme::Udp),
+            "--pcap replays UDP datagrams, but `{}` is not a udp source",
+            name
+        );
+    }
+    let quic_sources = args.sources.iter().filter(|source| source.scheme == Scheme::Quic).count();
+    #[cfg(not(feature = "quic"))]
+    anyhow::ensure!(
+        quic_sources == 0 && args.quic_cert.is_none(),
+        "quic:// sources require building with `--features quic`"
+    );
+    anyhow::ensure!(
+        quic_sources == 0 || args.quic_cert.is_some(),
+        "quic:// sources need a certificate to present, set with --quic-cert and --quic-key"
+    );
+    if args.self_test {
+        anyhow::ensure!(
+            [reference_idx, self_test_candidate].iter().all(|idx| args.sources[*idx].scheme == Scheme::Udp),
+            "--self-test sends datagrams, so the reference and the first other source must be udp"
+        );
+    }
+    for (name, _, _) in &args.source_label {
+        anyhow::ensure!(
+            args.sources.iter().any(|source| source.name == *name),
+            "--source-label refers to unknown source `{}`",
+            name
+        );
+    }
+    for (name, _) in &args.baseline_offset_us {
+        anyhow::ensure!(
+            args.sources.iter().any(|source| source.name == *name),
+            "--baseline-offset-us refers to unknown source `{}`",
+            name
+        );
+    }
+    for name in args.strip_prefix_bytes.iter().map(|(name, _)| name).chain(args.decapsulate.iter().map(|(name, _)| name)) {
+        anyhow::ensure!(
+            args.sources.iter().any(|source| source.name == *name),
+            "--strip-prefix-bytes/--decapsulate refers to unknown source `{}`",
+            name
+        );
+    }
+    let cores = core_affinity::get_core_ids().unwrap_or_default();
+    for (name, cpu) in &args.pin_cores {
+        anyhow::ensure!(
+            args.sources.iter().any(|source| source.name == *name),
+            "--pin-cores refers to unknown source `{}`",
+            name
+        );
+        anyhow::ensure!(cores.iter().any(|core| core.id == *cpu), "--pin-cores: no CPU {} on this machine", cpu);
+    }
+    for name in &args.dedicated_thread {
+        anyhow::ensure!(
+            args.sources.iter().any(|source| source.name == *name),
+            "--dedicated-thread refers to unknown source `{}`",
+            name
+        );
+    }
+    anyhow::ensure!(
+        !args.watch_config
+            || (args.pcap.is_empty() && !args.self_test && args.binary_log.is_none()),
+        "--watch-config can't be combined with --pcap, --self-test or --binary-log, which fix the sources at startup"
+    );
+    #[cfg(not(target_os = "linux"))]
+    anyhow::ensure!(!args.batch_recv, "--batch-recv requires Linux recvmmsg");
+    #[cfg(not(target_os = "linux"))]
+    if args.kernel_timestamps {
+        warn!(target: "shred_perf::socket", "--kernel-timestamps is only supported on Linux; using userspace timestamps");
+    }
+    clock::check();
 
-    let (processor_tx, mut processor_rx) = mpsc::channel(4096);
+    let listener_config = ListenerConfig {
+        rx_sockets: args.rx_sockets as usize,
+        rcvbuf_bytes: args.rcvbuf_bytes,
+        max_packet_size: args.max_packet_size as usize,
+        bind_retries: args.bind_retries,
+        bind_retry_delay: Duration::from_millis(args.bind_retry_delay_ms),
+        #[cfg(target_os = "linux")]
+        batch_recv: args.batch_recv,
+        #[cfg(target_os = "linux")]
+        kernel_timestamps: args.kernel_timestamps,
+        #[cfg(feature = "quic")]
+        quic: match (&args.quic_cert, &args.quic_key) {
+            (Some(cert), Some(key)) => Some(transport::Quic::new(cert, key)?),
+            _ => None,
+        },
+    };
+    if args.dry_run {
+        return dry_run(&args, &listener_config);
+    }
 
-    let port0_task = start_port_listener(0, args.name_0.clone().into(), args.port_0, processor_tx.clone());
-    let port1_task = start_port_listener(1, args.name_1.clone().into(), args.port_1, processor_tx.clone());
+    let source_names: Vec<Arc<str>> = args.sources.iter().map(|source| source.name.as_str().into()).collect();
+    let labelled_sources: Vec<(Arc<str>, BTreeMap<String, String>)> =
+        source_names.iter().map(|name| (Arc::clone(name), source_labels(&args, name))).collect();
+    let metrics = match args.metrics_port {
+        Some(port) => {
+            let metrics = Arc::new(Metrics::new(&labelled_sources)?);
+            let server_metrics = Arc::clone(&metrics);
+            tokio::spawn(async move {
+                if let Err(e) = metrics::serve(server_metrics, port).await {
+                    error!("Metrics server on port {} failed: {}", port, e);
+                }
+            });
+            Some(metrics)
+        }
+        None => None,
+    };
 
+    #[cfg(not(feature = "otlp"))]
+    anyhow::ensure!(args.otlp_endpoint.is_none(), "--otlp-endpoint requires building with `--features otlp`");
+    #[cfg(feature = "otlp")]
+    let otel = match &args.otlp_endpoint {
+        Some(endpoint) => {
+            let otel = solana_shred_perf::otel::OtelMetrics::new(endpoint, &labelled_sources)
+                .with_context(|| format!("failed to set up OTLP export to {}", endpoint))?;
+            Some(Arc::new(otel))
+        }
+        None => None,
+    };
+
+    #[cfg(not(feature = "grpc"))]
+    anyhow::ensure!(args.grpc_port.is_none(), "--grpc-port requires building with `--features grpc`");
+    #[cfg(feature = "grpc")]
+    let match_events = args.grpc_port.map(|port| {
+        let (match_events, _) = tokio::sync::broadcast::channel(args.channel_capacity as usize);
+        let server_events = match_events.clone();
+        tokio::spawn(async move {
+            if let Err(e) = solana_shred_perf::grpc::serve(server_events, port).await {
+                error!("gRPC server on port {} failed: {:#}", port, e);
+            }
+        });
+        match_events
+    });
+    #[cfg(not(feature = "grpc"))]
+    let match_events = None;
+
+    let csv_out = match &args.csv_out {
+        Some(path) => Some(CsvWriter::create(path, args.delay_unit).with_context(|| format!("failed to open {}", path.display()))?),
+        None => None,
+    };
+
+    // Replayed captures keep their own timeline: the earliest captured datagram maps to now,
+    // and exported timestamps show the original capture time.
+    let mut replay_start = None;
+    for (source, path) in pcap_sources(&args) {
+        match pcap::first_timestamp(path, &source.ports())? {
+            Some(ts) => replay_start = Some(replay_start.map_or(ts, |start: u64| start.min(ts))),
+            None => warn!("[{}] No UDP datagrams to port(s) {:?} in {}", source.name, source.ports(), path.display()),
+        }
+    }
+    let wall_clock = match replay_start {
+        Some(start) => WallClock::anchored(Instant::now(), start),
+        None => WallClock::now(),
+    };
+
+    let ndjson_out = match &args.ndjson_out {
+        Some(path) => Some(
+            NdjsonWriter::create(
+                path,
+                args.rotate_mb.map(|mb| mb * 1_000_000),
+                args.delay_unit,
+                labelled_sources.iter().map(|(name, labels)| (name.to_string(), labels.clone())).collect(),
+            )
+                .with_context(|| format!("failed to open {}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let json_stats_out = match &args.json_stats_out {
+        Some(path) => Some(JsonStatsWriter::create(path).with_context(|| format!("failed to open {}", path.display()))?),
+        None => None,
+    };
+
+    let binary_log = match &args.binary_log {
+        Some(path) => Some(BinaryLogWriter::create(path, &source_names).with_context(|| format!("failed to open {}", path.display()))?),
+        None => None,
+    };
+
+    let saved_stats = match &args.state_file {
+        Some(path) => persist::load(path).with_context(|| format!("failed to load {}", path.display()))?,
+        None => None,
+    };
+
+    let leaders = match &args.leader_schedule {
+        Some(path) => {
+            let leaders = LeaderSchedule::load(path)?;
+            info!("Verifying shred signatures against {} scheduled leader slots", leaders.slots());
+            Some(Arc::new(leaders))
+        }
+        None => None,
+    };
+
+    let reference_shreds = match &args.reference_shreds {
+        Some(path) => {
+            let reference_shreds = ReferenceShreds::load(path)?;
+            info!("Scoring sources against {} reference shreds", reference_shreds.shreds().len());
+            Some(reference_shreds)
+        }
+        None => None,
+    };
+
+    #[cfg(not(feature = "tui"))]
+    anyhow::ensure!(!args.tui, "--tui requires building with `--features tui`");
+    #[cfg(not(feature = "tui"))]
+    let mut quit: Option<mpsc::UnboundedReceiver<()>> = None;
+    #[cfg(feature = "tui")]
+    let (dashboard, mut quit) = match log_pane {
+        Some(log_pane) => {
+            let (dashboard, quit) = Dashboard::open(log_pane).context("failed to open the --tui dashboard")?;
+            (Some(dashboard), Some(quit))
+        }
+        None => {
+            if args.tui {
+                warn!("stdout isn't a terminal, logging stats instead of showing --tui");
+            }
+            (None, None)
+        }
+    };
+
+    let mut processor = Processor::new(
+        source_names.clone(),
+        ProcessorConfig {
+            reference_idx,
+            match_key: args.match_key,
+            filter_type: args.filter_type.map(ShredType::from),
+            slot_range: args.slot_min.unwrap_or(0)..=args.slot_max.unwrap_or(u64::MAX),
+            max_slot_age: args.max_slot_age,
+            sample_rate: args.sample_rate,
+            retention: Duration::from_secs(args.timeout_secs),
+            evict_after_slots: args.evict_after_slots,
+            expiry_report: args.no_match_expiry_report,
+            warmup: Duration::from_secs(args.warmup_secs),
+            delay_window: args.delay_window as usize,
+            ewma_alpha: args.ewma_alpha,
+            per_slot_report: args.per_slot_report,
+            per_fec_set_report: args.per_fec_set_report,
+            index_buckets: args.index_buckets.clone(),
+            baseline_offsets: (!args.baseline_offset_us.is_empty()).then(|| {
+                args.sources
+                    .iter()
+                    .map(|source| {
+                        let offset = args.baseline_offset_us.iter().rfind(|(name, _)| *name == source.name);
+                        offset.map_or(0, |(_, us)| us * 1000)
+                    })
+                    .collect()
+            }),
+            top_laggards: args.top_laggards.map(|n| n as usize),
+            reset_stats_each_tick: args.reset_stats_each_tick,
+            imbalance_ratio: (args.imbalance_warn_ratio > 0.0).then_some(args.imbalance_warn_ratio),
+            alert_above: args.alert_above_ms.map(|ms| (ms * 1e6) as i64),
+            alert_window: Duration::from_secs(args.alert_window_secs),
+            alert_cmd: args.alert_cmd.clone(),
+            slot_gaps: args.slot_gaps.map(|slots| slots as usize),
+            completeness_report: args.completeness_report.map(|slots| slots as usize),
+            shreds_per_slot: args.shreds_per_slot,
+            index_loss: args.index_loss.map(|slots| slots as usize),
+            first_seen_wins: args.first_seen_wins,
+            inter_arrival: args.inter_arrival,
+            reorder_depth: args.reorder_depth,
+            compare_mode: args.compare_mode,
+            agreement_latency: args.agreement_latency,
+            reference_shreds,
+            bloom_dedup: args.bloom_dedup,
+            histogram_edges: args.histogram_buckets.iter().map(|us| *us as i64 * 1000).collect(),
+            min_samples: args.min_samples,
+            signatures_verified: leaders.is_some(),
+            quiet: args.quiet,
+            log_format: args.log_format,
+            stats_format: args.stats_format,
+            delay_unit: args.delay_unit,
+            metrics,
+            #[cfg(feature = "otlp")]
+            otel: otel.clone(),
+            wall_clock,
+            csv_out,
+            ndjson_out,
+            json_stats_out,
+            #[cfg(feature = "tui")]
+            dashboard,
+            binary_log,
+            flush_every: match args.flush_policy {
+                FlushPolicy::PerMatch => Some(1),
+                FlushPolicy::PerTick => None,
+                FlushPolicy::PerN => Some(args.flush_every),
+            },
+            match_events,
+            state_file: args.state_file.clone(),
+        },
+    )?;
+    if let Some(saved) = saved_stats {
+        processor.restore(saved);
+    }
+
+    let (processor_tx, mut processor_rx) = mpsc::channel(args.channel_capacity as usize);
+
+    let relay = match args.forward_to {
+        Some(target) => {
+            let relay = Relay::bind(target, &source_names).context("failed to bind --forward-to socket")?;
+            info!("Forwarding the first copy of every shred to {}", target);
+            Some(Arc::new(relay))
+        }
+        None => None,
+    };
+    let mut replay_tasks = JoinSet::new();
+    let epoch = Instant::now();
+    let silence = args.silence_warn_secs.map(|secs| Arc::new(Mutex::new(SilenceMonitor::new(Duration::from_secs(secs), epoch))));
+    let mut receivers = Receivers {
+        config: listener_config,
+        sender: processor_tx.clone(),
+        epoch,
+        leaders,
+        relay: relay.clone(),
+        silence: silence.clone(),
+        tasks: JoinSet::new(),
+        running: BTreeMap::new(),
+    };
+    // Replays run ahead of the wall clock, so with nothing live they drive cleanup.
+    let replay_clock = (!args.pcap.is_empty() && pcap_sources(&args).count() == args.sources.len()).then(|| {
+        let every = Duration::from_secs(args.cleanup_interval_secs.unwrap_or(args.timeout_secs));
+        Arc::new(ReplayClock::new(wall_clock, every, Duration::from_secs(args.timeout_secs)))
+    });
+    for (source_idx, source) in args.sources.iter().enumerate() {
+        let dropped = processor.dropped_counter(source_idx);
+        if let Some((_, path)) = args.pcap.iter().find(|(name, _)| *name == source.name) {
+            let forwarder = receivers.forwarder(&args, source, source_idx, dropped);
+            let (path, ports) = (path.clone(), source.ports());
+            let replay_clock = replay_clock.clone();
+            if let Some(replay_clock) = &replay_clock {
+                replay_clock.start(source_idx);
+            }
+            replay_tasks.spawn_blocking(move || replay_pcap(&path, &ports, wall_clock, forwarder, replay_clock));
+            continue;
+        }
+        receivers.start(&args, source, source_idx, dropped);
+    }
+    let mut config_changes = match (&args.config, args.watch_config) {
+        (Some(path), true) => Some(watch_file(path).with_context(|| format!("failed to watch {}", path.display()))?),
+        _ => None,
+    };
+
+    if args.self_test {
+        let targets = [reference_idx, self_test_candidate].map(|idx| loopback(args.sources[idx].addrs[0]));
+        let delay = Duration::from_micros(args.self_test_delay_us);
+        info!(
+            "Self-test: sending {} shreds/s to {} and {}, {:?} apart",
+            args.self_test_rate, targets[0], targets[1], delay
+        );
+        selftest::spawn(targets, delay, args.self_test_rate).context("failed to start self-test senders")?;
+    }
+
+    let replay_driven = replay_clock.is_some();
     let timer_task = {
         let processor_tx = processor_tx.clone();
+        let relay = relay.clone();
         tokio::spawn(async move {
-            let mut cleanup_interval = time::interval(Duration::from_secs(args.timeout_secs));
-            let mut stats_interval = time::interval(Duration::from_secs(10));
+            let cleanup_secs = args.cleanup_interval_secs.unwrap_or(args.timeout_secs);
+            let mut cleanup_interval = time::interval(Duration::from_secs(cleanup_secs));
+            let mut stats_interval = time::interval(Duration::from_secs(args.stats_interval_secs));
+            let mut stats_signal = StatsSignal::new();
+            let mut silence_interval = time::interval(Duration::from_secs(1));
+            let run_deadline = async {
+                match args.run_for_secs {
+                    Some(secs) => time::sleep(Duration::from_secs(secs)).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::pin!(run_deadline);
 
             loop {
                 tokio::select! {
-                    _ = cleanup_interval.tick() => {
-                        processor_tx.send(ProcessorEvent::Cleanup).await.ok();
+                    _ = &mut run_deadline => {
+                        info!("Run time elapsed, shutting down...");
+                        return;
+                    }
+                    _ = cleanup_interval.tick(), if !replay_driven => {
+                        processor_tx.send(ProcessorEvent::Cleanup { now: Instant::now() }).await.ok();
+                        if let Some(relay) = &relay {
+                            relay.cleanup(Instant::now(), Duration::from_secs(args.timeout_secs));
+                        }
                     }
                     _ = stats_interval.tick() => {
                         processor_tx.send(ProcessorEvent::StatsTick).await.ok();
                     }
+                    _ = stats_signal.recv() => {
+                        processor_tx.send(ProcessorEvent::StatsTick).await.ok();
+                    }
+                    _ = silence_interval.tick(), if silence.is_some() => {
+                        if let Some(silence) = &silence {
+                            silence.lock().unwrap().check(Instant::now());
+                        }
+                    }
                 }
             }
         })
     };
 
-    let processor_task = tokio::spawn(async move {
-        let mut state = ProcessorState {
-            port0_data: HashMap::new(),
-            port1_data: HashMap::new(),
-            matched_pairs: 0,
-            delays: Vec::new(),
-        };
-
+    let reference_name = args.sources[reference_idx].name.clone();
+    let run_start = Instant::now();
+    let mut processor_task = tokio::spawn(async move {
         while let Some(event) = processor_rx.recv().await {
             match event {
-                ProcessorEvent::ShredReceived { port_id, name,shred_id, timestamp } => {
-                    process_shred(&mut state, port_id, name, shred_id, timestamp);
+                ProcessorEvent::ShredReceived { source_idx, shred_id, fec_set_index, timestamp, bytes } => {
+                    processor.record_bytes(source_idx, bytes);
+                    processor.record_with_fec_set(source_idx, shred_id, fec_set_index, timestamp);
+                }
+                ProcessorEvent::ParseFailed { source_idx, bytes } => {
+                    processor.record_bytes(source_idx, bytes);
+                    processor.record_parse_failure(source_idx);
+                }
+                ProcessorEvent::Truncated { source_idx, bytes } => {
+                    processor.record_bytes(source_idx, bytes);
+                    processor.record_truncated(source_idx, bytes);
+                }
+                ProcessorEvent::SignatureRejected { source_idx, bytes, leader_known } => {
+                    processor.record_bytes(source_idx, bytes);
+                    processor.record_signature_rejected(source_idx, leader_known);
                 }
-                ProcessorEvent::Cleanup => {
-                    cleanup_data(&mut state, Duration::from_secs(args.timeout_secs));
+                ProcessorEvent::AddSource { name, labels, added } => {
+                    let source_idx = processor.add_source(name, &labels);
+                    added.send((source_idx, processor.dropped_counter(source_idx))).ok();
                 }
+                ProcessorEvent::RemoveSource { source_idx } => processor.remove_source(source_idx)?,
+                ProcessorEvent::Cleanup { now } => processor.cleanup(now),
                 ProcessorEvent::StatsTick => {
-                    report_stats(&state, &args);
+                    processor.report_stats();
+                    processor.flush_outputs();
+                    processor.start_stats_interval();
+                }
+                ProcessorEvent::Shutdown => {
+                    processor.finish();
+                    info!("Final stats:");
+                    processor.report_stats();
+                    processor.flush_outputs();
+                    #[cfg(feature = "otlp")]
+                    if let Some(otel) = &otel {
+                        otel.shutdown();
+                    }
+                    if let Some(relay) = &relay {
+                        relay.log_summary();
+                    }
+                    if args.self_test {
+                        let delay = Duration::from_micros(args.self_test_delay_us);
+                        selftest::report(&processor.stats(), self_test_candidate, delay);
+                    }
+                    if let Some(path) = &args.hdr_out {
+                        processor.write_hdr_log(path, run_start)?;
+                    }
+                    if let Some(max_ms) = args.max_avg_delay_ms {
+                        return processor.check_delay_threshold(max_ms, args.threshold_stat);
+                    }
+                    break;
                 }
             }
         }
+        Ok(())
     });
 
+    let live_sources = !receivers.tasks.is_empty();
+    let mut timer_task = timer_task;
+    loop {
+        tokio::select! {
+            Some(result) = receivers.tasks.join_next() => {
+                // Listeners of removed sources end cancelled.
+                if result.as_ref().is_err_and(tokio::task::JoinError::is_cancelled) {
+                    continue;
+                }
+                result??;
+                break;
+            }
+            _ = async { while replay_tasks.join_next().await.is_some() {} }, if !live_sources => {
+                info!("Replay finished");
+                break;
+            }
+            result = &mut processor_task => return result?,
+            _ = &mut timer_task => break,
+            _ = tokio::signal::ctrl_c() => {
+                info!("Shutting down, draining queued shreds (Ctrl-C again to exit immediately)...");
+                break;
+            }
+            Some(()) = async { quit.as_mut()?.recv().await }, if quit.is_some() => {
+                info!("Shutting down, draining queued shreds...");
+                break;
+            }
+            Some(()) = async { config_changes.as_mut()?.1.recv().await }, if config_changes.is_some() => {
+                // Editors often write a file in several steps; let them finish.
+                time::sleep(Duration::from_millis(100)).await;
+                if let Some((_, changes)) = &mut config_changes {
+                    while changes.try_recv().is_ok() {}
+                }
+                receivers.reload(&reference_name).await;
+            }
+        }
+    }
+
+    // Stop taking in packets, then queue the shutdown behind any still pending so the final
+    // summary includes them.
+    receivers.tasks.abort_all();
+    replay_tasks.abort_all();
+    processor_tx.send(ProcessorEvent::Shutdown).await.ok();
     tokio::select! {
-        _ = port0_task => {},
-        _ = port1_task => {},
-        _ = processor_task => {},
-        _ = timer_task => {},
-        _ = tokio::signal::ctrl_c() => info!("Shutting down..."),
+        result = &mut processor_task => result?,
+        _ = tokio::signal::ctrl_c() => {
+            warn!("Interrupted again, exiting without the final summary");
+            std::process::exit(130);
+        }
     }
+}
 
+// Binds (and immediately releases) the sockets a real run would listen on.
+fn dry_run(args: &Args, config: &ListenerConfig) -> anyhow::Result<()> {
+    let mut failed = 0;
+    let mut bound = 0;
+    for source in &args.sources {
+        if args.pcap.iter().any(|(name, _)| *name == source.name) {
+            info!("[{}] Replayed from a capture, nothing to bind", source.name);
+            continue;
+        }
+        for addr in &source.addrs {
+            // Held until every socket for the port is bound, as --rx-sockets would.
+            let sockets: std::io::Result<Vec<_>> = match source.scheme {
+                Scheme::Udp => (0..config.rx_sockets).map(|_| bind_socket(*addr, config)).collect(),
+                Scheme::Quic => bind_socket(*addr, config).map(|socket| vec![socket]),
+                Scheme::Tcp => transport::bind_tcp(*addr, config).map(|_| Vec::new()),
+            };
+            match sockets {
+                Ok(sockets) => {
+                    let rcvbuf = sockets.first().map_or(config.rcvbuf_bytes, |(_, rcvbuf)| *rcvbuf);
+                    info!(
+                        target: "shred_perf::socket",
+                        "[{}] Bound {}://{} (rcvbuf {} bytes)",
+                        source.name, source.scheme, addr, rcvbuf
+                    );
+                    bound += 1;
+                }
+                Err(e) => {
+                    error!(target: "shred_perf::socket", "[{}] Failed to bind {}: {}", source.name, addr, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    anyhow::ensure!(failed == 0, "dry run: {} of {} addresses failed to bind", failed, failed + bound);
+    info!("Dry run passed: {} address(es) bound", bound);
     Ok(())
 }
 
-fn start_port_listener(
-    port_id: u8,
-    name: Arc<str>,
-    port: u16,
+// Warns about live sources that stopped delivering. The warning repeats every `threshold`
+// while a source stays silent, and its recovery is logged once.
+struct SilenceMonitor {
+    threshold: Duration,
+    epoch: Instant,
+    // Per watched source: its forwarder's `last_seen`, and the silence last warned about.
+    sources: Vec<(Arc<str>, Arc<AtomicU64>, Option<Duration>)>,
+}
+
+impl SilenceMonitor {
+    fn new(threshold: Duration, epoch: Instant) -> Self {
+        Self {
+            threshold,
+            epoch,
+            sources: Vec::new(),
+        }
+    }
+
+    fn watch(&mut self, forwarder: &Forwarder) {
+        self.sources.push((Arc::clone(&forwarder.name), Arc::clone(&forwarder.last_seen), None));
+    }
+
+    fn unwatch(&mut self, name: &str) {
+        self.sources.retain(|(watched, _, _)| **watched != *name);
+    }
+
+    fn check(&mut self, now: Instant) {
+        for (name, last_seen, warned) in &mut self.sources {
+            let last_seen = self.epoch + Duration::from_nanos(last_seen.load(Ordering::Relaxed));
+            let silent = now.saturating_duration_since(last_seen);
+            if silent >= self.threshold {
+                if warned.is_none_or(|warned| silent >= warned + self.threshold) {
+                    warn!(target: "shred_perf::socket", "Port {} silent for {}s", name, silent.as_secs());
+                    *warned = Some(silent);
+                }
+            } else if warned.take().is_some() {
+                info!(target: "shred_perf::socket", "Port {} receiving again", name);
+            }
+        }
+    }
+}
+
+// SIGUSR1 asks for an immediate stats report. Never fires where there is no such signal.
+struct StatsSignal {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl StatsSignal {
+    fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let signal = signal(SignalKind::user_defined1())
+                .inspect_err(|e| warn!("Failed to install SIGUSR1 handler: {}", e))
+                .ok();
+            Self { signal }
+        }
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            if signal.recv().await.is_some() {
+                return;
+            }
+            self.signal = None;
+        }
+        std::future::pending().await
+    }
+}
+
+fn parse_source(s: &str) -> Result<SourceSpec, String> {
+    let (name, addr) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected `name:[ip:]port[,port...]`, got `{}`", s))?;
+    if name.is_empty() {
+        return Err(format!("missing source name in `{}`", s));
+    }
+    let (scheme, addr) = match addr.split_once("://") {
+        Some((scheme, addr)) => (scheme.parse()?, addr),
+        None => (Scheme::Udp, addr),
+    };
+    let (ip, ports) = match addr.rsplit_once(':') {
+        // IPv6 addresses need brackets, as in `name:[::1]:8001`.
+        Some((ip, ports)) => {
+            let ip = match ip.strip_prefix('[').and_then(|ip| ip.strip_suffix(']')) {
+                Some(v6) => v6.parse::<Ipv6Addr>().map(IpAddr::V6),
+                None if ip.contains(':') => return Err(format!("IPv6 address needs brackets in `{}`", s)),
+                None => ip.parse::<Ipv4Addr>().map(IpAddr::V4),
+            };
+            (ip.map_err(|e| format!("invalid bind address in `{}`: {}", s, e))?, ports)
+        }
+        None => (IpAddr::V4(Ipv4Addr::UNSPECIFIED), addr),
+    };
+    let addrs = ports
+        .split(',')
+        .map(|port| {
+            let port = port.parse().map_err(|e| format!("invalid port in `{}`: {}", s, e))?;
+            Ok(SocketAddr::new(ip, port))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(SourceSpec {
+        name: name.to_string(),
+        scheme,
+        addrs,
+    })
+}
+
+// Where to reach a listener from this host: its own address, or loopback for a wildcard.
+fn loopback(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, addr.port()).into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => (Ipv6Addr::LOCALHOST, addr.port()).into(),
+        _ => addr,
+    }
+}
+
+fn addrs_overlap(a: SocketAddr, b: SocketAddr) -> bool {
+    a.port() == b.port() && (a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified())
+}
+
+fn parse_pcap(s: &str) -> Result<(String, PathBuf), String> {
+    match s.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => Ok((name.to_string(), path.into())),
+        _ => Err(format!("expected `name=file`, got `{}`", s)),
+    }
+}
+
+fn parse_label(s: &str) -> Result<(String, String, String), String> {
+    let (name, label) = s.split_once(':').ok_or_else(|| format!("expected `name:key=value`, got `{}`", s))?;
+    let (key, value) = label.split_once('=').ok_or_else(|| format!("expected `name:key=value`, got `{}`", s))?;
+    // Keys become Prometheus label names.
+    let valid_key = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if name.is_empty() || !valid_key || key == "source" {
+        return Err(format!("invalid label `{}`: keys are letters, digits and `_`, and not `source`", s));
+    }
+    Ok((name.to_string(), key.to_string(), value.to_string()))
+}
+
+fn parse_strip_prefix(s: &str) -> Result<(String, usize), String> {
+    match s.split_once('=') {
+        Some((name, bytes)) if !name.is_empty() => {
+            let bytes = bytes.parse().map_err(|e| format!("invalid byte count in `{}`: {}", s, e))?;
+            Ok((name.to_string(), bytes))
+        }
+        _ => Err(format!("expected `name=bytes`, got `{}`", s)),
+    }
+}
+
+fn parse_decapsulate(s: &str) -> Result<(String, Encapsulation), String> {
+    match s.split_once('=') {
+        Some((name, kind)) if !name.is_empty() => Ok((name.to_string(), Encapsulation::from_str(kind, true)?)),
+        _ => Err(format!("expected `name=kind`, got `{}`", s)),
+    }
+}
+
+fn parse_baseline_offset(s: &str) -> Result<(String, i64), String> {
+    match s.split_once('=') {
+        Some((name, us)) if !name.is_empty() => {
+            let us = us.parse().map_err(|e| format!("invalid offset in `{}`: {}", s, e))?;
+            Ok((name.to_string(), us))
+        }
+        _ => Err(format!("expected `name=microseconds`, got `{}`", s)),
+    }
+}
+
+fn parse_pin(s: &str) -> Result<(String, usize), String> {
+    match s.split_once('=') {
+        Some((name, cpu)) if !name.is_empty() => {
+            let cpu = cpu.parse().map_err(|e| format!("invalid CPU in `{}`: {}", s, e))?;
+            Ok((name.to_string(), cpu))
+        }
+        _ => Err(format!("expected `name=cpu`, got `{}`", s)),
+    }
+}
+
+fn pcap_sources(args: &Args) -> impl Iterator<Item = (&SourceSpec, &PathBuf)> {
+    args.pcap.iter().filter_map(|(name, path)| {
+        let source = args.sources.iter().find(|source| source.name == *name)?;
+        Some((source, path))
+    })
+}
+
+// Feeds a capture through the same parse path as live traffic, as fast as the processor
+// keeps up. Runs on a blocking thread.
+fn replay_pcap(path: &Path, ports: &[u16], clock: WallClock, forwarder: Forwarder, replay_clock: Option<Arc<ReplayClock>>) {
+    let mut replayed = 0u64;
+    let mut next_cleanup = None;
+    let result = pcap::for_each_udp(path, ports, |ts, data| {
+        replayed += 1;
+        if forwarder.sender.blocking_send(forwarder.parse(data, clock.instant(ts))).is_err() {
+            return false;
+        }
+        let Some(replay_clock) = &replay_clock else {
+            return true;
+        };
+        let next = *next_cleanup.get_or_insert(ts.saturating_add(replay_clock.every));
+        if ts < next {
+            return true;
+        }
+        next_cleanup = Some(ts.saturating_add(replay_clock.every));
+        let Some(now) = replay_clock.advance(forwarder.source_idx, ts) else {
+            return true;
+        };
+        if let Some(relay) = &forwarder.relay {
+            relay.cleanup(now, replay_clock.retention);
+        }
+        forwarder.sender.blocking_send(ProcessorEvent::Cleanup { now }).is_ok()
+    });
+    if let Some(replay_clock) = &replay_clock {
+        replay_clock.finish(forwarder.source_idx);
+    }
+    match result {
+        Ok(()) => info!("[{}] Replayed {} datagrams from {}", forwarder.name, replayed, path.display()),
+        Err(e) => error!("[{}] Replay failed: {:#}", forwarder.name, e),
+    }
+}
+
+// Cleanup's clock while every source is replayed. Replays running side by side each only
+// advance it as far as the one furthest behind, so shreds the others haven't replayed yet
+// aren't expired.
+struct ReplayClock {
+    clock: WallClock,
+    // Capture nanoseconds between cleanups.
+    every: u64,
+    retention: Duration,
+    // Capture time each unfinished replay has reached, by source index; `None` until its
+    // first cleanup.
+    positions: Mutex<BTreeMap<usize, Option<u64>>>,
+}
+
+impl ReplayClock {
+    fn new(clock: WallClock, every: Duration, retention: Duration) -> Self {
+        Self {
+            clock,
+            every: every.as_nanos() as u64,
+            retention,
+            positions: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Holds cleanup back until `source_idx`'s replay has got going.
+    fn start(&self, source_idx: usize) {
+        self.positions.lock().unwrap().insert(source_idx, None);
+    }
+
+    // Records that `source_idx` has replayed up to `ts` and returns the time cleanup has
+    // reached, if every replay has started.
+    fn advance(&self, source_idx: usize, ts: u64) -> Option<Instant> {
+        let mut positions = self.positions.lock().unwrap();
+        positions.insert(source_idx, Some(ts));
+        let behind = positions.values().min().copied().flatten()?;
+        Some(self.clock.instant(behind))
+    }
+
+    fn finish(&self, source_idx: usize) {
+        self.positions.lock().unwrap().remove(&source_idx);
+    }
+}
+
+// Starts and stops the live sources' receive tasks, so --watch-config can change them while
+// running.
+struct Receivers {
+    config: ListenerConfig,
     sender: mpsc::Sender<ProcessorEvent>,
-) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(async move {
-        let socket = match UdpSocket::bind(format!("0.0.0.0:{}", port)).await {
-            Ok(s) => s,
+    epoch: Instant,
+    leaders: Option<Arc<LeaderSchedule>>,
+    relay: Option<Arc<Relay>>,
+    silence: Option<Arc<Mutex<SilenceMonitor>>>,
+    tasks: JoinSet<anyhow::Result<()>>,
+    // By name: the spec each live source was started with, its index and dropped counter,
+    // and its listener tasks.
+    running: BTreeMap<String, (SourceSpec, usize, Arc<AtomicU64>, Vec<AbortHandle>)>,
+}
+
+impl Receivers {
+    fn forwarder(&self, args: &Args, source: &SourceSpec, source_idx: usize, dropped: Arc<AtomicU64>) -> Forwarder {
+        Forwarder {
+            source_idx,
+            name: source.name.as_str().into(),
+            sender: self.sender.clone(),
+            dropped,
+            last_seen: Arc::default(),
+            epoch: self.epoch,
+            leaders: self.leaders.clone(),
+            relay: self.relay.clone(),
+            preprocess: Preprocess {
+                strip_prefix: args.strip_prefix_bytes.iter().rfind(|(name, _)| *name == source.name).map_or(0, |(_, n)| *n),
+                encapsulation: args.decapsulate.iter().rfind(|(name, _)| *name == source.name).map(|(_, kind)| *kind),
+            },
+        }
+    }
+
+    // Listens for `source` with the per-source options in `args`.
+    fn start(&mut self, args: &Args, source: &SourceSpec, source_idx: usize, dropped: Arc<AtomicU64>) {
+        let forwarder = self.forwarder(args, source, source_idx, Arc::clone(&dropped));
+        if let Some(silence) = &self.silence {
+            silence.lock().unwrap().watch(&forwarder);
+        }
+        let pinned = args.pin_cores.iter().find(|(name, _)| *name == source.name).map(|(_, cpu)| *cpu);
+        let handles = if pinned.is_some() || args.dedicated_thread.contains(&source.name) {
+            let listener = start_dedicated_listener(source.scheme, source.addrs.clone(), pinned, self.config.clone(), forwarder);
+            vec![self.tasks.spawn(listener)]
+        } else {
+            source
+                .addrs
+                .iter()
+                .map(|addr| self.tasks.spawn(start_port_listener(source.scheme, *addr, self.config.clone(), forwarder.clone())))
+                .collect()
+        };
+        self.running.insert(source.name.clone(), (source.clone(), source_idx, dropped, handles));
+    }
+
+    fn stop(&mut self, name: &str) -> Option<(SourceSpec, usize, Arc<AtomicU64>)> {
+        let (source, source_idx, dropped, handles) = self.running.remove(name)?;
+        handles.iter().for_each(AbortHandle::abort);
+        if let Some(silence) = &self.silence {
+            silence.lock().unwrap().unwatch(name);
+        }
+        Some((source, source_idx, dropped))
+    }
+
+    // Applies the source list of the changed --config file. A file that doesn't parse, or
+    // whose sources couldn't run, is skipped with a warning and the current sources kept.
+    async fn reload(&mut self, reference: &str) {
+        let args = match Args::reload().and_then(|args| {
+            anyhow::ensure!(args.sources.len() >= 2, "at least two sources are required to compare");
+            anyhow::ensure!(
+                args.sources.iter().any(|source| source.name == reference),
+                "the reference source `{}` can't be removed",
+                reference
+            );
+            check_listen_addrs(&args)?;
+            Ok(args)
+        }) {
+            Ok(args) => args,
             Err(e) => {
-                error!("[{}] Failed to bind port {}: {}", name, port, e);
+                warn!("Not applying the changed config file: {:#}", e);
                 return;
             }
         };
-        info!("[{}] Listening on port {}", name, port);
+        let removed: Vec<String> = self
+            .running
+            .keys()
+            .filter(|name| !args.sources.iter().any(|source| source.name == **name))
+            .cloned()
+            .collect();
+        for name in removed {
+            if let Some((_, source_idx, _)) = self.stop(&name) {
+                self.sender.send(ProcessorEvent::RemoveSource { source_idx }).await.ok();
+                info!("Removed source `{}`", name);
+            }
+        }
+        for source in &args.sources {
+            match self.running.get(&source.name) {
+                Some((running, ..)) if running == source => continue,
+                // Same name, new address: it keeps its stats.
+                Some(_) => {
+                    if let Some((_, source_idx, dropped)) = self.stop(&source.name) {
+                        info!("Restarting source `{}` on {:?}", source.name, source.addrs);
+                        self.start(&args, source, source_idx, dropped);
+                    }
+                }
+                None => {
+                    let (added, reply) = oneshot::channel();
+                    let name: Arc<str> = source.name.as_str().into();
+                    let labels = source_labels(&args, &name);
+                    self.sender.send(ProcessorEvent::AddSource { name: Arc::clone(&name), labels, added }).await.ok();
+                    let Ok((source_idx, dropped)) = reply.await else {
+                        return;
+                    };
+                    if let Some(relay) = &self.relay {
+                        relay.add_source(name);
+                    }
+                    info!("Added source `{}` on {:?}", source.name, source.addrs);
+                    self.start(&args, source, source_idx, dropped);
+                }
+            }
+        }
+    }
+}
 
-        let mut buf = [0u8; 2048];
-        loop {
-            match socket.recv_from(&mut buf).await {
-                Ok((size, _)) => {
-                    let data = buf[..size].to_vec();
-                    if let Ok(shred) = Shred::new_from_serialized_shred(data) {
-                        let event = ProcessorEvent::ShredReceived {
-                            port_id,
-                            name: Arc::clone(&name),
-                            shred_id: shred.id(),
-                            timestamp: Instant::now(),
-                        };
-                        if let Err(e) = sender.send(event).await {
-                            error!("[{}] Failed to send event: {}", name, e);
+// The --source-label pairs given for `name`.
+fn source_labels(args: &Args, name: &str) -> BTreeMap<String, String> {
+    args.source_label
+        .iter()
+        .filter(|(source, _, _)| *source == name)
+        .map(|(_, key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+// Fails if two live sources would listen on the same port.
+fn check_listen_addrs(args: &Args) -> anyhow::Result<()> {
+    let listening: Vec<(&str, bool, SocketAddr)> = args
+        .sources
+        .iter()
+        .filter(|source| !args.pcap.iter().any(|(name, _)| *name == source.name))
+        .flat_map(|source| source.addrs.iter().map(|addr| (source.name.as_str(), source.scheme.binds_udp(), *addr)))
+        .collect();
+    for (idx, (name, udp, addr)) in listening.iter().enumerate() {
+        if let Some((other_name, _, other_addr)) =
+            listening[..idx].iter().find(|(_, other_udp, other)| other_udp == udp && addrs_overlap(*other, *addr))
+        {
+            anyhow::bail!(
+                "sources `{}` ({}) and `{}` ({}) would listen on the same port",
+                other_name,
+                other_addr,
+                name,
+                addr
+            );
+        }
+    }
+    Ok(())
+}
+
+// Signals on the returned channel whenever `path` may have changed. The directory is
+// watched rather than the file, since editors often save by replacing it.
+fn watch_file(path: &Path) -> notify::Result<(RecommendedWatcher, mpsc::Receiver<()>)> {
+    let (changed, changes) = mpsc::channel(1);
+    let file_name = path.file_name().map(ToOwned::to_owned);
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if (event.kind.is_create() || event.kind.is_modify())
+            && event.paths.iter().any(|path| path.file_name() == file_name.as_deref())
+        {
+            // One pending signal is enough.
+            changed.try_send(()).ok();
+        }
+    })?;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    Ok((watcher, changes))
+}
+
+async fn start_port_listener(
+    scheme: Scheme,
+    addr: SocketAddr,
+    config: ListenerConfig,
+    forwarder: Forwarder,
+) -> anyhow::Result<()> {
+    match scheme {
+        Scheme::Udp => Udp.listen(addr, config, forwarder).await,
+        Scheme::Tcp => Tcp.listen(addr, config, forwarder).await,
+        #[cfg(feature = "quic")]
+        Scheme::Quic => {
+            let quic = config.quic.clone().context("quic:// sources need --quic-cert")?;
+            quic.listen(addr, config, forwarder).await
+        }
+        #[cfg(not(feature = "quic"))]
+        Scheme::Quic => anyhow::bail!("quic:// sources require building with `--features quic`"),
+    }
+}
+
+/// Datagrams, one shred each, on --rx-sockets sockets sharing the port.
+struct Udp;
+
+impl Transport for Udp {
+    async fn listen(self, addr: SocketAddr, config: ListenerConfig, forwarder: Forwarder) -> anyhow::Result<()> {
+        let name = Arc::clone(&forwarder.name);
+        let mut receivers = JoinSet::new();
+        let mut granted_rcvbuf = 0;
+        for _ in 0..config.rx_sockets {
+            let (socket, rcvbuf) = bind_with_retry(&name, addr, &config, || bind_socket(addr, &config)).await?;
+            granted_rcvbuf = rcvbuf;
+            #[cfg(target_os = "linux")]
+            {
+                let kernel_timestamps = config.kernel_timestamps
+                    && match mmsg::enable_timestamps(&socket) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            warn!(target: "shred_perf::socket", "[{}] Kernel timestamps unavailable, using userspace time: {}", name, e);
+                            false
                         }
-                    }
+                    };
+                if config.batch_recv || kernel_timestamps {
+                    let capacity = if config.batch_recv { mmsg::BATCH_SIZE } else { 1 };
+                    receivers.spawn(receive_loop_batched(socket, config.max_packet_size, capacity, forwarder.clone()));
+                    continue;
                 }
-                Err(e) => error!("[{}] Receive error: {}", name, e),
             }
+            receivers.spawn(receive_loop(socket, config.max_packet_size, forwarder.clone()));
         }
-    })
+        info!(
+            target: "shred_perf::socket",
+            "[{}] Listening on {} ({} socket(s), rcvbuf {} bytes, requested {})",
+            name, addr, config.rx_sockets, granted_rcvbuf, config.rcvbuf_bytes
+        );
+
+        receivers.join_next().await;
+        Ok(())
+    }
 }
 
-fn process_shred(state: &mut ProcessorState, port_id: u8, name: Arc<str>, shred_id: ShredId, timestamp: Instant) {
-    match port_id {
-        0 => {
-            if state.port0_data.contains_key(&shred_id) {
-                return;
+// Runs all of a source's listeners on one thread with a current-thread runtime of its own,
+// optionally pinned to `cpu`. Resolves when a listener stops, like `start_port_listener`.
+async fn start_dedicated_listener(
+    scheme: Scheme,
+    addrs: Vec<SocketAddr>,
+    cpu: Option<usize>,
+    config: ListenerConfig,
+    forwarder: Forwarder,
+) -> anyhow::Result<()> {
+    let name = Arc::clone(&forwarder.name);
+    let (done_tx, done_rx) = oneshot::channel();
+    // Dropped, stopping the thread's listeners, if this task is aborted.
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    std::thread::Builder::new()
+        .name(format!("recv-{}", name))
+        .spawn(move || {
+            if let Some(cpu) = cpu {
+                if core_affinity::set_for_current(core_affinity::CoreId { id: cpu }) {
+                    info!(target: "shred_perf::socket", "[{}] Receiving on a thread pinned to CPU {}", forwarder.name, cpu);
+                } else {
+                    warn!(target: "shred_perf::socket", "[{}] Failed to pin receive thread to CPU {}", forwarder.name, cpu);
+                }
+            }
+            let result = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .context("failed to build receive runtime")
+                .and_then(|runtime| {
+                    runtime.block_on(async {
+                        let mut listeners = JoinSet::new();
+                        for addr in addrs {
+                            listeners.spawn(start_port_listener(scheme, addr, config.clone(), forwarder.clone()));
+                        }
+                        tokio::select! {
+                            result = listeners.join_next() => result.map_or(Ok(()), |result| result?),
+                            _ = stop_rx => Ok(()),
+                        }
+                    })
+                });
+            done_tx.send(result).ok();
+        })
+        .with_context(|| format!("[{}] failed to start receive thread", name))?;
+    let _stop = stop_tx;
+    done_rx.await.with_context(|| format!("[{}] receive thread panicked", name))?
+}
+
+async fn bind_with_retry<T>(
+    name: &str,
+    addr: SocketAddr,
+    config: &ListenerConfig,
+    bind: impl Fn() -> std::io::Result<T>,
+) -> anyhow::Result<T> {
+    let mut delay = config.bind_retry_delay;
+    let mut attempt = 1;
+    loop {
+        match bind() {
+            Ok(bound) => return Ok(bound),
+            Err(e) if attempt <= config.bind_retries => {
+                warn!(
+                    target: "shred_perf::socket",
+                    "[{}] Failed to bind {} (attempt {}/{}): {}; retrying in {:?}",
+                    name,
+                    addr,
+                    attempt,
+                    config.bind_retries + 1,
+                    e,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
             }
-            state.port0_data.insert(shred_id.clone(), timestamp);
-            if let Some(other_time) = state.port1_data.get(&shred_id) {
-                let delay = timestamp.duration_since(*other_time);
-                state.matched_pairs += 1;
-                state.delays.push(delay);
-                info!("{}: Shred {:?} delay: {:?}", name, shred_id, delay);
+            Err(e) => {
+                return Err(e).with_context(|| format!("[{}] failed to bind {} after {} attempt(s)", name, addr, attempt))
             }
         }
-        1 => {
-            if state.port1_data.contains_key(&shred_id) {
-                return;
+    }
+}
+
+// Returns the socket together with the receive buffer size the kernel actually granted.
+fn bind_socket(addr: SocketAddr, config: &ListenerConfig) -> std::io::Result<(UdpSocket, usize)> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if config.rx_sockets > 1 {
+        socket.set_reuse_port(true)?;
+    }
+    socket.set_recv_buffer_size(config.rcvbuf_bytes)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    let rcvbuf = socket.recv_buffer_size()?;
+    Ok((UdpSocket::from_std(socket.into())?, rcvbuf))
+}
+
+async fn receive_loop(socket: UdpSocket, packet_size: usize, forwarder: Forwarder) {
+    let mut buf = vec![0u8; packet_size];
+    loop {
+        match socket.recv_from(&mut buf).await {
+            Ok((size, _)) => {
+                let timestamp = Instant::now();
+                forwarder.forward_received(&buf[..size], packet_size, timestamp);
+            }
+            Err(e) => error!(target: "shred_perf::socket", "[{}] Receive error: {}", forwarder.name, e),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn receive_loop_batched(socket: UdpSocket, packet_size: usize, capacity: usize, forwarder: Forwarder) {
+    let mut batch = mmsg::RecvBatch::new(packet_size, capacity);
+    loop {
+        match mmsg::recv_batch(&socket, &mut batch).await {
+            Ok(count) => {
+                // Without a kernel timestamp, the whole batch shares the time recvmmsg returned.
+                let now = Instant::now();
+                let wall_now = SystemTime::now();
+                for (data, received) in batch.packets(count) {
+                    let timestamp = received.map_or(now, |ts| mmsg::to_instant(ts, now, wall_now));
+                    forwarder.forward_received(data, packet_size, timestamp);
+                }
             }
-            state.port1_data.insert(shred_id.clone(), timestamp);
-            if let Some(other_time) = state.port0_data.get(&shred_id) {
-                let delay = timestamp.duration_since(*other_time);
-                state.matched_pairs += 1;
-                state.delays.push(delay);
-                info!("{}: Shred {:?} delay: {:?}", name, shred_id, delay);
+            Err(e) => error!(target: "shred_perf::socket", "[{}] Receive error: {}", forwarder.name, e),
+        }
+    }
+}
+
+impl Forwarder {
+    // Never waits on the processor: stalling here would only move the loss into the kernel
+    // socket buffer, where it can't be seen.
+    fn forward(&self, data: &[u8], timestamp: Instant) {
+        let event = self.parse(data, timestamp);
+        if let (Some(relay), ProcessorEvent::ShredReceived { shred_id, .. }) = (&self.relay, &event) {
+            // Downstream gets the bare shred, not this source's framing.
+            let shred = self.preprocess.apply(data).unwrap_or(data);
+            relay.relay(self.source_idx, *shred_id, shred, timestamp);
+        }
+        self.send(event, timestamp);
+    }
+
+    // A datagram that exactly fills the receive buffer may have been cut short, so it is
+    // counted apart rather than parsed.
+    fn forward_received(&self, data: &[u8], buf_size: usize, timestamp: Instant) {
+        if data.len() == buf_size {
+            let (source_idx, bytes) = (self.source_idx, data.len());
+            self.send(ProcessorEvent::Truncated { source_idx, bytes }, timestamp);
+        } else {
+            self.forward(data, timestamp);
+        }
+    }
+
+    fn send(&self, event: ProcessorEvent, timestamp: Instant) {
+        let since_epoch = timestamp.saturating_duration_since(self.epoch).as_nanos() as u64;
+        self.last_seen.fetch_max(since_epoch, Ordering::Relaxed);
+        match self.sender.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => error!(target: "shred_perf::socket", "[{}] Failed to send event: {}", self.name, e),
+        }
+    }
+
+    fn parse(&self, data: &[u8], timestamp: Instant) -> ProcessorEvent {
+        let (source_idx, bytes) = (self.source_idx, data.len());
+        let Some(data) = self.preprocess.apply(data) else {
+            return ProcessorEvent::ParseFailed { source_idx, bytes };
+        };
+        // Matching only needs header fields, read straight from the receive buffer; only a
+        // signature check needs the shred copied out and fully parsed.
+        let Some(leaders) = &self.leaders else {
+            return match (layout::get_shred_id(data), fec_set_index(data)) {
+                (Some(shred_id), Some(fec_set_index)) => ProcessorEvent::ShredReceived {
+                    source_idx,
+                    shred_id,
+                    fec_set_index,
+                    timestamp,
+                    bytes,
+                },
+                _ => ProcessorEvent::ParseFailed { source_idx, bytes },
+            };
+        };
+        let shred = match Shred::new_from_serialized_shred(data.to_vec()) {
+            Ok(shred) => shred,
+            Err(_) => return ProcessorEvent::ParseFailed { source_idx, bytes },
+        };
+        match leaders.leader(shred.slot()) {
+            Some(leader) if shred.verify(leader) => {}
+            leader => {
+                return ProcessorEvent::SignatureRejected {
+                    source_idx,
+                    bytes,
+                    leader_known: leader.is_some(),
+                }
             }
         }
-        _ => unreachable!(),
+        ProcessorEvent::ShredReceived {
+            source_idx,
+            shred_id: shred.id(),
+            fec_set_index: shred.fec_set_index(),
+            timestamp,
+            bytes,
+        }
     }
 }
 
-fn cleanup_data(state: &mut ProcessorState, timeout: Duration) {
-    let now = Instant::now();
-    state.port0_data.retain(|_, t| now.duration_since(*t) < timeout);
-    state.port1_data.retain(|_, t| now.duration_since(*t) < timeout);
-    info!("Cleanup completed");
+// The common shred header ends with the FEC set index, which `layout` has no getter for.
+fn fec_set_index(shred: &[u8]) -> Option<u32> {
+    let header = layout::get_common_header_bytes(shred)?;
+    Some(u32::from_le_bytes(header.get(header.len().checked_sub(4)?..)?.try_into().ok()?))
 }
 
-fn report_stats(state: &ProcessorState, args: &Args) {
-    let avg_delay = if !state.delays.is_empty() {
-        state.delays.iter().sum::<Duration>() / state.delays.len() as u32
-    } else {
-        Duration::ZERO
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_ledger::shred::ShredFlags;
 
-    info!(
-        "Stats: Port {}: {} | Port {}: {} | Matched: {} | Avg delay: {:?}",
-        args.name_0,
-        state.port0_data.len(),
-        args.name_1,
-        state.port1_data.len(),
-        state.matched_pairs,
-        avg_delay
-    );
+    #[test]
+    fn replay_cleanup_follows_the_replay_furthest_behind() {
+        let anchor = Instant::now();
+        let replay_clock = ReplayClock::new(WallClock::anchored(anchor, 1_000), Duration::from_secs(1), Duration::from_secs(1));
+        replay_clock.start(0);
+        replay_clock.start(1);
+        assert_eq!(replay_clock.advance(0, 5_000), None);
+        assert_eq!(replay_clock.advance(1, 3_000), Some(anchor + Duration::from_nanos(2_000)));
+        assert_eq!(replay_clock.advance(1, 9_000), Some(anchor + Duration::from_nanos(4_000)));
+        replay_clock.finish(0);
+        assert_eq!(replay_clock.advance(1, 10_000), Some(anchor + Duration::from_nanos(9_000)));
+    }
+
+    #[test]
+    fn parses_sources_with_and_without_an_address() {
+        let source = parse_source("uk:20001").unwrap();
+        assert_eq!(source.name, "uk");
+        assert_eq!(source.addrs, [SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 20001)]);
+
+        let source = parse_source("de:10.0.0.5:20002").unwrap();
+        assert_eq!(source.addrs, ["10.0.0.5:20002".parse().unwrap()]);
+
+        let source = parse_source("v6:[::1]:20003").unwrap();
+        assert_eq!(source.addrs, [SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 20003)]);
+
+        let source = parse_source("relay:tcp://10.0.0.5:9000").unwrap();
+        assert_eq!((source.scheme, source.addrs[0]), (Scheme::Tcp, "10.0.0.5:9000".parse().unwrap()));
+        assert_eq!(parse_source("relay:quic://9001").unwrap().scheme, Scheme::Quic);
+        assert_eq!(parse_source("uk:udp://20001").unwrap().scheme, Scheme::Udp);
+        assert!(parse_source("relay:sctp://9000").is_err());
+
+        assert!(parse_source(":20001").is_err());
+        assert!(parse_source("uk").is_err());
+        assert!(parse_source("v6:::1:20003").is_err());
+    }
+
+    #[test]
+    fn parses_source_labels() {
+        let label = ("uk".to_string(), "region".to_string(), "eu-west".to_string());
+        assert_eq!(parse_label("uk:region=eu-west").unwrap(), label);
+        assert!(parse_label("uk:region").is_err());
+        assert!(parse_label("uk:source=x").is_err());
+        assert!(parse_label("uk:1dc=x").is_err());
+    }
+
+    #[test]
+    fn parses_pinned_cores() {
+        assert_eq!(parse_pin("jito=3").unwrap(), ("jito".to_string(), 3));
+        assert!(parse_pin("jito=").is_err());
+        assert!(parse_pin("=3").is_err());
+    }
+
+    #[test]
+    fn unwraps_prefixed_and_tunnelled_datagrams() {
+        let shred = b"shred bytes";
+        let mut udp = vec![0x1f, 0x40, 0x1f, 0x41, 0, 8 + shred.len() as u8, 0, 0];
+        udp.extend_from_slice(shred);
+        let mut ip = vec![0x45, 0, 0, 20 + udp.len() as u8, 0, 0, 0, 0, 64, 17];
+        ip.resize(20, 0);
+        ip.extend_from_slice(&udp);
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&ip);
+        let mut datagram = vec![0xaa; 4];
+        datagram.extend_from_slice(&[0x08, 0, 0, 0, 0, 0, 1, 0]);
+        datagram.extend_from_slice(&frame);
+
+        let vxlan = Preprocess { strip_prefix: 4, encapsulation: Some(Encapsulation::Vxlan) };
+        assert_eq!(vxlan.apply(&datagram), Some(&shred[..]));
+        let prefix = Preprocess { strip_prefix: 12, encapsulation: None };
+        assert_eq!(prefix.apply(&datagram), Some(&frame[..]));
+        // Without the I flag this isn't VXLAN.
+        datagram[4] = 0;
+        assert_eq!(vxlan.apply(&datagram), None);
+        assert_eq!(prefix.apply(&datagram[..8]), None);
+
+        assert_eq!(parse_decapsulate("mirror=geneve").unwrap(), ("mirror".to_string(), Encapsulation::Geneve));
+        assert!(parse_decapsulate("mirror=gre").is_err());
+        assert_eq!(parse_strip_prefix("mirror=16").unwrap(), ("mirror".to_string(), 16));
+    }
+
+    #[test]
+    fn parses_a_port_list_into_one_source() {
+        let source = parse_source("feed:10.0.0.5:8001,8002,8003").unwrap();
+        assert_eq!(source.ports(), [8001, 8002, 8003]);
+        assert!(source.addrs.iter().all(|addr| addr.ip() == "10.0.0.5".parse::<IpAddr>().unwrap()));
+
+        assert!(parse_source("feed:8001,").is_err());
+    }
+
+    #[test]
+    fn wildcard_addresses_overlap_specific_ones() {
+        let any: SocketAddr = "0.0.0.0:8001".parse().unwrap();
+        assert!(addrs_overlap(any, "10.0.0.5:8001".parse().unwrap()));
+        assert!(!addrs_overlap(any, "10.0.0.5:8002".parse().unwrap()));
+        assert!(!addrs_overlap("10.0.0.5:8001".parse().unwrap(), "10.0.0.6:8001".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn receives_on_ipv6_loopback() {
+        let config = ListenerConfig {
+            rx_sockets: 1,
+            rcvbuf_bytes: 1 << 20,
+            max_packet_size: 2048,
+            bind_retries: 0,
+            bind_retry_delay: Duration::ZERO,
+            #[cfg(target_os = "linux")]
+            batch_recv: false,
+            #[cfg(target_os = "linux")]
+            kernel_timestamps: false,
+            #[cfg(feature = "quic")]
+            quic: None,
+        };
+        let (socket, _) = bind_socket("[::1]:0".parse().unwrap(), &config).unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let sender = std::net::UdpSocket::bind("[::1]:0").unwrap();
+        sender.send_to(b"shred", addr).unwrap();
+        let mut buf = [0u8; 2048];
+        let (size, from) = time::timeout(Duration::from_secs(5), socket.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..size], b"shred");
+        assert_eq!(from, sender.local_addr().unwrap());
+    }
+
+    fn test_forwarder(sender: mpsc::Sender<ProcessorEvent>) -> Forwarder {
+        Forwarder {
+            source_idx: 1,
+            name: "relay".into(),
+            sender,
+            dropped: Arc::default(),
+            last_seen: Arc::default(),
+            epoch: Instant::now(),
+            leaders: None,
+            relay: None,
+            preprocess: Preprocess::default(),
+        }
+    }
+
+    #[test]
+    fn reads_the_shred_header_without_copying() {
+        let shred = Shred::new_from_data(7, 3, 1, &[], ShredFlags::empty(), 0, 0, 2);
+        let (sender, _events) = mpsc::channel(1);
+        match test_forwarder(sender).parse(&shred.payload()[..], Instant::now()) {
+            ProcessorEvent::ShredReceived { shred_id, fec_set_index, .. } => {
+                assert_eq!((shred_id, fec_set_index), (shred.id(), shred.fec_set_index()));
+            }
+            _ => panic!("expected the shred to parse"),
+        }
+    }
+
+    #[tokio::test]
+    async fn splits_a_stream_into_length_prefixed_shreds() {
+        let (sender, mut events) = mpsc::channel(8);
+        let forwarder = test_forwarder(sender);
+        let stream: &[u8] = &[0, 3, b'a', b'b', b'c', 0, 0, 0, 2, b'd', b'e'];
+        transport::read_frames(stream, &forwarder).await.unwrap();
+        drop(forwarder);
+
+        let mut sizes = Vec::new();
+        while let Some(event) = events.recv().await {
+            match event {
+                ProcessorEvent::ParseFailed { source_idx: 1, bytes } => sizes.push(bytes),
+                _ => panic!("expected only unparseable frames"),
+            }
+        }
+        assert_eq!(sizes, [3, 0, 2]);
+
+        // A stream cut off inside a frame is an error, not a clean close.
+        let (sender, _events) = mpsc::channel(8);
+        assert!(transport::read_frames(&[0, 5, b'a'][..], &test_forwarder(sender)).await.is_err());
+    }
 }
@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use solana_shred_perf::export::{BinaryLogReader, BINARY_LOG_MAGIC};
+use solana_shred_perf::stats::{format_delay, mean_delay, pct, percentile, DelayUnit};
+
+/// The `merge` subcommand's arguments.
+#[derive(clap::Args, Debug)]
+pub struct MergeArgs {
+    /// Exported files to combine; CSV and binary logs can be mixed.
+    #[clap(required = true, value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+}
+
+/// Every matched pair's delay across the merged files, by candidate source.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Matched pairs read from each file, in the order given.
+    pub runs: Vec<(PathBuf, u64)>,
+    pub delays: BTreeMap<String, Vec<i64>>,
+}
+
+impl Summary {
+    pub fn load(files: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut summary = Self::default();
+        for path in files {
+            let before = summary.matched_pairs();
+            summary.read(path).with_context(|| format!("failed to read {}", path.display()))?;
+            summary.runs.push((path.clone(), summary.matched_pairs() - before));
+        }
+        Ok(summary)
+    }
+
+    pub fn matched_pairs(&self) -> u64 {
+        self.delays.values().map(|delays| delays.len() as u64).sum()
+    }
+
+    // Tells the two formats apart by the binary log's magic.
+    fn read(&mut self, path: &Path) -> anyhow::Result<()> {
+        let mut input = BufReader::new(File::open(path)?);
+        if input.fill_buf()?.starts_with(&BINARY_LOG_MAGIC) {
+            self.read_binary_log(input)
+        } else {
+            self.read_csv(input)
+        }
+    }
+
+    fn read_binary_log(&mut self, input: impl Read) -> anyhow::Result<()> {
+        let reader = BinaryLogReader::new(input)?;
+        let sources = reader.sources().to_vec();
+        for record in reader {
+            let record = record?;
+            let source = sources.get(record.source).map_or("?", String::as_str);
+            self.delays.entry(source.to_string()).or_default().push(record.delay_nanos);
+        }
+        Ok(())
+    }
+
+    fn read_csv(&mut self, input: impl BufRead) -> anyhow::Result<()> {
+        let mut lines = input.lines();
+        let header = lines.next().context("empty file")??;
+        let columns: Vec<&str> = header.split(',').collect();
+        let delay_col = columns
+            .iter()
+            .position(|column| column.starts_with("delay_"))
+            .context("not a --csv-out file: no delay column")?;
+        let source_col = columns.iter().position(|column| *column == "source").context("no source column")?;
+        // Each row's delay in nanoseconds, whatever --delay-unit it was written with.
+        let scale = match columns[delay_col] {
+            "delay_nanos" => 1.0,
+            "delay_us" => 1e3,
+            "delay_ms" => 1e6,
+            other => anyhow::bail!("unknown delay column `{}`", other),
+        };
+        for (row, line) in lines.enumerate() {
+            let line = line?;
+            let fields: Vec<&str> = line.split(',').collect();
+            let (Some(delay), Some(source)) = (fields.get(delay_col), fields.get(source_col)) else {
+                anyhow::bail!("line {}: expected {} columns", row + 2, columns.len());
+            };
+            let delay: f64 = delay.parse().with_context(|| format!("line {}: bad delay `{}`", row + 2, delay))?;
+            self.delays.entry(source.to_string()).or_default().push((delay * scale).round() as i64);
+        }
+        Ok(())
+    }
+
+    pub fn print(&mut self, unit: DelayUnit) {
+        println!("Merged {} matched pairs from {} file(s)", self.matched_pairs(), self.runs.len());
+        for (path, matched) in &self.runs {
+            println!("  {}: {}", path.display(), matched);
+        }
+        let mut all: Vec<i64> = self.delays.values().flatten().copied().collect();
+        all.sort_unstable();
+        println!("Delay vs reference: {}", describe(&all, unit));
+        println!("Per source (wins are matches won against the reference; ties go to the reference):");
+        for (source, delays) in &mut self.delays {
+            delays.sort_unstable();
+            let wins = delays.partition_point(|delay| *delay < 0) as u64;
+            println!(
+                "  {}: {} matches, {} wins ({:.1}%), {}",
+                source,
+                delays.len(),
+                wins,
+                pct(wins, delays.len() as u64),
+                describe(delays, unit)
+            );
+        }
+    }
+}
+
+// Mean, percentiles and extremes of sorted delays.
+fn describe(sorted: &[i64], unit: DelayUnit) -> String {
+    let (Some(min), Some(max)) = (sorted.first(), sorted.last()) else {
+        return "no matches".to_string();
+    };
+    format!(
+        "mean {}, p50 {}, p90 {}, p99 {}, min {}, max {}",
+        format_delay(mean_delay(sorted.iter()), unit),
+        format_delay(percentile(sorted, 50.0), unit),
+        format_delay(percentile(sorted, 90.0), unit),
+        format_delay(percentile(sorted, 99.0), unit),
+        format_delay(*min, unit),
+        format_delay(*max, unit)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use solana_ledger::shred::{ShredId, ShredType};
+    use solana_shred_perf::export::{BinaryLogWriter, MatchRecord};
+    use super::*;
+
+    #[test]
+    fn merges_csv_and_binary_logs_by_source() {
+        let path = |ext: &str| std::env::temp_dir().join(format!("shred-perf-merge-{}.{}", std::process::id(), ext));
+        let (csv, bin) = (path("csv"), path("bin"));
+        std::fs::write(&csv, "slot,index,shred_type,port0_ts_nanos,port1_ts_nanos,delay_us,source\n1,0,data,0,0,-1.5,b\n1,1,data,0,0,2.000,c\n").unwrap();
+        let sources: Vec<Arc<str>> = vec!["a".into(), "b".into()];
+        let mut writer = BinaryLogWriter::create(&bin, &sources).unwrap();
+        for delay_nanos in [500, 0] {
+            writer
+                .write_match(&MatchRecord {
+                    shred_id: ShredId::new(2, 0, ShredType::Code),
+                    source: Arc::clone(&sources[1]),
+                    reference: Arc::clone(&sources[0]),
+                    reference_ts_nanos: 0,
+                    candidate_ts_nanos: 0,
+                    delay_nanos,
+                })
+                .unwrap();
+        }
+        writer.flush().unwrap();
+
+        let summary = Summary::load(&[csv.clone(), bin.clone()]).unwrap();
+        std::fs::remove_file(&csv).unwrap();
+        std::fs::remove_file(&bin).unwrap();
+        assert_eq!(summary.runs.iter().map(|(_, matched)| *matched).collect::<Vec<_>>(), [2, 2]);
+        assert_eq!(summary.delays["b"], [-1500, 500, 0]);
+        assert_eq!(summary.delays["c"], [2000]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use tracing::info;
+use prometheus::{exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts, Registry, TextEncoder};
+use tokio::net::TcpListener;
+
+pub struct Metrics {
+    registry: Registry,
+    matched_pairs: IntCounter,
+    // Each source's `shred_received_total` counter with its labels, resolved once.
+    received: RwLock<HashMap<String, IntCounter>>,
+    received_vec: IntCounterVec,
+    // Fixed by the sources passed to `new`, since Prometheus can't add label names later.
+    label_keys: Vec<String>,
+    match_delay: Histogram,
+}
+
+impl Metrics {
+    /// `sources` pairs each source's name with its --source-label pairs. Every label key
+    /// any source uses is added to the per-source metrics, empty for sources without it.
+    pub fn new(sources: &[(Arc<str>, BTreeMap<String, String>)]) -> prometheus::Result<Self> {
+        let registry = Registry::new();
+
+        let label_keys: Vec<String> =
+            sources.iter().flat_map(|(_, labels)| labels.keys().cloned()).collect::<BTreeSet<_>>().into_iter().collect();
+        let label_names: Vec<&str> = ["source"].into_iter().chain(label_keys.iter().map(String::as_str)).collect();
+        let matched_pairs = IntCounter::new("shred_matched_pairs_total", "Shreds matched across sources")?;
+        let received_vec = IntCounterVec::new(
+            Opts::new("shred_received_total", "Shreds received per source"),
+            &label_names,
+        )?;
+        let received: HashMap<String, IntCounter> = sources
+            .iter()
+            .map(|(name, labels)| Ok((name.to_string(), received_counter(&received_vec, &label_keys, name, labels)?)))
+            .collect::<prometheus::Result<_>>()?;
+        // 10µs .. ~1.3s
+        let match_delay = Histogram::with_opts(
+            HistogramOpts::new("shred_match_delay_seconds", "Delay between sources for matched shreds")
+                .buckets(exponential_buckets(0.00001, 2.0, 18)?),
+        )?;
+
+        registry.register(Box::new(matched_pairs.clone()))?;
+        registry.register(Box::new(received_vec.clone()))?;
+        registry.register(Box::new(match_delay.clone()))?;
+
+        Ok(Self {
+            registry,
+            matched_pairs,
+            received: RwLock::new(received),
+            received_vec,
+            label_keys,
+            match_delay,
+        })
+    }
+
+    /// Counts a source added while running. Label keys no source passed to `new` used are
+    /// dropped.
+    pub fn add_source(&self, name: &str, labels: &BTreeMap<String, String>) -> prometheus::Result<()> {
+        let received = received_counter(&self.received_vec, &self.label_keys, name, labels)?;
+        self.received.write().unwrap().insert(name.to_string(), received);
+        Ok(())
+    }
+
+    pub fn record_received(&self, source: &str) {
+        // Sources neither passed to `new` nor added aren't counted.
+        if let Some(received) = self.received.read().unwrap().get(source) {
+            received.inc();
+        }
+    }
+
+    /// `delay` is `None` for matches that should be counted but not timed, e.g. during warmup.
+    pub fn record_match(&self, delay: Option<Duration>) {
+        self.matched_pairs.inc();
+        if let Some(delay) = delay {
+            self.match_delay.observe(delay.as_secs_f64());
+        }
+    }
+
+    fn render(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        // Encoding into a Vec only fails on malformed metric families, which we never build.
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf).ok();
+        buf
+    }
+}
+
+// `source`'s counter, with an empty value for each label key it doesn't have.
+fn received_counter(
+    received_vec: &IntCounterVec,
+    label_keys: &[String],
+    source: &str,
+    labels: &BTreeMap<String, String>,
+) -> prometheus::Result<IntCounter> {
+    let values: Vec<&str> = [source]
+        .into_iter()
+        .chain(label_keys.iter().map(|key| labels.get(key).map_or("", String::as_str)))
+        .collect();
+    received_vec.get_metric_with_label_values(&values)
+}
+
+pub async fn serve(metrics: Arc<Metrics>, port: u16) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(metrics);
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("Serving metrics on port {}", port);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn metrics_handler(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, TextEncoder::new().format_type().to_string())], metrics.render())
+}
@@ -0,0 +1,148 @@
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, RawFd};
+use std::ptr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+
+pub const BATCH_SIZE: usize = 32;
+
+// Room for one SCM_TIMESTAMPNS message, u64s for cmsghdr alignment.
+type ControlBuf = [u64; 8];
+
+pub struct RecvBatch {
+    bufs: Vec<Vec<u8>>,
+    lens: Vec<usize>,
+    control: Vec<ControlBuf>,
+    timestamps: Vec<Option<SystemTime>>,
+    // The syscall's arguments, allocated once and pointed at the buffers above before
+    // every call.
+    iovecs: Vec<libc::iovec>,
+    hdrs: Vec<libc::mmsghdr>,
+}
+
+// SAFETY: the raw pointers in `iovecs` and `hdrs` only ever point into the batch's own
+// buffers, and are only dereferenced by `recvmmsg` while the batch is mutably borrowed.
+unsafe impl Send for RecvBatch {}
+
+impl RecvBatch {
+    pub fn new(packet_size: usize, capacity: usize) -> Self {
+        Self {
+            bufs: vec![vec![0u8; packet_size]; capacity],
+            lens: vec![0; capacity],
+            control: vec![[0; 8]; capacity],
+            timestamps: vec![None; capacity],
+            iovecs: vec![
+                libc::iovec {
+                    iov_base: ptr::null_mut(),
+                    iov_len: 0,
+                };
+                capacity
+            ],
+            // SAFETY: mmsghdr is a plain C struct for which all-zeroes is a valid (empty) value.
+            hdrs: (0..capacity).map(|_| unsafe { mem::zeroed() }).collect(),
+        }
+    }
+
+    /// The first `count` datagrams with their kernel receive time, if one was attached.
+    pub fn packets(&self, count: usize) -> impl Iterator<Item = (&[u8], Option<SystemTime>)> {
+        self.bufs
+            .iter()
+            .zip(&self.lens)
+            .zip(&self.timestamps)
+            .take(count)
+            .map(|((buf, len), ts)| (&buf[..*len], *ts))
+    }
+}
+
+/// Asks the kernel to attach a nanosecond receive timestamp to every datagram.
+pub fn enable_timestamps(socket: &UdpSocket) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    // SAFETY: passes a pointer to a live c_int together with its exact size.
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPNS,
+            (&enable as *const libc::c_int).cast(),
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Converts a kernel (CLOCK_REALTIME) receive time into the `Instant` timeline by
+/// measuring how long ago it was. Falls back to `now` if the clock looks off.
+pub fn to_instant(received: SystemTime, now: Instant, wall_now: SystemTime) -> Instant {
+    wall_now
+        .duration_since(received)
+        .ok()
+        .and_then(|age| now.checked_sub(age))
+        .unwrap_or(now)
+}
+
+/// Waits for the socket to become readable, then pulls up to the batch capacity of
+/// datagrams with a single `recvmmsg` call. Returns how many were received.
+pub async fn recv_batch(socket: &UdpSocket, batch: &mut RecvBatch) -> io::Result<usize> {
+    loop {
+        socket.readable().await?;
+        match socket.try_io(Interest::READABLE, || recvmmsg(socket.as_raw_fd(), batch)) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            result => return result,
+        }
+    }
+}
+
+fn recvmmsg(fd: RawFd, batch: &mut RecvBatch) -> io::Result<usize> {
+    let capacity = batch.bufs.len();
+    // The kernel overwrites `msg_controllen`, and the batch may have moved since the last
+    // call, so every pointer and length is set afresh.
+    for (((iov, hdr), buf), control) in batch
+        .iovecs
+        .iter_mut()
+        .zip(batch.hdrs.iter_mut())
+        .zip(batch.bufs.iter_mut())
+        .zip(batch.control.iter_mut())
+    {
+        iov.iov_base = buf.as_mut_ptr().cast();
+        iov.iov_len = buf.len();
+        hdr.msg_hdr.msg_iov = iov;
+        hdr.msg_hdr.msg_iovlen = 1;
+        hdr.msg_hdr.msg_control = control.as_mut_ptr().cast();
+        hdr.msg_hdr.msg_controllen = mem::size_of::<ControlBuf>() as _;
+    }
+
+    // SAFETY: every header points at one live iovec and control buffer, which in turn
+    // point at memory owned by `batch`; all of them outlive the call.
+    let received = unsafe { libc::recvmmsg(fd, batch.hdrs.as_mut_ptr(), capacity as _, 0, ptr::null_mut()) };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let received = received as usize;
+    for ((len, ts), hdr) in batch.lens.iter_mut().zip(batch.timestamps.iter_mut()).zip(&batch.hdrs).take(received) {
+        *len = hdr.msg_len as usize;
+        *ts = read_timestamp(&hdr.msg_hdr);
+    }
+    Ok(received)
+}
+
+fn read_timestamp(msg: &libc::msghdr) -> Option<SystemTime> {
+    // SAFETY: walks the control messages the kernel just wrote into `msg`'s buffer,
+    // using the libc helpers that bound every step by `msg_controllen`.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPNS {
+                let ts: libc::timespec = ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+                return Some(UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32));
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+    None
+}
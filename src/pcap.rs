@@ -0,0 +1,159 @@
+use std::fs::File;
+use std::path::Path;
+use anyhow::Context;
+use pcap_parser::pcapng::Block;
+use pcap_parser::{create_reader, Linktype, PcapBlockOwned, PcapError};
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_UDP: u8 = 17;
+
+struct Interface {
+    linktype: Linktype,
+    ts_offset_secs: i64,
+    ts_units_per_sec: u64,
+}
+
+/// Calls `f` with the capture time (unix nanoseconds) and payload of every UDP datagram
+/// sent to one of `ports`, in file order. Reads both legacy pcap and pcapng. Stops early
+/// once `f` returns false.
+pub fn for_each_udp(path: &Path, ports: &[u16], mut f: impl FnMut(u64, &[u8]) -> bool) -> anyhow::Result<()> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut reader = create_reader(1 << 20, file)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    // Legacy files have a single link type; pcapng declares one per interface.
+    let mut legacy: Option<(Linktype, bool)> = None;
+    let mut interfaces: Vec<Interface> = Vec::new();
+
+    loop {
+        match reader.next() {
+            Ok((offset, block)) => {
+                let packet = match block {
+                    PcapBlockOwned::LegacyHeader(header) => {
+                        legacy = Some((header.network, header.is_nanosecond_precision()));
+                        None
+                    }
+                    PcapBlockOwned::Legacy(block) => legacy.map(|(linktype, nanos)| {
+                        let frac = if nanos { block.ts_usec as u64 } else { block.ts_usec as u64 * 1000 };
+                        (linktype, block.ts_sec as u64 * 1_000_000_000 + frac, block.data)
+                    }),
+                    PcapBlockOwned::NG(Block::SectionHeader(_)) => {
+                        interfaces.clear();
+                        None
+                    }
+                    PcapBlockOwned::NG(Block::InterfaceDescription(idb)) => {
+                        interfaces.push(Interface {
+                            linktype: idb.linktype,
+                            ts_offset_secs: idb.ts_offset(),
+                            ts_units_per_sec: idb.ts_resolution().unwrap_or(1_000_000),
+                        });
+                        None
+                    }
+                    PcapBlockOwned::NG(Block::EnhancedPacket(epb)) => interfaces.get(epb.if_id as usize).map(|iface| {
+                        let ticks = (epb.ts_high as u64) << 32 | epb.ts_low as u64;
+                        let nanos = (ticks as u128 * 1_000_000_000 / iface.ts_units_per_sec as u128) as i128
+                            + iface.ts_offset_secs as i128 * 1_000_000_000;
+                        let caplen = (epb.caplen as usize).min(epb.data.len());
+                        (iface.linktype, nanos.max(0) as u64, &epb.data[..caplen])
+                    }),
+                    _ => None,
+                };
+                let keep_going = match packet.and_then(|(linktype, ts, frame)| Some((ts, udp_payload(linktype, frame, ports)?))) {
+                    Some((ts, payload)) => f(ts, payload),
+                    None => true,
+                };
+                reader.consume(offset);
+                if !keep_going {
+                    return Ok(());
+                }
+            }
+            Err(PcapError::Eof) => return Ok(()),
+            Err(PcapError::Incomplete(_)) => reader
+                .refill()
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?,
+            Err(e) => anyhow::bail!("failed to parse {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Capture time of the first datagram sent to one of `ports`, if there is one.
+pub fn first_timestamp(path: &Path, ports: &[u16]) -> anyhow::Result<Option<u64>> {
+    let mut first = None;
+    for_each_udp(path, ports, |ts, _| {
+        first = Some(ts);
+        false
+    })?;
+    Ok(first)
+}
+
+fn udp_payload<'a>(linktype: Linktype, frame: &'a [u8], ports: &[u16]) -> Option<&'a [u8]> {
+    let (port, payload) = udp_datagram(linktype, frame)?;
+    ports.contains(&port).then_some(payload)
+}
+
+/// Payload of the UDP datagram in an Ethernet frame, whatever its port.
+pub fn ethernet_udp_payload(frame: &[u8]) -> Option<&[u8]> {
+    Some(udp_datagram(Linktype::ETHERNET, frame)?.1)
+}
+
+/// Payload of the UDP datagram in a bare IPv4 or IPv6 packet, whatever its port.
+pub fn ip_udp_payload(packet: &[u8]) -> Option<&[u8]> {
+    Some(udp_datagram(Linktype::RAW, packet)?.1)
+}
+
+// The destination port and payload of a frame's UDP datagram.
+fn udp_datagram(linktype: Linktype, frame: &[u8]) -> Option<(u16, &[u8])> {
+    let (ethertype, ip) = match linktype {
+        Linktype::ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = be16(frame, offset)?;
+            while ethertype == ETHERTYPE_VLAN {
+                offset += 4;
+                ethertype = be16(frame, offset)?;
+            }
+            (ethertype, frame.get(offset + 2..)?)
+        }
+        Linktype::LINUX_SLL => (be16(frame, 14)?, frame.get(16..)?),
+        Linktype::LINUX_SLL2 => (be16(frame, 0)?, frame.get(20..)?),
+        // BSD loopback: a host-order address family, which we sniff from the IP header instead.
+        Linktype::NULL => (ip_ethertype(frame.get(4..)?)?, frame.get(4..)?),
+        Linktype::RAW | Linktype::IPV4 | Linktype::IPV6 => (ip_ethertype(frame)?, frame),
+        _ => return None,
+    };
+
+    let udp = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let header_len = (*ip.first()? & 0x0f) as usize * 4;
+            let total_len = be16(ip, 2)? as usize;
+            let fragment_offset = be16(ip, 6)? & 0x1fff;
+            if *ip.get(9)? != IPPROTO_UDP || fragment_offset != 0 {
+                return None;
+            }
+            ip.get(header_len..total_len.min(ip.len()))?
+        }
+        ETHERTYPE_IPV6 => {
+            let payload_len = be16(ip, 4)? as usize;
+            if *ip.get(6)? != IPPROTO_UDP {
+                return None;
+            }
+            ip.get(40..(40 + payload_len).min(ip.len()))?
+        }
+        _ => return None,
+    };
+
+    let udp_len = be16(udp, 4)? as usize;
+    Some((be16(udp, 2)?, udp.get(8..udp_len.min(udp.len()))?))
+}
+
+fn ip_ethertype(ip: &[u8]) -> Option<u16> {
+    match ip.first()? >> 4 {
+        4 => Some(ETHERTYPE_IPV4),
+        6 => Some(ETHERTYPE_IPV6),
+        _ => None,
+    }
+}
+
+fn be16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
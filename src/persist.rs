@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+/// Cumulative counters that survive a restart. In-flight shreds are not saved.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedStats {
+    pub matched_pairs: usize,
+    pub data_matched_pairs: usize,
+    pub code_matched_pairs: usize,
+    // Keyed by source name, so sources can be reordered or added between runs.
+    pub sources: BTreeMap<String, SavedSource>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedSource {
+    pub datagrams: u64,
+    pub parse_errors: u64,
+    pub truncated: u64,
+    pub bytes_received: u64,
+    pub wins: usize,
+    pub matched: u64,
+    pub led: u64,
+    pub first_seen: u64,
+    pub seen_later: u64,
+    pub seen_later_nanos: u128,
+    pub duplicates: u64,
+    pub duplicate_delay_nanos: u128,
+    pub expired: u64,
+    pub expired_unmatched: u64,
+}
+
+/// Returns the saved stats, or `None` if the file doesn't exist yet.
+pub fn load(path: &Path) -> anyhow::Result<Option<SavedStats>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes through a temporary file so a crash mid-write never leaves a truncated file.
+pub fn save(path: &Path, stats: &SavedStats) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(stats)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
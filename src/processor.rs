@@ -0,0 +1,2691 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use anyhow::Context;
+use chrono::Utc;
+use clap::ValueEnum;
+use hdrhistogram::Histogram;
+use tracing::{debug, error, info, warn};
+use solana_ledger::shred::{ShredId, ShredType};
+use crate::alert::DelayAlert;
+use crate::bloom::RotatingBloom;
+use crate::export::{self, shred_type_name, BinaryLogWriter, CsvWriter, JsonStatsWriter, MatchRecord, NdjsonWriter, WallClock};
+use crate::gaps::{CompletedSlot, IndexLoss, LossTotals, SlotCompleteness, SlotGaps};
+use crate::metrics::Metrics;
+#[cfg(feature = "otlp")]
+use crate::otel::OtelMetrics;
+use crate::persist::{self, SavedSource, SavedStats};
+use crate::truth::ReferenceShreds;
+#[cfg(feature = "tui")]
+use crate::tui::Dashboard;
+use crate::stats::{
+    self, format_delay, pct, AgreementSnapshot, BloomSnapshot, CompletenessSnapshot, DelaySnapshot, DelayUnit, FecSlotSnapshot, HdrSnapshot, HistogramBucket, IndexBucketSnapshot, IndexLossSnapshot, InterArrivalSnapshot, LaggardSnapshot, LeadMarginsSnapshot, OverlapSnapshot, ReorderSnapshot, SlotSnapshot, SourceSnapshot, StatsSnapshot, TruthSnapshot, Welford,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum StatsFormat {
+    Human,
+    Kv,
+    Csv,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ThresholdStat {
+    Avg,
+    P50,
+    P90,
+    P99,
+}
+
+// What one tracked arrival costs in a source's map, counting hashbrown's control byte.
+const EXACT_ENTRY_BYTES: usize = std::mem::size_of::<(MatchKey, Arrival)>() + 1;
+
+// Upper edges of the age-at-eviction buckets for unmatched entries, as multiples of the
+// retention window.
+const EVICTION_AGE_EDGES: [f64; 5] = [0.5, 1.0, 1.5, 2.0, 4.0];
+
+// Delays past a minute are clamped in the HdrHistogram.
+const HDR_MAX_MICROS: u64 = 60_000_000;
+
+#[derive(Clone, Default)]
+struct SourceStats {
+    datagrams: u64,
+    parse_errors: u64,
+    // Datagrams that filled the receive buffer; not parsed.
+    truncated: u64,
+    // Shreds discarded by --max-slot-age.
+    stale: u64,
+    // Only counted with --verify-signatures; neither kind is matched.
+    invalid_signatures: u64,
+    unknown_leader: u64,
+    bytes_received: u64,
+    // `bytes_received` as of the previous stats tick, for per-interval throughput.
+    interval_start_bytes: u64,
+    // First arrivals that passed the filters, and pairs this source was matched in, with
+    // their values as of the previous stats tick for the per-interval match rate.
+    unique_shreds: u64,
+    matched: u64,
+    interval_start_unique_shreds: u64,
+    interval_start_matched: u64,
+    // Matched pairs this source delivered first, and as of the previous stats tick.
+    led: u64,
+    interval_start_led: u64,
+    // Repeat arrivals of a shred already tracked for this source, and their total lag
+    // behind the first copy.
+    duplicates: u64,
+    duplicate_delay_nanos: u128,
+    // With --first-seen-wins: shreds this source delivered before any other, and copies
+    // it delivered after some source (itself included) already had, with their total lag.
+    first_seen: u64,
+    seen_later: u64,
+    seen_later_nanos: u128,
+    // Entries evicted by cleanup, and how many of those never matched another source.
+    expired: u64,
+    expired_unmatched: u64,
+    // How long the unmatched ones had waited, bucketed by EVICTION_AGE_EDGES.
+    eviction_ages: [u64; EVICTION_AGE_EDGES.len() + 1],
+    // With --bloom-dedup: repeats only the Bloom filter caught, and the expected number of
+    // first copies it wrongly dropped as repeats.
+    bloom_hits: u64,
+    bloom_false_positives: f64,
+}
+
+#[derive(Clone, Copy)]
+struct Arrival {
+    time: Instant,
+    // Sources this arrival has been matched with.
+    partners: u16,
+    shred_type: ShredType,
+    // Only known when recorded with `record_with_fec_set`.
+    fec_set_index: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MatchKeyArg {
+    /// Slot, index and shred type must all agree.
+    ShredId,
+    /// Ignore the shred type, e.g. to pair a data shred with one recovered from coding shreds.
+    SlotIndex,
+}
+
+// What arrivals are matched on across sources.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct MatchKey {
+    slot: u64,
+    index: u32,
+    // `None` with --match-key slot-index.
+    shred_type: Option<ShredType>,
+}
+
+impl MatchKey {
+    fn new(shred_id: ShredId, mode: MatchKeyArg) -> Self {
+        Self {
+            slot: shred_id.slot(),
+            index: shred_id.index(),
+            shred_type: (mode == MatchKeyArg::ShredId).then(|| shred_id.shred_type()),
+        }
+    }
+}
+
+const SHRED_TYPES: [ShredType; 2] = [ShredType::Data, ShredType::Code];
+
+fn shred_type_index(shred_type: ShredType) -> usize {
+    match shred_type {
+        ShredType::Data => 0,
+        ShredType::Code => 1,
+    }
+}
+
+struct DelayStats {
+    matched_pairs: usize,
+    // `matched_pairs` when the stats interval started, for --reset-stats-each-tick.
+    interval_start_matched_pairs: usize,
+    // Signed nanoseconds of each candidate source relative to the reference source,
+    // limited to the most recent `delay_window` matches.
+    delays: VecDeque<i64>,
+    // Running total of `delays`, so the average isn't re-summed on every report.
+    sum: i128,
+    // Exponentially weighted moving average of the same delays, for near-real-time trends.
+    ewma: Option<f64>,
+}
+
+impl DelayStats {
+    fn new(delay_window: usize) -> Self {
+        Self {
+            matched_pairs: 0,
+            interval_start_matched_pairs: 0,
+            delays: VecDeque::with_capacity(delay_window),
+            sum: 0,
+            ewma: None,
+        }
+    }
+
+    // A match seen during warmup: counted, but its delay is left out.
+    fn count(&mut self) {
+        self.matched_pairs += 1;
+    }
+
+    fn record(&mut self, delay: i64, delay_window: usize, ewma_alpha: f64) {
+        self.matched_pairs += 1;
+        if self.delays.len() == delay_window {
+            self.sum -= self.delays.pop_front().map_or(0, i128::from);
+        }
+        self.delays.push_back(delay);
+        self.sum += i128::from(delay);
+        let delay = delay as f64;
+        self.ewma = Some(self.ewma.map_or(delay, |ewma| ewma + ewma_alpha * (delay - ewma)));
+    }
+
+    fn snapshot(&self, label: &'static str, histogram_edges: &[i64], unit: DelayUnit) -> DelaySnapshot {
+        let mut snapshot = DelaySnapshot::new(label, self.matched_pairs, self.delays.iter().copied(), self.sum, histogram_edges, unit);
+        snapshot.ewma = self.ewma.map_or(0, |ewma| ewma.round() as i64);
+        snapshot
+    }
+}
+
+// With --agreement-latency, how long after a shred's first delivery on any source a
+// majority of sources (quorum), and then every source, had delivered it.
+struct Agreement {
+    // First copy from each source that has delivered the shred so far, in processing order.
+    deliveries: HashMap<MatchKey, Vec<Instant>>,
+    quorum: DelayStats,
+    all: DelayStats,
+    // Shreds evicted before every source delivered them.
+    incomplete: u64,
+}
+
+impl Agreement {
+    fn new(delay_window: usize) -> Self {
+        Self {
+            deliveries: HashMap::new(),
+            quorum: DelayStats::new(delay_window),
+            all: DelayStats::new(delay_window),
+            incomplete: 0,
+        }
+    }
+
+    // Records that another source delivered `key` at `timestamp`, out of `sources` sources.
+    fn observe(&mut self, key: MatchKey, timestamp: Instant, sources: usize, warming_up: bool, delay_window: usize, ewma_alpha: f64) {
+        let deliveries = self.deliveries.entry(key).or_default();
+        deliveries.push(timestamp);
+        let delivered = deliveries.len();
+        // Measured from the earliest copy, which needn't have been processed first.
+        let first = *deliveries.iter().min().unwrap();
+        let latest = *deliveries.iter().max().unwrap();
+        let since_first = (latest - first).as_nanos() as i64;
+        let record = |stats: &mut DelayStats| {
+            if warming_up {
+                stats.count();
+            } else {
+                stats.record(since_first, delay_window, ewma_alpha);
+            }
+        };
+        if delivered == quorum_of(sources) {
+            record(&mut self.quorum);
+        }
+        if delivered >= sources {
+            record(&mut self.all);
+            self.deliveries.remove(&key);
+        }
+    }
+}
+
+// With --reference-shreds, each source scored against a trusted record of the shreds.
+struct GroundTruth {
+    // When the recording node received each expected shred, if known, and the sources
+    // that have delivered it since.
+    expected: HashMap<MatchKey, (Option<u64>, Vec<usize>)>,
+    // Slots the record covers; other shreds in them are unexpected.
+    slots: RangeInclusive<u64>,
+    timestamps: bool,
+    // Per source.
+    scores: Vec<TruthScore>,
+}
+
+#[derive(Clone, Default)]
+struct TruthScore {
+    delivered: u64,
+    unexpected: u64,
+    // Signed nanoseconds after the recording node received each shred.
+    latency: Welford,
+}
+
+// A majority of `sources`: 2 of 3, 3 of 4 or 5.
+fn quorum_of(sources: usize) -> usize {
+    sources / 2 + 1
+}
+
+// How far ahead the winner of each of one candidate's pairs with the reference was, split
+// by which of the two led (candidate first), for --compare-mode.
+#[derive(Clone, Default)]
+struct LeadMargins {
+    led: [usize; 2],
+    margins: [VecDeque<i64>; 2],
+    sums: [i128; 2],
+}
+
+impl LeadMargins {
+    fn record(&mut self, delay: i64, delay_window: usize) {
+        let side = usize::from(delay >= 0);
+        self.led[side] += 1;
+        let margins = &mut self.margins[side];
+        if margins.len() == delay_window {
+            self.sums[side] -= margins.pop_front().map_or(0, i128::from);
+        }
+        margins.push_back(delay.abs());
+        self.sums[side] += i128::from(delay.abs());
+    }
+
+    fn snapshot(&self, histogram_edges: &[i64], unit: DelayUnit) -> LeadMarginsSnapshot {
+        let [candidate_led, reference_led] = [0, 1].map(|side| {
+            let label = if side == 0 { "candidate_led" } else { "reference_led" };
+            DelaySnapshot::new(label, self.led[side], self.margins[side].iter().copied(), self.sums[side], histogram_edges, unit)
+        });
+        LeadMarginsSnapshot { candidate_led, reference_led }
+    }
+}
+
+// One matched pair, ordered by how far the later copy trailed.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Laggard {
+    behind_nanos: u64,
+    slot: u64,
+    index: u32,
+    shred_type: &'static str,
+    late_idx: usize,
+    leader_idx: usize,
+}
+
+// The `limit` matched pairs with the largest |delay| so far, for --top-laggards. A
+// min-heap, so the mildest of them is the one a worse pair replaces.
+struct Laggards {
+    limit: usize,
+    heap: BinaryHeap<Reverse<Laggard>>,
+}
+
+impl Laggards {
+    fn new(limit: usize) -> Self {
+        Self { limit, heap: BinaryHeap::new() }
+    }
+
+    fn observe(&mut self, laggard: Laggard) {
+        if self.heap.len() < self.limit {
+            self.heap.push(Reverse(laggard));
+        } else if self.heap.peek().is_some_and(|Reverse(mildest)| laggard.behind_nanos > mildest.behind_nanos) {
+            self.heap.pop();
+            self.heap.push(Reverse(laggard));
+        }
+    }
+
+    // Worst first.
+    fn snapshot(&self, source_names: &[Arc<str>]) -> Vec<LaggardSnapshot> {
+        let mut laggards: Vec<&Laggard> = self.heap.iter().map(|Reverse(laggard)| laggard).collect();
+        laggards.sort_unstable_by(|a, b| b.cmp(a));
+        laggards
+            .into_iter()
+            .map(|laggard| LaggardSnapshot {
+                slot: laggard.slot,
+                index: laggard.index,
+                shred_type: laggard.shred_type,
+                late: Arc::clone(&source_names[laggard.late_idx]),
+                leader: Arc::clone(&source_names[laggard.leader_idx]),
+                behind: laggard.behind_nanos as i64,
+            })
+            .collect()
+    }
+}
+
+// Gaps between consecutive shreds from one source, for --inter-arrival.
+#[derive(Clone, Default)]
+struct InterArrival {
+    last: Option<Instant>,
+    gaps: Welford,
+    max: Duration,
+}
+
+impl InterArrival {
+    fn observe(&mut self, timestamp: Instant) {
+        if let Some(last) = self.last {
+            let gap = timestamp.saturating_duration_since(last);
+            self.gaps.add(gap.as_nanos() as f64);
+            self.max = self.max.max(gap);
+        }
+        // Batched kernel timestamps can run slightly out of order; gaps never go negative.
+        self.last = self.last.max(Some(timestamp));
+    }
+}
+
+// Depth buckets --reorder-depth reports: 0, 1, 2-3, 4-7, 8-15 and 16+ slots behind.
+const REORDER_BUCKETS: usize = 6;
+
+// How far behind the highest slot already seen on one source each shred's slot was, for
+// --reorder-depth.
+#[derive(Clone, Default)]
+struct Reordering {
+    highest_slot: Option<u64>,
+    max_depth: u64,
+    buckets: [u64; REORDER_BUCKETS],
+}
+
+impl Reordering {
+    fn observe(&mut self, slot: u64) {
+        let depth = self.highest_slot.map_or(0, |highest| highest.saturating_sub(slot));
+        let bucket = (u64::BITS - depth.leading_zeros()) as usize;
+        self.buckets[bucket.min(REORDER_BUCKETS - 1)] += 1;
+        self.max_depth = self.max_depth.max(depth);
+        self.highest_slot = self.highest_slot.max(Some(slot));
+    }
+
+    fn snapshot(&self) -> ReorderSnapshot {
+        let labels = ["0", "1", "2-3", "4-7", "8-15", "16+"];
+        ReorderSnapshot {
+            max_depth: self.max_depth,
+            buckets: labels
+                .iter()
+                .zip(self.buckets)
+                .map(|(label, count)| HistogramBucket {
+                    label: label.to_string(),
+                    count: count as usize,
+                })
+                .collect(),
+        }
+    }
+}
+
+fn eviction_age_buckets(counts: &[u64]) -> Vec<HistogramBucket> {
+    counts
+        .iter()
+        .enumerate()
+        .map(|(idx, count)| {
+            let label = match (idx.checked_sub(1).map(|i| EVICTION_AGE_EDGES[i]), EVICTION_AGE_EDGES.get(idx)) {
+                (None, Some(upper)) => format!("<{}x", upper),
+                (Some(lower), Some(upper)) => format!("{}-{}x", lower, upper),
+                (Some(lower), None) => format!(">{}x", lower),
+                (None, None) => "all".to_string(),
+            };
+            HistogramBucket { label, count: *count as usize }
+        })
+        .collect()
+}
+
+// Warmup is measured on the shred timeline, so a replayed capture warms up the same way
+// every time.
+enum Warmup {
+    NotStarted(Duration),
+    Until(Instant),
+    Done,
+}
+
+impl Warmup {
+    fn new(duration: Duration) -> Self {
+        if duration.is_zero() {
+            Warmup::Done
+        } else {
+            Warmup::NotStarted(duration)
+        }
+    }
+
+    fn observe(&mut self, timestamp: Instant) {
+        match *self {
+            Warmup::NotStarted(duration) => *self = Warmup::Until(timestamp + duration),
+            Warmup::Until(end) if timestamp >= end => {
+                info!(target: "shred_perf::processor", "Warmup finished, recording delays");
+                *self = Warmup::Done;
+            }
+            _ => {}
+        }
+    }
+
+    fn in_progress(&self) -> bool {
+        !matches!(self, Warmup::Done)
+    }
+}
+
+/// Matches shreds across sources and keeps the delay stats between them.
+pub struct Processor {
+    source_names: Vec<Arc<str>>,
+    reference_idx: usize,
+    source_data: Vec<HashMap<MatchKey, Arrival>>,
+    match_key: MatchKeyArg,
+    source_stats: Vec<SourceStats>,
+    filter_type: Option<ShredType>,
+    slot_range: RangeInclusive<u64>,
+    // Highest slot seen on any source, and how far behind it a shred may be.
+    highest_slot: u64,
+    max_slot_age: Option<u64>,
+    // Only 1 in this many shreds, picked by id, are matched.
+    sample_rate: Option<u64>,
+    // Shreds are kept for matching this long, or with --evict-after-slots until their slot
+    // falls this many slots behind `highest_slot`, and per source the last cleanup evicted
+    // this many.
+    retention: Duration,
+    evict_after_slots: Option<u64>,
+    last_evicted: Vec<u64>,
+    // With --no-match-expiry-report, cleanup logs every shred it evicts unmatched.
+    expiry_report: bool,
+    // Sources `remove_source` dropped. They keep their index, so events already queued
+    // for them can't land on another source, but receive nothing further and leave the
+    // reports.
+    retired: Vec<bool>,
+    warmup: Warmup,
+    matched_pairs: usize,
+    // Matches where the two sources delivered different shred types (--match-key slot-index).
+    cross_type_matches: usize,
+    // Indexed by `shred_type_index`.
+    delay_stats: [DelayStats; 2],
+    delay_window: usize,
+    ewma_alpha: f64,
+    // Only populated with --per-slot-report; trimmed to `per_slot_report` slots on cleanup.
+    slot_delays: BTreeMap<u64, Vec<i64>>,
+    per_slot_report: Option<usize>,
+    // The same per FEC set within each slot, with --per-fec-set-report.
+    fec_set_delays: BTreeMap<u64, BTreeMap<u32, Vec<i64>>>,
+    per_fec_set_report: Option<usize>,
+    // With --index-buckets, the bucket edges and the delays of the shreds in each bucket.
+    index_buckets: Option<(Vec<u32>, Vec<Welford>)>,
+    // With --baseline-offset-us, each source's offset in nanoseconds and, per candidate, its
+    // delays before the offsets were applied.
+    baseline: Option<(Vec<i64>, Vec<Welford>)>,
+    slot_gaps: Option<SlotGaps>,
+    // With --completeness-report; trimmed to its slots on cleanup.
+    completeness: Option<SlotCompleteness>,
+    index_loss: Option<IndexLoss>,
+    // With --first-seen-wins, the first delivery of each shred across all sources.
+    first_seen: Option<HashMap<MatchKey, (usize, Instant)>>,
+    // Per source, with --inter-arrival.
+    inter_arrival: Option<Vec<InterArrival>>,
+    // Per source, with --reorder-depth.
+    reordering: Option<Vec<Reordering>>,
+    // Per candidate source, with --compare-mode.
+    lead_margins: Option<Vec<LeadMargins>>,
+    agreement: Option<Agreement>,
+    truth: Option<GroundTruth>,
+    // Per source, with --bloom-dedup.
+    bloom: Option<Vec<RotatingBloom>>,
+    // With --top-laggards.
+    laggards: Option<Laggards>,
+    // With --alert-above-ms.
+    alert: Option<DelayAlert>,
+    wins: Vec<usize>,
+    // With --reset-stats-each-tick, matches, wins and leads are reported for the current
+    // stats interval only, counted from these values as of the previous tick.
+    reset_stats_each_tick: bool,
+    // Warn when one source receives this many times the shreds of another in an interval.
+    imbalance_ratio: Option<f64>,
+    interval_start_matched_pairs: usize,
+    interval_start_wins: Vec<usize>,
+    // Per candidate source, over every recorded (post-warmup) delay.
+    jitter: Vec<Welford>,
+    // Live datagrams the receive tasks dropped because the processor channel was full.
+    dropped_events: Vec<Arc<AtomicU64>>,
+    metrics: Option<Arc<Metrics>>,
+    #[cfg(feature = "otlp")]
+    otel: Option<Arc<OtelMetrics>>,
+    wall_clock: WallClock,
+    csv_out: Option<CsvWriter>,
+    ndjson_out: Option<NdjsonWriter>,
+    json_stats_out: Option<JsonStatsWriter>,
+    #[cfg(feature = "tui")]
+    dashboard: Option<Dashboard>,
+    binary_log: Option<BinaryLogWriter>,
+    // Flush the match exports after this many matches as well as on every tick, counting
+    // the ones written since the last flush.
+    flush_every: Option<u64>,
+    unflushed: u64,
+    match_events: Option<broadcast::Sender<MatchRecord>>,
+    state_file: Option<PathBuf>,
+    signatures_verified: bool,
+    quiet: bool,
+    log_format: LogFormat,
+    stats_format: StatsFormat,
+    delay_unit: DelayUnit,
+    stats_header_printed: bool,
+    stats_interval_start: Instant,
+    // When the processor was created, and when the first pair matched after that.
+    start_time: Instant,
+    first_match: Option<Instant>,
+    // Histogram bucket upper edges in nanoseconds.
+    histogram_edges: Vec<i64>,
+    min_samples: usize,
+    // |delay| in microseconds across all shred types, for precise tail percentiles.
+    hdr: Histogram<u64>,
+}
+
+/// How a [`Processor`] matches and reports. The default matches on the full shred id
+/// against the first source, keeps shreds for a minute and writes no exports.
+pub struct ProcessorConfig {
+    /// Source every delay is measured against.
+    pub reference_idx: usize,
+    pub match_key: MatchKeyArg,
+    /// Only shreds of this type and within `slot_range` are matched.
+    pub filter_type: Option<ShredType>,
+    pub slot_range: RangeInclusive<u64>,
+    /// Shreds more than this many slots behind the highest slot seen are discarded.
+    pub max_slot_age: Option<u64>,
+    /// Only match 1 in this many shreds, chosen by slot and index so every source keeps
+    /// the same ones.
+    pub sample_rate: Option<u64>,
+    /// How long shreds are kept for matching before `cleanup` evicts them.
+    pub retention: Duration,
+    /// Have `cleanup` evict shreds more than this many slots behind the highest slot seen
+    /// instead, however recently they arrived. Slot gaps still expire after `retention`.
+    pub evict_after_slots: Option<u64>,
+    /// Log each shred cleanup evicts without it ever having matched.
+    pub expiry_report: bool,
+    /// Matches this soon after the first shred are counted, but their delays left out.
+    pub warmup: Duration,
+    /// Number of most recent delays the windowed stats cover.
+    pub delay_window: usize,
+    pub ewma_alpha: f64,
+    /// Report the average delay of this many recent slots.
+    pub per_slot_report: Option<usize>,
+    /// Report the average delay of each FEC set in this many recent slots. Needs shreds
+    /// recorded with `record_with_fec_set`.
+    pub per_fec_set_report: Option<usize>,
+    /// Report the average delay by shred index, in buckets split at these indices.
+    pub index_buckets: Option<Vec<u32>>,
+    /// Per source, nanoseconds subtracted from its arrival times before delays are taken,
+    /// to cancel out a known difference in network path. The raw delays are reported too.
+    pub baseline_offsets: Option<Vec<i64>>,
+    /// Report this many matched pairs with the largest |delay|, worst first.
+    pub top_laggards: Option<usize>,
+    /// Report matches, wins and leads over each stats interval rather than the whole run,
+    /// alongside the lifetime totals.
+    pub reset_stats_each_tick: bool,
+    /// Warn with each stats report when one source received more than this many times the
+    /// shreds of another over the interval, a sign of a misconfigured comparison.
+    pub imbalance_ratio: Option<f64>,
+    /// Alert when a candidate's average delay vs the reference stays above this many
+    /// nanoseconds for `alert_window`.
+    pub alert_above: Option<i64>,
+    pub alert_window: Duration,
+    /// Shell command run when an alert fires or resolves.
+    pub alert_cmd: Option<String>,
+    /// Track delivered shred indices for this many recent slots.
+    pub slot_gaps: Option<usize>,
+    /// Report how many data shreds each source delivered for this many recent slots.
+    pub completeness_report: Option<usize>,
+    /// Data shreds a slot should hold; learned per slot from the highest index if unset.
+    pub shreds_per_slot: Option<u32>,
+    /// Estimate each source's packet loss from gaps in its own data shred indices, counting
+    /// a slot once the source has delivered for this many newer ones.
+    pub index_loss: Option<usize>,
+    pub first_seen_wins: bool,
+    /// Track the gaps between consecutive shreds on each source.
+    pub inter_arrival: bool,
+    /// Track how far out of slot order each source delivers.
+    pub reorder_depth: bool,
+    /// Keep separate delay stats for the pairs each side of a candidate/reference pair led.
+    pub compare_mode: bool,
+    /// Track how long after its first delivery a majority of sources, and then all of
+    /// them, had delivered each shred.
+    pub agreement_latency: bool,
+    /// Score each source against these shreds, the ones `filter_type`, `slot_range` and
+    /// `sample_rate` keep.
+    pub reference_shreds: Option<ReferenceShreds>,
+    /// Once a shred has matched every other source, replace its exact entry with a Bloom
+    /// filter at this false-positive rate, used only to spot repeats.
+    pub bloom_dedup: Option<f64>,
+    /// Upper edges of the delay histogram buckets, in nanoseconds.
+    pub histogram_edges: Vec<i64>,
+    /// Human stats show percentiles only once a delay window has this many samples.
+    pub min_samples: usize,
+    /// Shreds are signature-checked before `record`, so the rejection counts are reported.
+    pub signatures_verified: bool,
+    /// Never log individual matches.
+    pub quiet: bool,
+    pub log_format: LogFormat,
+    pub stats_format: StatsFormat,
+    pub delay_unit: DelayUnit,
+    pub metrics: Option<Arc<Metrics>>,
+    #[cfg(feature = "otlp")]
+    pub otel: Option<Arc<OtelMetrics>>,
+    /// Maps shred timestamps onto unix time for exports.
+    pub wall_clock: WallClock,
+    pub csv_out: Option<CsvWriter>,
+    pub ndjson_out: Option<NdjsonWriter>,
+    /// Every stats report is also appended here.
+    pub json_stats_out: Option<JsonStatsWriter>,
+    /// Draws each stats report here instead of logging it, for --tui.
+    #[cfg(feature = "tui")]
+    pub dashboard: Option<Dashboard>,
+    pub binary_log: Option<BinaryLogWriter>,
+    /// Also flush the CSV, NDJSON and binary exports after this many matches; 1 flushes
+    /// every match. Otherwise they are flushed by `flush_outputs`.
+    pub flush_every: Option<u64>,
+    /// Every match is also broadcast here, e.g. for the gRPC stream.
+    pub match_events: Option<broadcast::Sender<MatchRecord>>,
+    /// Cumulative counters are saved here on every flush.
+    pub state_file: Option<PathBuf>,
+}
+
+impl Default for ProcessorConfig {
+    fn default() -> Self {
+        Self {
+            reference_idx: 0,
+            match_key: MatchKeyArg::ShredId,
+            filter_type: None,
+            slot_range: 0..=u64::MAX,
+            max_slot_age: None,
+            sample_rate: None,
+            retention: Duration::from_secs(60),
+            evict_after_slots: None,
+            expiry_report: false,
+            warmup: Duration::ZERO,
+            delay_window: 100_000,
+            ewma_alpha: 0.01,
+            per_slot_report: None,
+            per_fec_set_report: None,
+            index_buckets: None,
+            baseline_offsets: None,
+            top_laggards: None,
+            reset_stats_each_tick: false,
+            imbalance_ratio: None,
+            alert_above: None,
+            alert_window: Duration::from_secs(30),
+            alert_cmd: None,
+            slot_gaps: None,
+            completeness_report: None,
+            shreds_per_slot: None,
+            index_loss: None,
+            first_seen_wins: false,
+            inter_arrival: false,
+            reorder_depth: false,
+            compare_mode: false,
+            agreement_latency: false,
+            reference_shreds: None,
+            bloom_dedup: None,
+            histogram_edges: [50, 100, 250, 1000, 10000].map(|us| us * 1000).to_vec(),
+            min_samples: 0,
+            signatures_verified: false,
+            quiet: false,
+            log_format: LogFormat::Pretty,
+            stats_format: StatsFormat::Human,
+            delay_unit: DelayUnit::Auto,
+            metrics: None,
+            #[cfg(feature = "otlp")]
+            otel: None,
+            wall_clock: WallClock::now(),
+            csv_out: None,
+            ndjson_out: None,
+            json_stats_out: None,
+            #[cfg(feature = "tui")]
+            dashboard: None,
+            binary_log: None,
+            flush_every: None,
+            match_events: None,
+            state_file: None,
+        }
+    }
+}
+
+impl Processor {
+    pub fn new(source_names: Vec<Arc<str>>, config: ProcessorConfig) -> anyhow::Result<Self> {
+        let sources = source_names.len();
+        anyhow::ensure!(config.reference_idx < sources, "reference source {} out of range", config.reference_idx);
+        anyhow::ensure!(
+            config.baseline_offsets.as_ref().is_none_or(|offsets| offsets.len() == sources),
+            "baseline offsets given for {} sources, expected {}",
+            config.baseline_offsets.as_ref().map_or(0, Vec::len),
+            sources
+        );
+        let truth = config.reference_shreds.map(|reference| {
+            let kept = reference.shreds().iter().filter(|(shred_id, _)| {
+                config.filter_type.is_none_or(|t| t == shred_id.shred_type())
+                    && config.slot_range.contains(&shred_id.slot())
+                    && config.sample_rate.is_none_or(|rate| sampled(*shred_id, rate))
+            });
+            let expected: HashMap<_, _> =
+                kept.map(|(shred_id, received)| (MatchKey::new(*shred_id, config.match_key), (*received, Vec::new()))).collect();
+            let slots = expected.keys().map(|key| key.slot);
+            GroundTruth {
+                slots: slots.clone().min().unwrap_or(1)..=slots.max().unwrap_or(0),
+                timestamps: expected.values().any(|(received, _)| received.is_some()),
+                expected,
+                scores: vec![TruthScore::default(); sources],
+            }
+        });
+        let alert = config.alert_above.map(|threshold| {
+            DelayAlert::new(threshold, config.alert_window, config.alert_cmd, &source_names, config.reference_idx, config.delay_unit)
+        });
+        Ok(Self {
+            source_names,
+            reference_idx: config.reference_idx,
+            source_data: vec![HashMap::new(); sources],
+            match_key: config.match_key,
+            source_stats: vec![SourceStats::default(); sources],
+            filter_type: config.filter_type,
+            retention: config.retention,
+            evict_after_slots: config.evict_after_slots,
+            expiry_report: config.expiry_report,
+            last_evicted: vec![0; sources],
+            retired: vec![false; sources],
+            slot_range: config.slot_range,
+            highest_slot: 0,
+            max_slot_age: config.max_slot_age,
+            sample_rate: config.sample_rate,
+            warmup: Warmup::new(config.warmup),
+            matched_pairs: 0,
+            cross_type_matches: 0,
+            delay_stats: SHRED_TYPES.map(|_| DelayStats::new(config.delay_window)),
+            delay_window: config.delay_window,
+            ewma_alpha: config.ewma_alpha,
+            slot_delays: BTreeMap::new(),
+            per_slot_report: config.per_slot_report,
+            fec_set_delays: BTreeMap::new(),
+            per_fec_set_report: config.per_fec_set_report,
+            index_buckets: config.index_buckets.map(|edges| {
+                let buckets = vec![Welford::default(); edges.len() + 1];
+                (edges, buckets)
+            }),
+            baseline: config.baseline_offsets.map(|offsets| (offsets, vec![Welford::default(); sources])),
+            first_seen: config.first_seen_wins.then(HashMap::new),
+            inter_arrival: config.inter_arrival.then(|| vec![InterArrival::default(); sources]),
+            reordering: config.reorder_depth.then(|| vec![Reordering::default(); sources]),
+            lead_margins: config.compare_mode.then(|| vec![LeadMargins::default(); sources]),
+            agreement: config.agreement_latency.then(|| Agreement::new(config.delay_window)),
+            truth,
+            bloom: config.bloom_dedup.map(|rate| (0..sources).map(|_| RotatingBloom::new(rate)).collect()),
+            slot_gaps: config.slot_gaps.map(|slots| SlotGaps::new(sources, slots)),
+            completeness: config.completeness_report.map(|slots| SlotCompleteness::new(slots, config.shreds_per_slot)),
+            index_loss: config.index_loss.map(|slots| IndexLoss::new(sources, slots)),
+            wins: vec![0; sources],
+            laggards: config.top_laggards.map(Laggards::new),
+            alert,
+            reset_stats_each_tick: config.reset_stats_each_tick,
+            imbalance_ratio: config.imbalance_ratio,
+            interval_start_matched_pairs: 0,
+            interval_start_wins: vec![0; sources],
+            jitter: vec![Welford::default(); sources],
+            dropped_events: (0..sources).map(|_| Arc::default()).collect(),
+            metrics: config.metrics,
+            #[cfg(feature = "otlp")]
+            otel: config.otel,
+            wall_clock: config.wall_clock,
+            csv_out: config.csv_out,
+            ndjson_out: config.ndjson_out,
+            json_stats_out: config.json_stats_out,
+            #[cfg(feature = "tui")]
+            dashboard: config.dashboard,
+            binary_log: config.binary_log,
+            flush_every: config.flush_every,
+            unflushed: 0,
+            match_events: config.match_events,
+            state_file: config.state_file,
+            signatures_verified: config.signatures_verified,
+            quiet: config.quiet,
+            log_format: config.log_format,
+            stats_format: config.stats_format,
+            delay_unit: config.delay_unit,
+            stats_header_printed: false,
+            stats_interval_start: Instant::now(),
+            start_time: Instant::now(),
+            first_match: None,
+            histogram_edges: config.histogram_edges,
+            min_samples: config.min_samples,
+            hdr: Histogram::new_with_bounds(1, HDR_MAX_MICROS, 3)?,
+        })
+    }
+
+    /// Starts matching a source added while running, e.g. by --watch-config, and returns
+    /// its index. It starts with no shreds and empty stats; `labels` are its --source-label
+    /// pairs.
+    pub fn add_source(&mut self, name: Arc<str>, labels: &BTreeMap<String, String>) -> usize {
+        let idx = self.source_names.len();
+        if let Some(metrics) = &self.metrics {
+            if let Err(e) = metrics.add_source(&name, labels) {
+                error!("Failed to add metrics for source `{}`: {}", name, e);
+            }
+        }
+        #[cfg(feature = "otlp")]
+        if let Some(otel) = &self.otel {
+            otel.add_source(&name, labels);
+        }
+        if let Some(ndjson_out) = &mut self.ndjson_out {
+            ndjson_out.add_source(&name, labels.clone());
+        }
+        if let Some(alert) = &mut self.alert {
+            alert.add_source(Arc::clone(&name));
+        }
+        self.source_names.push(name);
+        self.source_data.push(HashMap::new());
+        self.source_stats.push(SourceStats::default());
+        self.last_evicted.push(0);
+        self.retired.push(false);
+        if let Some(inter_arrival) = &mut self.inter_arrival {
+            inter_arrival.push(InterArrival::default());
+        }
+        if let Some(reordering) = &mut self.reordering {
+            reordering.push(Reordering::default());
+        }
+        if let Some(lead_margins) = &mut self.lead_margins {
+            lead_margins.push(LeadMargins::default());
+        }
+        if let Some((offsets, raw)) = &mut self.baseline {
+            offsets.push(0);
+            raw.push(Welford::default());
+        }
+        if let Some(bloom) = &mut self.bloom {
+            bloom.push(RotatingBloom::new(bloom[0].target_rate()));
+        }
+        if let Some(gaps) = &mut self.slot_gaps {
+            gaps.add_source();
+        }
+        if let Some(index_loss) = &mut self.index_loss {
+            index_loss.add_source();
+        }
+        if let Some(truth) = &mut self.truth {
+            truth.scores.push(TruthScore::default());
+        }
+        self.wins.push(0);
+        self.interval_start_wins.push(0);
+        self.jitter.push(Welford::default());
+        self.dropped_events.push(Arc::default());
+        // The CSV stats columns change with the sources.
+        self.stats_header_printed = false;
+        idx
+    }
+
+    /// Stops matching a source, dropping the shreds it still has waiting. The reference
+    /// can't be removed.
+    pub fn remove_source(&mut self, source_idx: usize) -> anyhow::Result<()> {
+        anyhow::ensure!(source_idx != self.reference_idx, "the reference source can't be removed");
+        self.retired[source_idx] = true;
+        self.source_data[source_idx] = HashMap::new();
+        self.stats_header_printed = false;
+        Ok(())
+    }
+
+    /// Counter for datagrams dropped before reaching the processor, reported per source.
+    pub fn dropped_counter(&self, source_idx: usize) -> Arc<AtomicU64> {
+        Arc::clone(&self.dropped_events[source_idx])
+    }
+
+    /// Adds to the bytes received from a source, whether or not they parsed.
+    pub fn record_bytes(&mut self, source_idx: usize, bytes: usize) {
+        self.source_stats[source_idx].bytes_received += bytes as u64;
+    }
+
+    /// Counts a datagram that didn't parse as a shred.
+    pub fn record_parse_failure(&mut self, source_idx: usize) {
+        let stats = &mut self.source_stats[source_idx];
+        stats.datagrams += 1;
+        stats.parse_errors += 1;
+    }
+
+    /// Counts a datagram that filled the whole `buf_size`-byte receive buffer, and so was
+    /// probably cut short. Warns the first time it happens on a source.
+    pub fn record_truncated(&mut self, source_idx: usize, buf_size: usize) {
+        let stats = &mut self.source_stats[source_idx];
+        stats.datagrams += 1;
+        stats.truncated += 1;
+        if stats.truncated == 1 {
+            warn!(
+                target: "shred_perf::processor",
+                "[{}] Datagram filled the {}-byte receive buffer and was likely truncated; raise --max-packet-size",
+                self.source_names[source_idx], buf_size
+            );
+        }
+    }
+
+    /// Counts a shred rejected for its signature, or because its slot's leader is unknown.
+    pub fn record_signature_rejected(&mut self, source_idx: usize, leader_known: bool) {
+        let stats = &mut self.source_stats[source_idx];
+        stats.datagrams += 1;
+        if leader_known {
+            stats.invalid_signatures += 1;
+        } else {
+            stats.unknown_leader += 1;
+        }
+    }
+
+    /// Counts one shred from `source_idx` received at `timestamp`, matching it against the
+    /// copies the other sources delivered.
+    pub fn record(&mut self, source_idx: usize, shred_id: ShredId, timestamp: Instant) {
+        self.record_arrival(source_idx, shred_id, None, timestamp);
+    }
+
+    /// Like `record`, for a shred whose FEC set is known, so --per-fec-set-report can
+    /// group its delay.
+    pub fn record_with_fec_set(&mut self, source_idx: usize, shred_id: ShredId, fec_set_index: u32, timestamp: Instant) {
+        self.record_arrival(source_idx, shred_id, Some(fec_set_index), timestamp);
+    }
+
+    fn record_arrival(&mut self, source_idx: usize, shred_id: ShredId, fec_set_index: Option<u32>, timestamp: Instant) {
+        if self.retired[source_idx] {
+            return;
+        }
+        self.source_stats[source_idx].datagrams += 1;
+        if let Some(inter_arrival) = &mut self.inter_arrival {
+            inter_arrival[source_idx].observe(timestamp);
+        }
+        if let Some(reordering) = &mut self.reordering {
+            reordering[source_idx].observe(shred_id.slot());
+        }
+        if self.filter_type.is_some_and(|t| t != shred_id.shred_type()) || !self.slot_range.contains(&shred_id.slot()) {
+            return;
+        }
+        if self.sample_rate.is_some_and(|rate| !sampled(shred_id, rate)) {
+            return;
+        }
+        self.highest_slot = self.highest_slot.max(shred_id.slot());
+        if self.max_slot_age.is_some_and(|age| shred_id.slot().saturating_add(age) < self.highest_slot) {
+            self.source_stats[source_idx].stale += 1;
+            return;
+        }
+        self.warmup.observe(timestamp);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_received(&self.source_names[source_idx]);
+        }
+        #[cfg(feature = "otlp")]
+        if let Some(otel) = &self.otel {
+            otel.record_received(source_idx);
+        }
+        let key = MatchKey::new(shred_id, self.match_key);
+        if let Some(first_seen) = &mut self.first_seen {
+            match first_seen.get_mut(&key) {
+                Some((owner, first)) if timestamp >= *first => {
+                    let stats = &mut self.source_stats[source_idx];
+                    stats.seen_later += 1;
+                    stats.seen_later_nanos += (timestamp - *first).as_nanos();
+                }
+                // Sources are processed in channel order, so an earlier copy can turn up after a
+                // later one (always with pcap replay). It takes over, and the previous owner
+                // becomes the later copy; copies counted before that keep their original lag.
+                Some((owner, first)) => {
+                    let previous = &mut self.source_stats[*owner];
+                    previous.first_seen -= 1;
+                    previous.seen_later += 1;
+                    previous.seen_later_nanos += (*first - timestamp).as_nanos();
+                    self.source_stats[source_idx].first_seen += 1;
+                    (*owner, *first) = (source_idx, timestamp);
+                }
+                None => {
+                    first_seen.insert(key, (source_idx, timestamp));
+                    self.source_stats[source_idx].first_seen += 1;
+                }
+            }
+        }
+        if let Some(first) = self.source_data[source_idx].get(&key) {
+            let stats = &mut self.source_stats[source_idx];
+            stats.duplicates += 1;
+            stats.duplicate_delay_nanos += timestamp.saturating_duration_since(first.time).as_nanos();
+            return;
+        }
+        if let Some(bloom) = &self.bloom {
+            let bloom = &bloom[source_idx];
+            let stats = &mut self.source_stats[source_idx];
+            if bloom.contains(&key) {
+                stats.bloom_hits += 1;
+                return;
+            }
+            // Of the first copies that reach the filter, a share p tests positive; each one
+            // let through stands for p / (1 - p) of those lost.
+            let rate = bloom.false_positive_rate();
+            stats.bloom_false_positives += rate / (1.0 - rate);
+        }
+        let arrival = Arrival {
+            time: timestamp,
+            partners: 0,
+            shred_type: shred_id.shred_type(),
+            fec_set_index,
+        };
+        self.source_data[source_idx].insert(key, arrival);
+        self.source_stats[source_idx].unique_shreds += 1;
+        if let Some(truth) = &mut self.truth {
+            let score = &mut truth.scores[source_idx];
+            match truth.expected.get_mut(&key) {
+                // A repeat the source's map no longer held.
+                Some((_, delivered_by)) if delivered_by.contains(&source_idx) => {}
+                Some((received, delivered_by)) => {
+                    delivered_by.push(source_idx);
+                    score.delivered += 1;
+                    if let Some(received) = received.filter(|_| !self.warmup.in_progress()) {
+                        score.latency.add((self.wall_clock.unix_nanos(timestamp) as i64 - received as i64) as f64);
+                    }
+                }
+                None if truth.slots.contains(&key.slot) => score.unexpected += 1,
+                None => {}
+            }
+        }
+        if let Some(agreement) = &mut self.agreement {
+            let sources = self.retired.iter().filter(|retired| !**retired).count();
+            let warming_up = self.warmup.in_progress();
+            agreement.observe(key, timestamp, sources, warming_up, self.delay_window, self.ewma_alpha);
+        }
+        if let Some(gaps) = &mut self.slot_gaps {
+            let type_idx = shred_type_index(shred_id.shred_type());
+            if let Some(completed) = gaps.record(source_idx, shred_id.slot(), type_idx, shred_id.index(), timestamp) {
+                self.log_slot_gaps(&completed);
+            }
+        }
+        if let Some(completeness) = self.completeness.as_mut().filter(|_| shred_id.shred_type() == ShredType::Data) {
+            completeness.record(source_idx, shred_id.slot(), shred_id.index());
+        }
+        if let Some(index_loss) = self.index_loss.as_mut().filter(|_| shred_id.shred_type() == ShredType::Data) {
+            if let Some((slot, loss)) = index_loss.record(source_idx, shred_id.slot(), shred_id.index()) {
+                self.log_slot_loss(source_idx, slot, loss);
+            }
+        }
+
+        let reference_idx = self.reference_idx;
+        if source_idx == reference_idx {
+            let candidates: Vec<(usize, Arrival)> = self
+                .source_data
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| *idx != reference_idx)
+                .filter_map(|(idx, data)| data.get(&key).map(|candidate| (idx, *candidate)))
+                .collect();
+            for (candidate_idx, candidate) in candidates {
+                self.record_match(candidate_idx, key, candidate, arrival);
+            }
+        } else if let Some(reference) = self.source_data[reference_idx].get(&key).copied() {
+            self.record_match(source_idx, key, arrival, reference);
+        }
+    }
+
+    fn record_match(&mut self, candidate_idx: usize, key: MatchKey, candidate: Arrival, reference: Arrival) {
+        for idx in [self.reference_idx, candidate_idx] {
+            if let Some(arrival) = self.source_data[idx].get_mut(&key) {
+                arrival.partners += 1;
+            }
+        }
+        if let Some(bloom) = &mut self.bloom {
+            // Once matched with every source it can be, an arrival is only needed to spot
+            // repeats, which the Bloom filter does in far less memory.
+            let others = self.retired.iter().filter(|retired| !**retired).count() as u16 - 1;
+            for (idx, partners) in [(self.reference_idx, others), (candidate_idx, 1)] {
+                if self.source_data[idx].get(&key).is_some_and(|arrival| arrival.partners >= partners) {
+                    self.source_data[idx].remove(&key);
+                    bloom[idx].insert(&key);
+                }
+            }
+        }
+        // Reported as the shred the candidate delivered; with --match-key slot-index the
+        // reference may have delivered the other type.
+        let shred_id = ShredId::new(key.slot, key.index, candidate.shred_type);
+        if candidate.shred_type != reference.shred_type {
+            self.cross_type_matches += 1;
+        }
+        let (candidate_time, reference_time) = (candidate.time, reference.time);
+        let raw_delay = signed_nanos(candidate_time, reference_time);
+        let delay = match &self.baseline {
+            Some((offsets, _)) => raw_delay - offsets[candidate_idx] + offsets[self.reference_idx],
+            None => raw_delay,
+        };
+        let winner = if delay < 0 { candidate_idx } else { self.reference_idx };
+        self.first_match.get_or_insert(candidate_time.max(reference_time));
+        let warming_up = self.warmup.in_progress();
+        self.matched_pairs += 1;
+        self.source_stats[candidate_idx].matched += 1;
+        self.source_stats[self.reference_idx].matched += 1;
+        self.source_stats[winner].led += 1;
+        let delay_stats = &mut self.delay_stats[shred_type_index(shred_id.shred_type())];
+        if warming_up {
+            delay_stats.count();
+        } else {
+            delay_stats.record(delay, self.delay_window, self.ewma_alpha);
+            self.hdr.saturating_record(delay.unsigned_abs() / 1000);
+            self.jitter[candidate_idx].add(delay as f64);
+            if let Some((_, raw)) = &mut self.baseline {
+                raw[candidate_idx].add(raw_delay as f64);
+            }
+            if let Some(lead_margins) = &mut self.lead_margins {
+                lead_margins[candidate_idx].record(delay, self.delay_window);
+            }
+            if self.per_slot_report.is_some() {
+                self.slot_delays.entry(shred_id.slot()).or_default().push(delay);
+            }
+            if let (Some(_), Some(fec_set_index)) =
+                (self.per_fec_set_report, candidate.fec_set_index.or(reference.fec_set_index))
+            {
+                let slot = self.fec_set_delays.entry(shred_id.slot()).or_default();
+                slot.entry(fec_set_index).or_default().push(delay);
+            }
+            if let Some((edges, buckets)) = &mut self.index_buckets {
+                buckets[edges.partition_point(|edge| *edge <= shred_id.index())].add(delay as f64);
+            }
+            if let Some(laggards) = &mut self.laggards {
+                let late_idx = if winner == candidate_idx { self.reference_idx } else { candidate_idx };
+                laggards.observe(Laggard {
+                    behind_nanos: delay.unsigned_abs(),
+                    slot: shred_id.slot(),
+                    index: shred_id.index(),
+                    shred_type: shred_type_name(shred_id.shred_type()),
+                    late_idx,
+                    leader_idx: winner,
+                });
+            }
+            if let Some(alert) = &mut self.alert {
+                alert.observe(candidate_idx, delay, candidate_time.max(reference_time));
+            }
+        }
+        self.wins[winner] += 1;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_match((!warming_up).then(|| Duration::from_nanos(delay.unsigned_abs())));
+        }
+        #[cfg(feature = "otlp")]
+        if let Some(otel) = &self.otel {
+            otel.record_match(winner, (!warming_up).then(|| Duration::from_nanos(delay.unsigned_abs())));
+        }
+        if self.csv_out.is_some() || self.ndjson_out.is_some() || self.binary_log.is_some() || self.match_events.is_some() {
+            let record = MatchRecord {
+                shred_id,
+                source: Arc::clone(&self.source_names[candidate_idx]),
+                reference: Arc::clone(&self.source_names[self.reference_idx]),
+                reference_ts_nanos: self.wall_clock.unix_nanos(reference_time),
+                candidate_ts_nanos: self.wall_clock.unix_nanos(candidate_time),
+                delay_nanos: delay,
+            };
+            if let Some(csv_out) = &mut self.csv_out {
+                if let Err(e) = csv_out.write_match(&record) {
+                    error!(target: "shred_perf::processor", "Failed to write CSV row: {}", e);
+                }
+            }
+            if let Some(ndjson_out) = &mut self.ndjson_out {
+                if let Err(e) = ndjson_out.write_match(&record) {
+                    error!(target: "shred_perf::processor", "Failed to write NDJSON record: {}", e);
+                }
+            }
+            if let Some(binary_log) = &mut self.binary_log {
+                if let Err(e) = binary_log.write_match(&record) {
+                    error!(target: "shred_perf::processor", "Failed to write binary log record: {}", e);
+                }
+            }
+            if let Some(match_events) = &self.match_events {
+                // Only fails while nobody is subscribed.
+                match_events.send(record).ok();
+            }
+            self.unflushed += 1;
+            if self.flush_every.is_some_and(|every| self.unflushed >= every) {
+                self.flush_exports();
+            }
+        }
+        if self.quiet {
+            return;
+        }
+        match self.log_format {
+            LogFormat::Pretty => {
+                let loser = if winner == candidate_idx { self.reference_idx } else { candidate_idx };
+                let (winner, loser) = (&self.source_names[winner], &self.source_names[loser]);
+                // Ties go to the reference, so say so rather than crown it.
+                let outcome = if delay == 0 {
+                    format!("{} tied with {}", winner, loser)
+                } else {
+                    format!("{} led {} by {}", winner, loser, format_delay(delay.abs(), self.delay_unit))
+                };
+                debug!(
+                    target: "shred_perf::processor",
+                    "Matched {}/{} ({}): {} ({} vs {}: {})",
+                    shred_id.slot(),
+                    shred_id.index(),
+                    shred_type_name(shred_id.shred_type()),
+                    outcome,
+                    self.source_names[candidate_idx],
+                    self.source_names[self.reference_idx],
+                    format_delay(delay, self.delay_unit)
+                );
+            }
+            LogFormat::Json => tracing::debug!(
+                target: "shred_perf::processor",
+                event = "match",
+                source = &*self.source_names[candidate_idx],
+                reference = &*self.source_names[self.reference_idx],
+                slot = shred_id.slot(),
+                index = shred_id.index(),
+                shred_type = shred_type_name(shred_id.shred_type()),
+                reference_shred_type = shred_type_name(reference.shred_type),
+                winner = &*self.source_names[winner],
+                delay_nanos = delay,
+            ),
+        }
+    }
+
+    /// Evicts shreds that arrived `retention` or longer before `now`, or with
+    /// `evict_after_slots` the ones whose slot fell too far behind.
+    pub fn cleanup(&mut self, now: Instant) {
+        let timeout = self.retention;
+        let (evict_after_slots, highest_slot) = (self.evict_after_slots, self.highest_slot);
+        let expired = |key: &MatchKey, time: Instant| match evict_after_slots {
+            Some(slots) => key.slot.saturating_add(slots) < highest_slot,
+            None => now.duration_since(time) >= timeout,
+        };
+        let (expiry_report, log_format) = (self.expiry_report, self.log_format);
+        let mut evicted = vec![0; self.source_data.len()];
+        let sources = self.source_data.iter_mut().zip(self.source_stats.iter_mut()).zip(&self.source_names).zip(&mut evicted);
+        for (((data, stats), name), evicted) in sources {
+            data.retain(|key, arrival| {
+                let keep = !expired(key, arrival.time);
+                if !keep {
+                    stats.expired += 1;
+                    if arrival.partners == 0 {
+                        stats.expired_unmatched += 1;
+                        let waited = now.saturating_duration_since(arrival.time);
+                        let age = waited.as_secs_f64() / timeout.as_secs_f64();
+                        stats.eviction_ages[EVICTION_AGE_EDGES.partition_point(|edge| *edge <= age)] += 1;
+                        if expiry_report {
+                            log_unmatched_expiry(log_format, name, key, arrival, waited);
+                        }
+                    }
+                    *evicted += 1;
+                }
+                keep
+            });
+        }
+        for bloom in self.bloom.iter_mut().flatten() {
+            bloom.rotate();
+        }
+        if let Some(first_seen) = &mut self.first_seen {
+            first_seen.retain(|key, (_, time)| !expired(key, *time));
+        }
+        if let Some(agreement) = &mut self.agreement {
+            let before = agreement.deliveries.len();
+            agreement.deliveries.retain(|key, deliveries| !expired(key, *deliveries.iter().min().unwrap()));
+            agreement.incomplete += (before - agreement.deliveries.len()) as u64;
+        }
+        let completed = self.slot_gaps.as_mut().map(|gaps| gaps.expire(now, timeout)).unwrap_or_default();
+        for completed in &completed {
+            self.log_slot_gaps(completed);
+        }
+        if let Some(slots) = self.per_slot_report {
+            while self.slot_delays.len() > slots {
+                self.slot_delays.pop_first();
+            }
+        }
+        if let Some(slots) = self.per_fec_set_report {
+            while self.fec_set_delays.len() > slots {
+                self.fec_set_delays.pop_first();
+            }
+        }
+        if let Some(completeness) = &mut self.completeness {
+            completeness.trim();
+        }
+        let summary = self
+            .source_names
+            .iter()
+            .zip(&evicted)
+            .zip(&self.retired)
+            .filter(|(_, retired)| !**retired)
+            .map(|(evicted, _)| evicted)
+            .map(|(name, evicted)| format!("{} from {}", evicted, name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        match evict_after_slots {
+            Some(slots) => info!(
+                target: "shred_perf::processor",
+                "Cleanup evicted {} (more than {} slots behind slot {})",
+                summary, slots, highest_slot
+            ),
+            None => info!(target: "shred_perf::processor", "Cleanup evicted {} (older than {:?})", summary, timeout),
+        }
+        self.last_evicted = evicted;
+    }
+
+    fn log_slot_gaps(&self, completed: &CompletedSlot) {
+        match self.log_format {
+            LogFormat::Pretty => {
+                let missing = self
+                    .source_names
+                    .iter()
+                    .zip(&completed.missing)
+                    .zip(&self.retired)
+                    .filter(|(_, retired)| !**retired)
+                    .map(|(missing, _)| missing)
+                    .map(|(name, missing)| format!("{} {}", name, missing))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                debug!(target: "shred_perf::processor", "Slot {} missing indices: {}", completed.slot, missing);
+            }
+            LogFormat::Json => {
+                for ((name, missing), _) in self.source_names.iter().zip(&completed.missing).zip(&self.retired).filter(|(_, retired)| !**retired) {
+                    tracing::debug!(target: "shred_perf::processor", event = "slot_gaps", slot = completed.slot, source = &**name, missing);
+                }
+            }
+        }
+    }
+
+    fn log_slot_loss(&self, source_idx: usize, slot: u64, loss: LossTotals) {
+        let source = &*self.source_names[source_idx];
+        match self.log_format {
+            LogFormat::Pretty => debug!(
+                target: "shred_perf::processor",
+                "Slot {} on {}: {} of {} data shred indices missing ({:.1}% estimated loss)",
+                slot,
+                source,
+                loss.missing,
+                loss.expected,
+                pct(loss.missing, loss.expected)
+            ),
+            LogFormat::Json => {
+                debug!(target: "shred_perf::processor", event = "slot_loss", slot, source, missing = loss.missing, expected = loss.expected)
+            }
+        }
+    }
+
+    /// Completes the slots still tracked for gaps, ahead of a final report.
+    pub fn finish(&mut self) {
+        // Gives the terminal back, so the final summary is logged as usual.
+        #[cfg(feature = "tui")]
+        {
+            self.dashboard = None;
+        }
+        let completed = self.slot_gaps.as_mut().map(SlotGaps::finish).unwrap_or_default();
+        for completed in &completed {
+            self.log_slot_gaps(completed);
+        }
+    }
+
+    /// Writes the |delay| HdrHistogram as an interval log covering `start` until now.
+    pub fn write_hdr_log(&self, path: &Path, start: Instant) -> anyhow::Result<()> {
+        export::write_hdr_log(path, &self.hdr, self.wall_clock.unix_nanos(start), start.elapsed())
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    pub fn flush_outputs(&mut self) {
+        self.flush_exports();
+        if let Some(path) = &self.state_file {
+            if let Err(e) = persist::save(path, &self.saved_stats()) {
+                error!(target: "shred_perf::processor", "Failed to save state to {}: {:#}", path.display(), e);
+            }
+        }
+    }
+
+    fn flush_exports(&mut self) {
+        self.unflushed = 0;
+        if let Some(csv_out) = &mut self.csv_out {
+            if let Err(e) = csv_out.flush() {
+                error!(target: "shred_perf::processor", "Failed to flush CSV output: {}", e);
+            }
+        }
+        if let Some(ndjson_out) = &mut self.ndjson_out {
+            if let Err(e) = ndjson_out.flush() {
+                error!(target: "shred_perf::processor", "Failed to flush NDJSON output: {}", e);
+            }
+        }
+        if let Some(binary_log) = &mut self.binary_log {
+            if let Err(e) = binary_log.flush() {
+                error!(target: "shred_perf::processor", "Failed to flush binary log: {}", e);
+            }
+        }
+    }
+
+    fn saved_stats(&self) -> SavedStats {
+        let sources = self
+            .source_names
+            .iter()
+            .zip(&self.source_stats)
+            .zip(&self.wins)
+            .zip(&self.retired)
+            .filter(|(_, retired)| !**retired)
+            .map(|(((name, stats), wins), _)| {
+                let saved = SavedSource {
+                    datagrams: stats.datagrams,
+                    parse_errors: stats.parse_errors,
+                    truncated: stats.truncated,
+                    bytes_received: stats.bytes_received,
+                    wins: *wins,
+                    matched: stats.matched,
+                    led: stats.led,
+                    first_seen: stats.first_seen,
+                    seen_later: stats.seen_later,
+                    seen_later_nanos: stats.seen_later_nanos,
+                    duplicates: stats.duplicates,
+                    duplicate_delay_nanos: stats.duplicate_delay_nanos,
+                    expired: stats.expired,
+                    expired_unmatched: stats.expired_unmatched,
+                };
+                (name.to_string(), saved)
+            })
+            .collect();
+        SavedStats {
+            matched_pairs: self.matched_pairs,
+            data_matched_pairs: self.delay_stats[shred_type_index(ShredType::Data)].matched_pairs,
+            code_matched_pairs: self.delay_stats[shred_type_index(ShredType::Code)].matched_pairs,
+            sources,
+        }
+    }
+
+    /// Picks up the cumulative counters of a previous run.
+    pub fn restore(&mut self, saved: SavedStats) {
+        self.matched_pairs = saved.matched_pairs;
+        self.delay_stats[shred_type_index(ShredType::Data)].matched_pairs = saved.data_matched_pairs;
+        self.delay_stats[shred_type_index(ShredType::Code)].matched_pairs = saved.code_matched_pairs;
+        for ((name, stats), wins) in self.source_names.iter().zip(&mut self.source_stats).zip(&mut self.wins) {
+            let Some(source) = saved.sources.get(name.as_ref()) else {
+                continue;
+            };
+            *stats = SourceStats {
+                datagrams: source.datagrams,
+                parse_errors: source.parse_errors,
+                truncated: source.truncated,
+                stale: 0,
+                invalid_signatures: 0,
+                unknown_leader: 0,
+                bytes_received: source.bytes_received,
+                interval_start_bytes: source.bytes_received,
+                unique_shreds: 0,
+                matched: source.matched,
+                interval_start_unique_shreds: 0,
+                interval_start_matched: source.matched,
+                led: source.led,
+                interval_start_led: source.led,
+                first_seen: source.first_seen,
+                seen_later: source.seen_later,
+                seen_later_nanos: source.seen_later_nanos,
+                duplicates: source.duplicates,
+                duplicate_delay_nanos: source.duplicate_delay_nanos,
+                expired: source.expired,
+                expired_unmatched: source.expired_unmatched,
+                eviction_ages: Default::default(),
+                bloom_hits: 0,
+                bloom_false_positives: 0.0,
+            };
+            *wins = source.wins;
+        }
+        self.interval_start_matched_pairs = self.matched_pairs;
+        for stats in &mut self.delay_stats {
+            stats.interval_start_matched_pairs = stats.matched_pairs;
+        }
+        self.interval_start_wins.clone_from(&self.wins);
+        info!(target: "shred_perf::processor", "Resumed from saved state: {} matched pairs", self.matched_pairs);
+    }
+
+    /// Starts the interval per-interval stats (throughput, match rate, and with
+    /// --reset-stats-each-tick the match counts) are measured over.
+    pub fn start_stats_interval(&mut self) {
+        self.stats_interval_start = Instant::now();
+        self.interval_start_matched_pairs = self.matched_pairs;
+        for stats in &mut self.delay_stats {
+            stats.interval_start_matched_pairs = stats.matched_pairs;
+        }
+        self.interval_start_wins.clone_from(&self.wins);
+        for stats in &mut self.source_stats {
+            stats.interval_start_bytes = stats.bytes_received;
+            stats.interval_start_unique_shreds = stats.unique_shreds;
+            stats.interval_start_matched = stats.matched;
+            stats.interval_start_led = stats.led;
+        }
+    }
+
+    /// Reports `stats()` in the configured format.
+    pub fn report_stats(&mut self) {
+        let snapshot = self.stats();
+        #[cfg(feature = "tui")]
+        let on_dashboard = match &mut self.dashboard {
+            Some(dashboard) => {
+                if let Err(e) = dashboard.draw(&snapshot) {
+                    error!(target: "shred_perf::processor", "Failed to draw the dashboard: {}", e);
+                }
+                true
+            }
+            None => false,
+        };
+        #[cfg(not(feature = "tui"))]
+        let on_dashboard = false;
+        match (self.stats_format, self.log_format) {
+            _ if on_dashboard => {}
+            (StatsFormat::Human, LogFormat::Pretty) => stats::log_human(&snapshot),
+            (StatsFormat::Human, LogFormat::Json) => stats::log_json(&snapshot),
+            (StatsFormat::Kv, _) => stats::print_kv(&snapshot),
+            (StatsFormat::Csv, _) => {
+                stats::print_csv(&snapshot, !self.stats_header_printed);
+                self.stats_header_printed = true;
+            }
+        }
+        if let Some(json_stats_out) = &mut self.json_stats_out {
+            if let Err(e) = json_stats_out.write_stats(&snapshot) {
+                error!(target: "shred_perf::processor", "Failed to write JSON stats: {}", e);
+            }
+        }
+        if let Some((most, most_received, least, least_received)) = self.imbalance() {
+            let (most, least) = (&self.source_names[most], &self.source_names[least]);
+            if least_received == 0 {
+                warn!(
+                    target: "shred_perf::processor",
+                    "Port {} received {} shreds this interval and Port {} none; check mirroring",
+                    most, most_received, least
+                );
+            } else {
+                warn!(
+                    target: "shred_perf::processor",
+                    "Port {} received {:.1}x Port {} this interval ({} vs {} shreds); check mirroring",
+                    most,
+                    most_received as f64 / least_received as f64,
+                    least,
+                    most_received,
+                    least_received
+                );
+            }
+        }
+    }
+
+    // The sources that received the most and the fewest shreds this interval, with their
+    // counts, when the first received more than `imbalance_ratio` times the second.
+    fn imbalance(&self) -> Option<(usize, u64, usize, u64)> {
+        let ratio = self.imbalance_ratio?;
+        let received: Vec<(usize, u64)> = self
+            .source_stats
+            .iter()
+            .map(|stats| stats.unique_shreds - stats.interval_start_unique_shreds)
+            .enumerate()
+            .filter(|(idx, _)| !self.retired[*idx])
+            .collect();
+        let (most, most_received) = received.iter().copied().max_by_key(|(_, received)| *received)?;
+        let (least, least_received) = received.iter().copied().min_by_key(|(_, received)| *received)?;
+        (most_received > 0 && most_received as f64 > least_received as f64 * ratio).then_some((most, most_received, least, least_received))
+    }
+
+    /// Fails if the chosen delay stat vs the reference exceeds `max_ms`, or if nothing
+    /// matched, so a dead feed doesn't pass.
+    pub fn check_delay_threshold(&self, max_ms: f64, stat: ThresholdStat) -> anyhow::Result<()> {
+        let delays = DelaySnapshot::new(
+            "all",
+            self.matched_pairs,
+            self.delay_stats.iter().flat_map(|stats| stats.delays.iter().copied()).collect::<Vec<_>>().into_iter(),
+            self.delay_stats.iter().map(|stats| stats.sum).sum(),
+            &[],
+            self.delay_unit,
+        );
+        if delays.samples == 0 {
+            error!(
+                target: "shred_perf::processor",
+                "FAIL: no matched pairs to compare against {}",
+                self.source_names[self.reference_idx]
+            );
+            anyhow::bail!("no matched pairs to compare against --max-avg-delay-ms {}", max_ms);
+        }
+        let value = match stat {
+            ThresholdStat::Avg => delays.avg,
+            ThresholdStat::P50 => delays.p50,
+            ThresholdStat::P90 => delays.p90,
+            ThresholdStat::P99 => delays.p99,
+        };
+        let limit = (max_ms * 1_000_000.0) as i64;
+        if value > limit {
+            error!(
+                target: "shred_perf::processor",
+                "FAIL: {:?} delay vs {} is {} over {} samples, above the {}ms threshold",
+                stat,
+                self.source_names[self.reference_idx],
+                format_delay(value, self.delay_unit),
+                delays.samples,
+                max_ms
+            );
+            anyhow::bail!("{:?} delay {} exceeds --max-avg-delay-ms {}", stat, format_delay(value, self.delay_unit), max_ms);
+        }
+        info!(
+            target: "shred_perf::processor",
+            "PASS: {:?} delay vs {} is {} over {} samples, within the {}ms threshold",
+            stat,
+            self.source_names[self.reference_idx],
+            format_delay(value, self.delay_unit),
+            delays.samples,
+            max_ms
+        );
+        Ok(())
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        let interval_secs = self.stats_interval_start.elapsed().as_secs_f64();
+        let reference_stats = &self.source_stats[self.reference_idx];
+        let reference_unique = reference_stats.unique_shreds - reference_stats.interval_start_unique_shreds;
+        let sources = self
+            .source_names
+            .iter()
+            .enumerate()
+            .zip(&self.source_data)
+            .zip(&self.source_stats)
+            .zip(self.wins.iter().zip(&self.interval_start_wins))
+            .zip(&self.dropped_events)
+            .zip(&self.jitter)
+            .zip(&self.last_evicted)
+            .map(|(((((((idx, name), data), stats), (wins, interval_start_wins)), dropped), jitter), last_evicted)| SourceSnapshot {
+                name: Arc::clone(name),
+                tracked: data.len(),
+                last_evicted: *last_evicted,
+                datagrams: stats.datagrams,
+                parse_errors: stats.parse_errors,
+                truncated: stats.truncated,
+                stale: stats.stale,
+                invalid_signatures: stats.invalid_signatures,
+                unknown_leader: stats.unknown_leader,
+                duplicates: stats.duplicates,
+                avg_duplicate_delay: stats::rounded_mean(stats.duplicate_delay_nanos as i128, stats.duplicates as usize),
+                dropped: dropped.load(Ordering::Relaxed),
+                bytes_received: stats.bytes_received,
+                bytes_per_sec: if interval_secs > 0.0 {
+                    (stats.bytes_received - stats.interval_start_bytes) as f64 / interval_secs
+                } else {
+                    0.0
+                },
+                // Out of whichever of the pair received more this interval, so a feed losing
+                // packets pulls the rate down whichever side it is on.
+                match_rate: {
+                    let received = (stats.unique_shreds - stats.interval_start_unique_shreds).max(reference_unique);
+                    (idx != self.reference_idx && received > 0)
+                        .then(|| pct(stats.matched - stats.interval_start_matched, received))
+                },
+                wins: if self.reset_stats_each_tick { wins - interval_start_wins } else { *wins },
+                lifetime_wins: self.reset_stats_each_tick.then_some(*wins),
+                led: {
+                    let (led, matched) = if self.reset_stats_each_tick {
+                        (stats.led - stats.interval_start_led, stats.matched - stats.interval_start_matched)
+                    } else {
+                        (stats.led, stats.matched)
+                    };
+                    (matched > 0).then(|| pct(led, matched))
+                },
+                delay_mean: jitter.mean().round() as i64,
+                delay_stddev: jitter.stddev() as i64,
+                expired: stats.expired,
+                expired_unmatched: stats.expired_unmatched,
+                eviction_ages: eviction_age_buckets(&stats.eviction_ages),
+                first_seen: stats.first_seen,
+                seen_later: stats.seen_later,
+                avg_seen_later: stats::rounded_mean(stats.seen_later_nanos as i128, stats.seen_later as usize),
+                missing_indices: self.slot_gaps.as_ref().map_or(0, |gaps| gaps.missing()[idx]),
+                inter_arrival: self.inter_arrival.as_ref().map(|inter_arrival| {
+                    let inter_arrival = &inter_arrival[idx];
+                    InterArrivalSnapshot {
+                        mean: inter_arrival.gaps.mean().round() as i64,
+                        stddev: inter_arrival.gaps.stddev() as i64,
+                        max: inter_arrival.max.as_nanos() as i64,
+                    }
+                }),
+                reordering: self.reordering.as_ref().map(|reordering| reordering[idx].snapshot()),
+                bloom: self.bloom.as_ref().map(|bloom| {
+                    let bloom = &bloom[idx];
+                    BloomSnapshot {
+                        hits: stats.bloom_hits,
+                        false_positives: stats.bloom_false_positives,
+                        entries: bloom.entries(),
+                        bytes: bloom.bytes(),
+                        exact_bytes: bloom.entries() as usize * EXACT_ENTRY_BYTES,
+                    }
+                }),
+                lead_margins: self
+                    .lead_margins
+                    .as_ref()
+                    .filter(|_| idx != self.reference_idx)
+                    .map(|lead_margins| lead_margins[idx].snapshot(&self.histogram_edges, self.delay_unit)),
+                alerting: self.alert.as_ref().filter(|_| idx != self.reference_idx).map(|alert| alert.firing(idx)),
+                baseline_offset: self.baseline.as_ref().map(|(offsets, _)| offsets[idx]),
+                overlap: (idx != self.reference_idx).then(|| {
+                    let both = stats.matched - stats.interval_start_matched;
+                    OverlapSnapshot {
+                        only_source: (stats.unique_shreds - stats.interval_start_unique_shreds).saturating_sub(both),
+                        only_reference: reference_unique.saturating_sub(both),
+                        both,
+                    }
+                }),
+                truth: self.truth.as_ref().map(|truth| {
+                    let score = &truth.scores[idx];
+                    TruthSnapshot {
+                        delivered: score.delivered,
+                        expected: truth.expected.len() as u64,
+                        unexpected: score.unexpected,
+                        latency_mean: truth.timestamps.then(|| score.latency.mean().round() as i64),
+                        latency_stddev: truth.timestamps.then(|| score.latency.stddev() as i64),
+                    }
+                }),
+                index_loss: self.index_loss.as_ref().map(|index_loss| {
+                    let totals = index_loss.totals(idx);
+                    IndexLossSnapshot {
+                        slots: totals.slots,
+                        expected: totals.expected,
+                        missing: totals.missing,
+                    }
+                }),
+                raw_delay_mean: self.baseline.as_ref().filter(|_| idx != self.reference_idx).map(|(_, raw)| raw[idx].mean().round() as i64),
+            })
+            .zip(&self.retired)
+            .filter(|(_, retired)| !**retired)
+            .map(|(source, _)| source)
+            .collect();
+
+        let delays = SHRED_TYPES
+            .into_iter()
+            .filter(|shred_type| self.filter_type.is_none_or(|t| t == *shred_type))
+            .map(|shred_type| {
+                let stats = &self.delay_stats[shred_type_index(shred_type)];
+                let mut snapshot = stats.snapshot(shred_type_name(shred_type), &self.histogram_edges, self.delay_unit);
+                if self.reset_stats_each_tick {
+                    snapshot.matched_pairs -= stats.interval_start_matched_pairs;
+                }
+                snapshot
+            })
+            .collect();
+
+        let per_slot = self.per_slot_report.map(|slots| {
+            let mut per_slot: Vec<SlotSnapshot> = self
+                .slot_delays
+                .iter()
+                .rev()
+                .take(slots)
+                .map(|(slot, delays)| SlotSnapshot {
+                    slot: *slot,
+                    avg: stats::mean_delay(delays.iter()),
+                    samples: delays.len(),
+                })
+                .collect();
+            per_slot.reverse();
+            per_slot
+        });
+        let per_fec_set = self.per_fec_set_report.map(|slots| {
+            let mut per_fec_set: Vec<FecSlotSnapshot> = self
+                .fec_set_delays
+                .iter()
+                .rev()
+                .take(slots)
+                .map(|(slot, sets)| FecSlotSnapshot::new(*slot, sets))
+                .collect();
+            per_fec_set.reverse();
+            per_fec_set
+        });
+
+        let completeness = self.completeness.as_ref().map(|completeness| {
+            completeness
+                .snapshot(self.source_names.len())
+                .into_iter()
+                .map(|slot| CompletenessSnapshot {
+                    slot: slot.slot,
+                    expected: slot.expected,
+                    delivered: slot.delivered.into_iter().zip(&self.retired).filter(|(_, retired)| !**retired).map(|(delivered, _)| delivered).collect(),
+                })
+                .collect()
+        });
+
+        let per_index = self.index_buckets.as_ref().map(|(edges, buckets)| {
+            buckets
+                .iter()
+                .enumerate()
+                .map(|(idx, delays)| {
+                    let label = match (idx.checked_sub(1).map(|prev| edges[prev]), edges.get(idx)) {
+                        (None, Some(upper)) => format!("0-{}", upper.saturating_sub(1)),
+                        (Some(lower), Some(upper)) => format!("{}-{}", lower, upper - 1),
+                        (Some(lower), None) => format!("{}+", lower),
+                        (None, None) => "all".to_string(),
+                    };
+                    IndexBucketSnapshot {
+                        label,
+                        avg: delays.mean().round() as i64,
+                        samples: delays.count(),
+                    }
+                })
+                .collect()
+        });
+
+        StatsSnapshot {
+            // Among the sources still reported.
+            reference_idx: self.retired[..self.reference_idx].iter().filter(|retired| !**retired).count(),
+            retention: self.retention,
+            evict_after_slots: self.evict_after_slots,
+            signatures_verified: self.signatures_verified,
+            hdr: HdrSnapshot::new(&self.hdr),
+            matched_pairs: if self.reset_stats_each_tick {
+                self.matched_pairs - self.interval_start_matched_pairs
+            } else {
+                self.matched_pairs
+            },
+            lifetime_matched_pairs: self.reset_stats_each_tick.then_some(self.matched_pairs),
+            cross_type_matches: (self.match_key == MatchKeyArg::SlotIndex).then_some(self.cross_type_matches),
+            sources,
+            delays,
+            per_slot,
+            per_fec_set,
+            completeness,
+            per_index,
+            laggards: self.laggards.as_ref().map(|laggards| laggards.snapshot(&self.source_names)),
+            agreement: self.agreement.as_ref().map(|agreement| {
+                let sources = self.retired.iter().filter(|retired| !**retired).count();
+                let snapshot = |label, stats: &DelayStats| stats.snapshot(label, &self.histogram_edges, self.delay_unit);
+                AgreementSnapshot {
+                    sources,
+                    quorum_sources: quorum_of(sources),
+                    quorum: snapshot("quorum", &agreement.quorum),
+                    all: snapshot("all", &agreement.all),
+                    incomplete: agreement.incomplete,
+                }
+            }),
+            min_samples: self.min_samples,
+            first_seen_wins: self.first_seen.is_some(),
+            gap_slots: self.slot_gaps.as_ref().map(SlotGaps::completed_slots),
+            max_slot_age: self.max_slot_age,
+            sample_rate: self.sample_rate,
+            delay_unit: self.delay_unit,
+            time: Utc::now(),
+            uptime: self.start_time.elapsed(),
+            first_match_after: self.first_match.map(|first| first.saturating_duration_since(self.start_time)),
+        }
+    }
+}
+
+// One line per shred evicted without a match, for --no-match-expiry-report.
+fn log_unmatched_expiry(log_format: LogFormat, source: &str, key: &MatchKey, arrival: &Arrival, waited: Duration) {
+    let shred_type = shred_type_name(arrival.shred_type);
+    match log_format {
+        LogFormat::Pretty => info!(
+            target: "shred_perf::processor",
+            "Expired unmatched {}/{} ({}) from {} after {:?}",
+            key.slot, key.index, shred_type, source, waited
+        ),
+        LogFormat::Json => tracing::info!(
+            target: "shred_perf::processor",
+            event = "unmatched_expiry",
+            source,
+            slot = key.slot,
+            index = key.index,
+            shred_type,
+            age_nanos = waited.as_nanos() as u64,
+        ),
+    }
+}
+
+// Whether `shred_id` is among the 1 in `rate` shreds --sample-rate keeps. Ignores the shred
+// type, so --match-key slot-index pairs still match.
+fn sampled(shred_id: ShredId, rate: u64) -> bool {
+    // splitmix64's finalizer, so a sample isn't just every Nth index.
+    let mut hash = shred_id.slot().wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ u64::from(shred_id.index());
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (hash ^ (hash >> 31)).is_multiple_of(rate)
+}
+
+fn signed_nanos(time: Instant, reference: Instant) -> i64 {
+    if time >= reference {
+        time.duration_since(reference).as_nanos() as i64
+    } else {
+        -(reference.duration_since(time).as_nanos() as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn processor() -> Processor {
+        Processor::new(vec!["a".into(), "b".into()], ProcessorConfig { quiet: true, ..Default::default() }).unwrap()
+    }
+
+    fn data_shred(index: u32) -> ShredId {
+        ShredId::new(1000, index, ShredType::Data)
+    }
+
+    fn data_delays(stats: &StatsSnapshot) -> &DelaySnapshot {
+        stats.delays.iter().find(|delay| delay.shred_type == "data").unwrap()
+    }
+
+    #[test]
+    fn matches_when_the_reference_arrives_first() {
+        let mut processor = processor();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(250));
+
+        let stats = processor.stats();
+        assert_eq!(stats.matched_pairs, 1);
+        assert_eq!(data_delays(&stats).avg, 250_000);
+        assert_eq!(stats.sources[0].wins, 1);
+    }
+
+    #[test]
+    fn matches_when_the_candidate_arrives_first() {
+        let mut processor = processor();
+        let t0 = Instant::now();
+        processor.record(1, data_shred(0), t0);
+        processor.record(0, data_shred(0), t0 + Duration::from_micros(250));
+
+        let stats = processor.stats();
+        assert_eq!(stats.matched_pairs, 1);
+        assert_eq!(data_delays(&stats).avg, -250_000);
+        assert_eq!(data_delays(&stats).negative, 1);
+        assert_eq!(stats.sources[1].wins, 1);
+    }
+
+    #[test]
+    fn average_delay_rounds_to_the_nearest_nanosecond_over_the_window() {
+        let config = ProcessorConfig {
+            delay_window: 2,
+            quiet: true,
+            ..Default::default()
+        };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        let mut record = |index, nanos: i64| {
+            let later = t0 + Duration::from_nanos(nanos.unsigned_abs());
+            let (reference, candidate) = if nanos < 0 { (later, t0) } else { (t0, later) };
+            processor.record(0, data_shred(index), reference);
+            processor.record(1, data_shred(index), candidate);
+            let stats = processor.stats();
+            (data_delays(&stats).avg, stats.sources[1].delay_mean)
+        };
+        assert_eq!(record(0, 100), (100, 100));
+        // 100.5ns, which truncating would report as 100; the per-source mean agrees.
+        assert_eq!(record(1, 101), (101, 101));
+        // 100 has left the window: (101 + 104) / 2, while the per-source mean covers all three.
+        assert_eq!(record(2, 104), (103, 102));
+        // (104 - 105) / 2 rounds away from zero too.
+        assert_eq!(record(3, -105).0, -1);
+    }
+
+    #[test]
+    fn matches_once_however_the_channel_interleaves_the_copies() {
+        // Each source's events stay in order through the channel, but the two sources'
+        // can interleave any way, and processing order needn't follow the timestamps.
+        // b's first copy arrived 200µs before a's; each source then repeats the shred.
+        let t0 = Instant::now();
+        let at = |us| t0 + Duration::from_micros(us);
+        let a = [(0, at(300)), (0, at(400))];
+        let b = [(1, at(100)), (1, at(500))];
+        let interleavings = [
+            [a[0], a[1], b[0], b[1]],
+            [a[0], b[0], a[1], b[1]],
+            [a[0], b[0], b[1], a[1]],
+            [b[0], a[0], a[1], b[1]],
+            [b[0], a[0], b[1], a[1]],
+            [b[0], b[1], a[0], a[1]],
+        ];
+        for events in interleavings {
+            let mut processor = processor();
+            for (source_idx, time) in events {
+                processor.record(source_idx, data_shred(0), time);
+            }
+
+            let stats = processor.stats();
+            assert_eq!(stats.matched_pairs, 1);
+            assert_eq!(data_delays(&stats).avg, -200_000);
+            assert_eq!((stats.sources[0].wins, stats.sources[1].wins), (0, 1));
+            assert_eq!((stats.sources[0].duplicates, stats.sources[1].duplicates), (1, 1));
+        }
+    }
+
+    #[test]
+    fn agreement_latency_times_quorum_and_all_sources_from_the_first_delivery() {
+        let mut processor = Processor::new(
+            vec!["a".into(), "b".into(), "c".into()],
+            ProcessorConfig {
+                agreement_latency: true,
+                ..ProcessorConfig::default()
+            },
+        )
+        .unwrap();
+        let t0 = Instant::now();
+        // c's copy is processed last but arrived first; a repeat from b doesn't count.
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(300));
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(400));
+        processor.record(0, data_shred(0), t0 + Duration::from_micros(700));
+        processor.record(2, data_shred(0), t0 + Duration::from_micros(100));
+        // Only two sources ever deliver shred 1.
+        processor.record(0, data_shred(1), t0);
+        processor.record(2, data_shred(1), t0 + Duration::from_micros(50));
+
+        let agreement = processor.stats().agreement.unwrap();
+        assert_eq!((agreement.quorum_sources, agreement.sources), (2, 3));
+        assert_eq!(agreement.quorum.matched_pairs, 2);
+        assert_eq!(agreement.all.matched_pairs, 1);
+        assert_eq!(agreement.all.avg, 600_000);
+
+        processor.cleanup(t0 + Duration::from_secs(120));
+        assert_eq!(processor.stats().agreement.unwrap().incomplete, 1);
+
+        // Expired by its earliest copy, even though that copy was processed second.
+        let (t1, retention) = (t0 + Duration::from_secs(200), Duration::from_secs(60));
+        processor.record(0, data_shred(2), t1 + retention);
+        processor.record(2, data_shred(2), t1);
+        processor.cleanup(t1 + retention);
+        assert_eq!(processor.stats().agreement.unwrap().incomplete, 2);
+    }
+
+    #[test]
+    fn completeness_counts_data_shreds_per_slot_and_keeps_the_latest_slots() {
+        let config = |shreds_per_slot| ProcessorConfig {
+            quiet: true,
+            completeness_report: Some(2),
+            shreds_per_slot,
+            ..Default::default()
+        };
+        let t0 = Instant::now();
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config(None)).unwrap();
+        for index in [0, 1, 2, 2] {
+            processor.record(0, ShredId::new(10, index, ShredType::Data), t0);
+        }
+        processor.record(1, ShredId::new(10, 1, ShredType::Data), t0);
+        processor.record(1, ShredId::new(10, 0, ShredType::Code), t0);
+        // Learned from the highest index; repeats and coding shreds don't count.
+        let completeness = processor.stats().completeness.unwrap();
+        assert_eq!((completeness[0].slot, completeness[0].expected, completeness[0].delivered.clone()), (10, 3, vec![3, 1]));
+
+        processor.record(1, ShredId::new(11, 0, ShredType::Data), t0);
+        processor.record(1, ShredId::new(12, 0, ShredType::Data), t0);
+        processor.cleanup(t0);
+        let completeness = processor.stats().completeness.unwrap();
+        let slots: Vec<_> = completeness.iter().map(|slot| (slot.slot, slot.expected, slot.delivered.clone())).collect();
+        assert_eq!(slots, [(11, 1, vec![0, 1]), (12, 1, vec![0, 1])]);
+
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config(Some(64))).unwrap();
+        processor.record(0, ShredId::new(10, 0, ShredType::Data), t0);
+        let completeness = processor.stats().completeness.unwrap();
+        assert_eq!((completeness[0].expected, completeness[0].delivered.clone()), (64, vec![1, 0]));
+    }
+
+    #[test]
+    fn index_loss_counts_each_sources_own_index_gaps_once_a_slot_leaves_the_window() {
+        let config = ProcessorConfig {
+            quiet: true,
+            index_loss: Some(1),
+            ..Default::default()
+        };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        // a skips 3 and 5 of 2..=6; b starts late at 4 but misses nothing after it.
+        for index in [2, 4, 6] {
+            processor.record(0, ShredId::new(10, index, ShredType::Data), t0);
+        }
+        processor.record(0, ShredId::new(10, 1, ShredType::Code), t0);
+        for index in [4, 5, 6] {
+            processor.record(1, ShredId::new(10, index, ShredType::Data), t0);
+        }
+        let loss = |processor: &Processor, idx: usize| {
+            let loss = processor.stats().sources[idx].index_loss.as_ref().map(|loss| (loss.slots, loss.expected, loss.missing));
+            loss.unwrap()
+        };
+        assert_eq!(loss(&processor, 0), (0, 0, 0));
+
+        processor.record(0, ShredId::new(11, 0, ShredType::Data), t0);
+        assert_eq!(loss(&processor, 0), (1, 5, 2));
+        assert_eq!(loss(&processor, 1), (0, 0, 0));
+        processor.record(1, ShredId::new(11, 0, ShredType::Data), t0);
+        assert_eq!(loss(&processor, 1), (1, 3, 0));
+        // A straggler for a counted slot doesn't reopen it.
+        processor.record(0, ShredId::new(10, 3, ShredType::Data), t0);
+        assert_eq!(loss(&processor, 0), (1, 5, 2));
+    }
+
+    #[test]
+    fn sources_are_scored_against_the_reference_shreds() {
+        let t0 = Instant::now();
+        let recorded = 1_700_000_000_000_000_000;
+        let reference = (0..3).map(|index| (data_shred(index), Some(recorded))).collect();
+        let mut processor = Processor::new(
+            vec!["a".into(), "b".into()],
+            ProcessorConfig {
+                quiet: true,
+                reference_shreds: Some(ReferenceShreds::new(reference)),
+                wall_clock: WallClock::anchored(t0, recorded),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let later = t0 + Duration::from_micros(100);
+        for index in [0, 1, 0, 5] {
+            processor.record(0, data_shred(index), later);
+        }
+        // Outside the recorded slots, so neither expected nor unexpected.
+        processor.record(0, ShredId::new(2000, 0, ShredType::Data), later);
+        processor.record(1, data_shred(2), t0);
+
+        let stats = processor.stats();
+        let (a, b) = (stats.sources[0].truth.as_ref().unwrap(), stats.sources[1].truth.as_ref().unwrap());
+        assert_eq!((a.delivered, a.expected, a.unexpected), (2, 3, 1));
+        assert_eq!(a.latency_mean, Some(100_000));
+        assert_eq!((b.delivered, b.unexpected, b.latency_mean), (1, 0, Some(0)));
+    }
+
+    #[test]
+    fn different_shred_types_do_not_match_by_default() {
+        let mut processor = processor();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, ShredId::new(1000, 0, ShredType::Code), t0);
+
+        assert_eq!(processor.stats().matched_pairs, 0);
+    }
+
+    #[test]
+    fn led_is_a_share_of_the_sources_own_matches() {
+        let mut processor =
+            Processor::new(vec!["a".into(), "b".into(), "c".into()], ProcessorConfig { quiet: true, ..Default::default() })
+                .unwrap();
+        let t0 = Instant::now();
+        for index in 0..4 {
+            processor.record(0, data_shred(index), t0);
+            processor.record(1, data_shred(index), t0 + Duration::from_micros(100));
+        }
+        processor.record(2, data_shred(0), t0 - Duration::from_micros(100));
+
+        let stats = processor.stats();
+        assert_eq!(stats.sources[0].led, Some(80.0));
+        assert_eq!(stats.sources[1].led, Some(0.0));
+        assert_eq!(stats.sources[2].led, Some(100.0));
+    }
+
+    #[test]
+    fn shreds_too_far_behind_the_tip_are_discarded() {
+        let mut processor =
+            Processor::new(vec!["a".into(), "b".into()], ProcessorConfig { quiet: true, max_slot_age: Some(2), ..Default::default() })
+                .unwrap();
+        let t0 = Instant::now();
+        processor.record(0, ShredId::new(1010, 0, ShredType::Data), t0);
+        processor.record(0, ShredId::new(1008, 0, ShredType::Data), t0);
+        processor.record(1, ShredId::new(1008, 0, ShredType::Data), t0);
+        processor.record(1, ShredId::new(1007, 0, ShredType::Data), t0);
+
+        let stats = processor.stats();
+        assert_eq!(stats.matched_pairs, 1);
+        assert_eq!(stats.sources[1].stale, 1);
+    }
+
+    #[test]
+    fn duplicates_are_counted_but_not_matched_again() {
+        let mut processor = processor();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(100));
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(300));
+        processor.record(0, data_shred(0), t0 + Duration::from_micros(400));
+
+        let stats = processor.stats();
+        assert_eq!(stats.matched_pairs, 1);
+        assert_eq!(data_delays(&stats).avg, 100_000);
+        assert_eq!(stats.sources[0].duplicates, 1);
+        assert_eq!(stats.sources[0].avg_duplicate_delay, 400_000);
+        assert_eq!(stats.sources[1].duplicates, 1);
+        assert_eq!(stats.sources[1].avg_duplicate_delay, 200_000);
+        assert_eq!(stats.sources[1].tracked, 1);
+    }
+
+    #[test]
+    fn cleanup_evicts_only_stale_entries() {
+        let mut processor = processor();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, data_shred(0), t0);
+        processor.record(0, data_shred(1), t0);
+        processor.record(0, data_shred(2), t0 + Duration::from_secs(30));
+
+        processor.cleanup(t0 + Duration::from_secs(61));
+        let stats = processor.stats();
+        assert_eq!(stats.sources[0].tracked, 1);
+        assert_eq!(stats.sources[0].last_evicted, 2);
+        assert_eq!(stats.sources[0].expired, 2);
+        assert_eq!(stats.sources[0].expired_unmatched, 1);
+        assert_eq!(stats.sources[1].expired_unmatched, 0);
+
+        // An evicted shred arriving again starts over rather than matching.
+        processor.record(1, data_shred(1), t0 + Duration::from_secs(62));
+        assert_eq!(processor.stats().matched_pairs, 1);
+    }
+
+    #[test]
+    fn unmatched_evictions_are_bucketed_by_age() {
+        let mut processor = processor();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, data_shred(0), t0);
+        processor.record(0, data_shred(1), t0);
+        processor.cleanup(t0 + Duration::from_secs(61));
+        processor.record(1, data_shred(2), t0 + Duration::from_secs(61));
+        processor.cleanup(t0 + Duration::from_secs(261));
+
+        let stats = processor.stats();
+        let counts = |idx: usize| stats.sources[idx].eviction_ages.iter().map(|bucket| bucket.count).collect::<Vec<_>>();
+        // The matched entries don't count.
+        assert_eq!(counts(0), [0, 0, 1, 0, 0, 0]);
+        assert_eq!(counts(1), [0, 0, 0, 0, 1, 0]);
+        assert_eq!(stats.sources[0].eviction_ages[2].label, "1-1.5x");
+    }
+
+    #[test]
+    fn top_laggards_keeps_the_worst_pairs_worst_first() {
+        let config = ProcessorConfig { top_laggards: Some(2), quiet: true, ..Default::default() };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        // b trails by 3ms, leads by 5ms, trails by 1ms and trails by 4ms.
+        for (index, a_ms, b_ms) in [(0, 0, 3), (1, 5, 0), (2, 0, 1), (3, 0, 4)] {
+            processor.record(0, data_shred(index), t0 + Duration::from_millis(a_ms));
+            processor.record(1, data_shred(index), t0 + Duration::from_millis(b_ms));
+        }
+
+        let laggards = processor.stats().laggards.unwrap();
+        let worst: Vec<_> = laggards.iter().map(|l| (l.index, &*l.late, &*l.leader, l.behind)).collect();
+        assert_eq!(worst, [(1, "a", "b", 5_000_000), (3, "b", "a", 4_000_000)]);
+    }
+
+    #[test]
+    fn alert_fires_after_a_sustained_window_and_resolves() {
+        let config = ProcessorConfig {
+            alert_above: Some(1_000_000),
+            alert_window: Duration::from_secs(3),
+            quiet: true,
+            ..Default::default()
+        };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        // b trails by 2ms for 5s, then keeps up for 2s, a match every 100ms.
+        for index in 0..70 {
+            let lag = if index < 50 { 2 } else { 0 };
+            let time = t0 + Duration::from_millis(index as u64 * 100);
+            processor.record(0, data_shred(index), time);
+            processor.record(1, data_shred(index), time + Duration::from_millis(lag));
+            if index == 49 {
+                let alerting: Vec<_> = processor.stats().sources.iter().map(|source| source.alerting).collect();
+                assert_eq!(alerting, [None, Some(true)]);
+            }
+        }
+
+        assert_eq!(processor.stats().sources[1].alerting, Some(false));
+    }
+
+    #[test]
+    fn delay_threshold_fails_without_matched_pairs() {
+        let mut processor = processor();
+        assert!(processor.check_delay_threshold(1.0, ThresholdStat::Avg).is_err());
+
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(500));
+        assert!(processor.check_delay_threshold(1.0, ThresholdStat::Avg).is_ok());
+        assert!(processor.check_delay_threshold(0.1, ThresholdStat::P99).is_err());
+    }
+
+    #[test]
+    fn sources_added_and_removed_while_running() {
+        let mut processor = processor();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(100));
+
+        let c = processor.add_source("c".into(), &BTreeMap::new());
+        processor.record(c, data_shred(0), t0 + Duration::from_micros(200));
+        processor.record(0, data_shred(1), t0);
+        processor.record(c, data_shred(1), t0 + Duration::from_micros(300));
+        processor.remove_source(1).unwrap();
+        processor.record(1, data_shred(1), t0);
+        assert!(processor.remove_source(0).is_err());
+
+        let stats = processor.stats();
+        let sources: Vec<_> = stats.sources.iter().map(|source| (&*source.name, source.datagrams)).collect();
+        assert_eq!(sources, [("a", 2), ("c", 2)]);
+        // c matched both of a's shreds, including the one a had before c joined; b's last
+        // copy arrived after it was removed.
+        assert_eq!(stats.matched_pairs, 3);
+        assert_eq!(stats.reference(), "a");
+    }
+
+    #[test]
+    fn baseline_offsets_adjust_delays_and_keep_the_raw_mean() {
+        // b's path is known to be 300µs longer than a's.
+        let config = ProcessorConfig { baseline_offsets: Some(vec![0, 300_000]), quiet: true, ..Default::default() };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0 + Duration::from_micros(100));
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(300));
+
+        let stats = processor.stats();
+        let b = &stats.sources[1];
+        assert_eq!((b.delay_mean, b.raw_delay_mean, b.baseline_offset), (-100_000, Some(200_000), Some(300_000)));
+        // Once adjusted, b led.
+        assert_eq!((stats.sources[0].wins, b.wins), (0, 1));
+        assert_eq!(stats.sources[0].raw_delay_mean, None);
+    }
+
+    #[test]
+    fn overlap_counts_shreds_seen_on_one_or_both_sources_this_interval() {
+        let mut processor = processor();
+        let t0 = Instant::now();
+        for index in 0..3 {
+            processor.record(0, data_shred(index), t0);
+            processor.record(1, data_shred(index), t0);
+        }
+        processor.record(0, data_shred(3), t0);
+        processor.record(1, data_shred(4), t0);
+        processor.record(1, data_shred(5), t0);
+        let overlap = |processor: &Processor| {
+            let stats = processor.stats();
+            let overlap = stats.sources[1].overlap.as_ref().unwrap();
+            (overlap.only_reference, overlap.only_source, overlap.both)
+        };
+        assert_eq!(overlap(&processor), (1, 2, 3));
+
+        processor.start_stats_interval();
+        processor.record(0, data_shred(6), t0);
+        assert_eq!(overlap(&processor), (1, 0, 0));
+        assert!(processor.stats().sources[0].overlap.is_none());
+    }
+
+    #[test]
+    fn json_stats_out_appends_one_object_per_report() {
+        let path = std::env::temp_dir().join(format!("shred-perf-stats-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = ProcessorConfig {
+            json_stats_out: Some(JsonStatsWriter::create(&path).unwrap()),
+            stats_format: StatsFormat::Kv,
+            quiet: true,
+            ..Default::default()
+        };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.report_stats();
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(250));
+        processor.report_stats();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let reports: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let matched: Vec<_> = reports.iter().map(|report| report["matched_pairs"].as_u64().unwrap()).collect();
+        assert_eq!(matched, [0, 1]);
+        assert_eq!(reports[1]["sources"][1]["delay_mean_nanos"], 250_000);
+        assert_eq!(reports[1]["delays"][0]["p50_nanos"], 250_000);
+    }
+
+    #[test]
+    fn flush_every_writes_the_csv_out_every_n_matches() {
+        let path = std::env::temp_dir().join(format!("shred-perf-flush-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = ProcessorConfig {
+            csv_out: Some(CsvWriter::create(&path, DelayUnit::Ns).unwrap()),
+            flush_every: Some(2),
+            quiet: true,
+            ..Default::default()
+        };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        let rows = |path: &PathBuf| std::fs::read_to_string(path).unwrap().lines().count();
+        for index in 0..3 {
+            processor.record(0, data_shred(index), t0);
+            processor.record(1, data_shred(index), t0 + Duration::from_micros(100));
+        }
+        // The header and the first two matches; the third waits for the next flush.
+        assert_eq!(rows(&path), 3);
+        processor.flush_outputs();
+        assert_eq!(rows(&path), 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_datagrams_are_not_parse_errors() {
+        let mut processor = processor();
+        processor.record_truncated(0, 2048);
+        processor.record_parse_failure(0);
+
+        let stats = processor.stats();
+        assert_eq!(stats.sources[0].datagrams, 2);
+        assert_eq!(stats.sources[0].truncated, 1);
+        assert_eq!(stats.sources[0].parse_errors, 1);
+    }
+
+    #[test]
+    fn restored_stats_keep_truncated_datagrams_apart_from_parsed_ones() {
+        let mut previous = processor();
+        previous.record_truncated(0, 2048);
+        previous.record_parse_failure(0);
+        let saved = serde_json::to_vec(&previous.saved_stats()).unwrap();
+
+        let mut restored = processor();
+        restored.restore(serde_json::from_slice(&saved).unwrap());
+        let stats = restored.stats();
+        assert_eq!((stats.sources[0].datagrams, stats.sources[0].truncated, stats.sources[0].parse_errors), (2, 1, 1));
+    }
+
+    #[test]
+    fn restored_stats_keep_first_arrivals_in_step_with_wins() {
+        let processor = || {
+            let config = ProcessorConfig { first_seen_wins: true, quiet: true, ..Default::default() };
+            Processor::new(vec!["a".into(), "b".into()], config).unwrap()
+        };
+        let mut previous = processor();
+        let t0 = Instant::now();
+        previous.record(1, data_shred(0), t0);
+        previous.record(0, data_shred(0), t0 + Duration::from_micros(100));
+        let saved = serde_json::to_vec(&previous.saved_stats()).unwrap();
+
+        let mut restored = processor();
+        restored.restore(serde_json::from_slice(&saved).unwrap());
+        restored.record(0, data_shred(1), t0);
+        restored.record(1, data_shred(1), t0 + Duration::from_micros(100));
+        let stats = restored.stats();
+        assert_eq!((stats.sources[1].wins, stats.sources[1].led), (1, Some(50.0)));
+        assert_eq!((stats.sources[1].first_seen, stats.sources[1].seen_later), (1, 1));
+    }
+
+    #[test]
+    fn reset_stats_each_tick_reports_the_interval_and_lifetime() {
+        let config = ProcessorConfig { reset_stats_each_tick: true, quiet: true, ..Default::default() };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(100));
+        processor.start_stats_interval();
+        processor.record(1, data_shred(1), t0);
+        processor.record(0, data_shred(1), t0 + Duration::from_micros(100));
+        processor.record(1, data_shred(2), t0);
+        processor.record(0, data_shred(2), t0 + Duration::from_micros(100));
+
+        let stats = processor.stats();
+        assert_eq!(stats.matched_pairs, 2);
+        assert_eq!(stats.lifetime_matched_pairs, Some(3));
+        assert_eq!((stats.sources[0].wins, stats.sources[0].lifetime_wins), (0, Some(1)));
+        assert_eq!((stats.sources[1].wins, stats.sources[1].lifetime_wins), (2, Some(2)));
+        assert_eq!(stats.sources[1].led, Some(100.0));
+        assert_eq!(stats.delays[0].matched_pairs, 2);
+
+        processor.start_stats_interval();
+        processor.record(0, ShredId::new(1000, 0, ShredType::Code), t0);
+        processor.record(1, ShredId::new(1000, 0, ShredType::Code), t0 + Duration::from_micros(100));
+        let stats = processor.stats();
+        assert_eq!(stats.matched_pairs, 1);
+        assert_eq!(stats.delays.iter().map(|delays| delays.matched_pairs).collect::<Vec<_>>(), [0, 1]);
+    }
+
+    #[test]
+    fn evict_after_slots_evicts_by_slot_not_age() {
+        let config = ProcessorConfig { evict_after_slots: Some(2), quiet: true, ..Default::default() };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        processor.record(0, ShredId::new(10, 0, ShredType::Data), t0);
+        processor.record(0, ShredId::new(12, 0, ShredType::Data), t0);
+        processor.record(0, ShredId::new(13, 0, ShredType::Data), t0);
+        processor.cleanup(t0);
+
+        let stats = processor.stats();
+        assert_eq!(stats.sources[0].tracked, 2);
+        assert_eq!(stats.sources[0].expired_unmatched, 1);
+    }
+
+    #[test]
+    fn flags_a_source_receiving_far_more_than_another() {
+        let config = ProcessorConfig { imbalance_ratio: Some(10.0), quiet: true, ..Default::default() };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        for index in 0..10 {
+            processor.record(0, data_shred(index), t0);
+        }
+        processor.record(1, data_shred(0), t0);
+        assert_eq!(processor.imbalance(), None);
+
+        processor.record(0, data_shred(10), t0);
+        assert_eq!(processor.imbalance(), Some((0, 11, 1, 1)));
+        processor.start_stats_interval();
+        assert_eq!(processor.imbalance(), None);
+    }
+
+    #[test]
+    fn buckets_delays_by_shred_index() {
+        let config = ProcessorConfig { index_buckets: Some(vec![32, 64]), quiet: true, ..Default::default() };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        for (index, delay_us) in [(0, 100), (31, 200), (40, 300), (70, 400)] {
+            processor.record(0, data_shred(index), t0);
+            processor.record(1, data_shred(index), t0 + Duration::from_micros(delay_us));
+        }
+
+        let stats = processor.stats();
+        let per_index = stats.per_index.unwrap();
+        let buckets: Vec<_> = per_index.iter().map(|bucket| (bucket.label.as_str(), bucket.avg, bucket.samples)).collect();
+        assert_eq!(buckets, [("0-31", 150_000, 2), ("32-63", 300_000, 1), ("64+", 400_000, 1)]);
+    }
+
+    #[test]
+    fn sampling_keeps_the_same_shreds_on_every_source() {
+        let config = ProcessorConfig { sample_rate: Some(4), quiet: true, ..Default::default() };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        for index in 0..1000 {
+            processor.record(0, data_shred(index), t0);
+            processor.record(1, data_shred(index), t0 + Duration::from_micros(100));
+        }
+
+        let stats = processor.stats();
+        assert_eq!(stats.sources[0].tracked, stats.matched_pairs);
+        assert!((200..300).contains(&stats.matched_pairs), "{} of 1000 sampled", stats.matched_pairs);
+    }
+
+    #[test]
+    fn compare_mode_splits_delays_by_which_side_led() {
+        let config = ProcessorConfig { compare_mode: true, quiet: true, ..Default::default() };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        for (index, a_us, b_us) in [(0, 0, 300), (1, 0, 100), (2, 1200, 0)] {
+            processor.record(0, data_shred(index), t0 + Duration::from_micros(a_us));
+            processor.record(1, data_shred(index), t0 + Duration::from_micros(b_us));
+        }
+
+        let stats = processor.stats();
+        assert!(stats.sources[0].lead_margins.is_none());
+        let margins = stats.sources[1].lead_margins.as_ref().unwrap();
+        assert_eq!((margins.candidate_led.matched_pairs, margins.candidate_led.avg), (1, 1_200_000));
+        assert_eq!((margins.reference_led.matched_pairs, margins.reference_led.avg), (2, 200_000));
+    }
+
+    #[test]
+    fn bloom_dedup_drops_matched_entries_but_still_catches_repeats() {
+        let config = ProcessorConfig { bloom_dedup: Some(0.001), quiet: true, ..Default::default() };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(100));
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(200));
+        processor.record(0, data_shred(0), t0 + Duration::from_micros(300));
+
+        let stats = processor.stats();
+        assert_eq!(stats.matched_pairs, 1);
+        assert_eq!(stats.sources.iter().map(|source| source.tracked).sum::<usize>(), 0);
+        let bloom_hits: Vec<_> = stats.sources.iter().map(|source| source.bloom.as_ref().unwrap().hits).collect();
+        assert_eq!(bloom_hits, [1, 1]);
+    }
+
+    #[test]
+    fn reports_time_to_first_match() {
+        let mut processor = processor();
+        assert!(processor.stats().first_match_after.is_none());
+
+        let t0 = Instant::now() + Duration::from_secs(2);
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, data_shred(0), t0 + Duration::from_millis(1));
+        processor.record(1, data_shred(1), t0 + Duration::from_secs(5));
+        processor.record(0, data_shred(1), t0 + Duration::from_secs(5));
+
+        let first_match_after = processor.stats().first_match_after.unwrap();
+        assert!(first_match_after >= Duration::from_millis(2001) && first_match_after < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn empty_stats_average_to_zero() {
+        let processor = processor();
+        let stats = processor.stats();
+        assert_eq!(stats.matched_pairs, 0);
+        for delay in &stats.delays {
+            assert_eq!(delay.samples, 0);
+            assert_eq!(delay.avg, 0);
+            assert_eq!(delay.p99, 0);
+        }
+        assert_eq!(stats.hdr.samples, 0);
+        assert_eq!(stats.sources[1].match_rate, None);
+    }
+
+    #[test]
+    fn inter_arrival_tracks_gaps_per_source() {
+        let config = ProcessorConfig {
+            inter_arrival: true,
+            quiet: true,
+            ..Default::default()
+        };
+        let mut processor = Processor::new(vec!["a".into(), "b".into()], config).unwrap();
+        let t0 = Instant::now();
+        for (index, micros) in [0, 100, 400].into_iter().enumerate() {
+            processor.record(0, data_shred(index as u32), t0 + Duration::from_micros(micros));
+        }
+
+        let stats = processor.stats();
+        let gaps = stats.sources[0].inter_arrival.as_ref().unwrap();
+        assert_eq!(gaps.mean, 200_000);
+        assert_eq!(gaps.max, 300_000);
+        assert_eq!(stats.sources[1].inter_arrival.as_ref().unwrap().max, 0);
+    }
+
+    #[test]
+    fn reorder_depth_is_measured_against_the_highest_slot_so_far() {
+        let mut processor =
+            Processor::new(vec!["a".into(), "b".into()], ProcessorConfig { quiet: true, reorder_depth: true, ..Default::default() })
+                .unwrap();
+        let t0 = Instant::now();
+        for slot in [100, 101, 99, 105, 102, 106] {
+            processor.record(0, ShredId::new(slot, 0, ShredType::Data), t0);
+        }
+
+        let reordering = processor.stats().sources.swap_remove(0).reordering.unwrap();
+        assert_eq!(reordering.max_depth, 3);
+        let counts: Vec<usize> = reordering.buckets.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(counts, [4, 0, 2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn groups_delays_by_fec_set() {
+        let mut processor =
+            Processor::new(vec!["a".into(), "b".into()], ProcessorConfig { quiet: true, per_fec_set_report: Some(4), ..Default::default() })
+                .unwrap();
+        let t0 = Instant::now();
+        for (index, fec_set_index, delay_us) in [(0, 0, 100), (1, 0, 300), (32, 32, 1000)] {
+            processor.record_with_fec_set(0, data_shred(index), fec_set_index, t0);
+            processor.record_with_fec_set(1, data_shred(index), fec_set_index, t0 + Duration::from_micros(delay_us));
+        }
+
+        let per_fec_set = processor.stats().per_fec_set.unwrap();
+        assert_eq!(per_fec_set.len(), 1);
+        let averages: Vec<(u32, i64)> = per_fec_set[0].sets.iter().map(|set| (set.fec_set_index, set.avg)).collect();
+        assert_eq!(averages, [(0, 200_000), (32, 1_000_000)]);
+        assert_eq!(per_fec_set[0].spread, 800_000);
+    }
+
+    #[test]
+    fn delays_are_measured_against_the_chosen_reference() {
+        let config = ProcessorConfig {
+            reference_idx: 1,
+            quiet: true,
+            ..Default::default()
+        };
+        let mut processor = Processor::new(vec!["a".into(), "b".into(), "c".into()], config).unwrap();
+        let t0 = Instant::now();
+        processor.record(0, data_shred(0), t0);
+        processor.record(1, data_shred(0), t0 + Duration::from_micros(100));
+        processor.record(2, data_shred(0), t0 + Duration::from_micros(300));
+
+        let stats = processor.stats();
+        assert_eq!(stats.matched_pairs, 2);
+        assert_eq!(stats.sources[0].delay_mean, -100_000);
+        assert_eq!(stats.sources[2].delay_mean, 200_000);
+    }
+}
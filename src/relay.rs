@@ -0,0 +1,110 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use solana_ledger::shred::ShredId;
+use tracing::{error, info};
+
+/// Re-sends the first copy of every shred, from whichever source delivered it, to one
+/// destination, for --forward-to. Later copies are dropped.
+pub struct Relay {
+    socket: UdpSocket,
+    target: SocketAddr,
+    // When each relayed shred first arrived, until `cleanup` forgets it.
+    seen: Mutex<HashMap<ShredId, Instant>>,
+    // Per source, by index; --watch-config appends to it.
+    relayed: RwLock<Vec<(Arc<str>, AtomicU64)>>,
+    suppressed: AtomicU64,
+}
+
+impl Relay {
+    pub fn bind(target: SocketAddr, sources: &[Arc<str>]) -> io::Result<Self> {
+        let local: SocketAddr = match target {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        Ok(Self {
+            socket: UdpSocket::bind(local)?,
+            target,
+            seen: Mutex::new(HashMap::new()),
+            relayed: RwLock::new(sources.iter().map(|name| (Arc::clone(name), AtomicU64::new(0))).collect()),
+            suppressed: AtomicU64::new(0),
+        })
+    }
+
+    /// Starts counting for a source added at runtime, which takes the next index.
+    pub fn add_source(&self, name: Arc<str>) {
+        self.relayed.write().unwrap().push((name, AtomicU64::new(0)));
+    }
+
+    /// Sends `data` on unless a copy of `shred_id` was already relayed. Called straight
+    /// from the receive path, so the fastest copy leaves without queueing behind the
+    /// processor.
+    pub fn relay(&self, source_idx: usize, shred_id: ShredId, data: &[u8], timestamp: Instant) {
+        match self.seen.lock().unwrap().entry(shred_id) {
+            Entry::Occupied(_) => {
+                self.suppressed.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(timestamp);
+            }
+        }
+        match self.socket.send_to(data, self.target) {
+            Ok(_) => {
+                if let Some((_, relayed)) = self.relayed.read().unwrap().get(source_idx) {
+                    relayed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Err(e) => error!(target: "shred_perf::socket", "Failed to forward shred to {}: {}", self.target, e),
+        }
+    }
+
+    /// Forgets shreds first seen longer than `retention` ago.
+    pub fn cleanup(&self, now: Instant, retention: Duration) {
+        self.seen.lock().unwrap().retain(|_, first| now.saturating_duration_since(*first) < retention);
+    }
+
+    pub fn log_summary(&self) {
+        let relayed = self
+            .relayed
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, relayed)| format!("{} {}", name, relayed.load(Ordering::Relaxed)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(
+            "Forwarded to {}: {} ({} later copies dropped)",
+            self.target,
+            relayed,
+            self.suppressed.load(Ordering::Relaxed)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_ledger::shred::ShredType;
+
+    #[test]
+    fn relays_for_a_source_added_at_runtime() {
+        let target = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        target.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let relay = Relay::bind(target.local_addr().unwrap(), &["a".into(), "b".into()]).unwrap();
+        relay.add_source("c".into());
+
+        relay.relay(2, ShredId::new(1, 0, ShredType::Data), b"shred", Instant::now());
+        relay.relay(0, ShredId::new(1, 0, ShredType::Data), b"shred", Instant::now());
+        let mut buf = [0; 16];
+        assert_eq!(target.recv(&mut buf).unwrap(), 5);
+        let relayed = relay.relayed.read().unwrap();
+        let counts: Vec<_> = relayed.iter().map(|(name, relayed)| (&**name, relayed.load(Ordering::Relaxed))).collect();
+        assert_eq!(counts, [("a", 0), ("b", 0), ("c", 1)]);
+        assert_eq!(relay.suppressed.load(Ordering::Relaxed), 1);
+    }
+}
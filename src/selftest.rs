@@ -0,0 +1,88 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+use solana_ledger::shred::{Shred, ShredFlags};
+use solana_shred_perf::stats::{format_delay, StatsSnapshot};
+
+// Indices per synthetic slot; slots start at 1 so the parent offset stays valid.
+const SHREDS_PER_SLOT: u64 = 64;
+// Lets the listeners bind before the first shred goes out.
+const START_DELAY: Duration = Duration::from_millis(500);
+
+/// Starts one sender thread per target, each sending the same sequence of synthetic data
+/// shreds at `rate` per second (as fast as possible at 0), the second target `delay`
+/// behind the first.
+pub fn spawn(targets: [SocketAddr; 2], delay: Duration, rate: u64) -> io::Result<()> {
+    let start = Instant::now() + START_DELAY;
+    for (target, offset) in targets.into_iter().zip([Duration::ZERO, delay]) {
+        let local: SocketAddr = match target {
+            SocketAddr::V4(_) => (Ipv4Addr::LOCALHOST, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::LOCALHOST, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        thread::Builder::new()
+            .name("self-test".into())
+            .spawn(move || send_loop(&socket, target, start + offset, rate))?;
+    }
+    Ok(())
+}
+
+fn send_loop(socket: &UdpSocket, target: SocketAddr, start: Instant, rate: u64) {
+    let interval_nanos = 1_000_000_000u64.checked_div(rate).unwrap_or(0);
+    for seq in 0u64.. {
+        wait_until(start + Duration::from_nanos(seq * interval_nanos));
+        let shred = Shred::new_from_data(
+            1 + seq / SHREDS_PER_SLOT,
+            (seq % SHREDS_PER_SLOT) as u32,
+            1,
+            &[],
+            ShredFlags::empty(),
+            0,
+            0,
+            0,
+        );
+        if let Err(e) = socket.send_to(&shred.payload()[..], target) {
+            error!("Self-test sender to {} failed: {}", target, e);
+            return;
+        }
+    }
+}
+
+// Sleeps most of the way, then spins, since sleeps alone overshoot by far more than the
+// delays being measured.
+fn wait_until(due: Instant) {
+    const SPIN: Duration = Duration::from_millis(1);
+    loop {
+        let now = Instant::now();
+        if now >= due {
+            return;
+        }
+        if due - now > SPIN {
+            thread::sleep(due - now - SPIN);
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// Compares the delay measured for `candidate_idx` with the one the senders introduced.
+pub fn report(stats: &StatsSnapshot, candidate_idx: usize, expected: Duration) {
+    let unit = stats.delay_unit;
+    let candidate = &stats.sources[candidate_idx];
+    let expected = expected.as_nanos() as i64;
+    if stats.matched_pairs == 0 {
+        warn!("Self-test: no shreds matched; expected a delay of {}", format_delay(expected, unit));
+        return;
+    }
+    info!(
+        "Self-test: expected {} delay for {}, measured {} ± {} (off by {}) over {} matched pairs",
+        format_delay(expected, unit),
+        candidate.name,
+        format_delay(candidate.delay_mean, unit),
+        format_delay(candidate.delay_stddev, unit),
+        format_delay(candidate.delay_mean - expected, unit),
+        stats.matched_pairs
+    );
+}
@@ -0,0 +1,1542 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use hdrhistogram::Histogram;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+pub struct SourceSnapshot {
+    pub name: Arc<str>,
+    pub tracked: usize,
+    pub last_evicted: u64,
+    pub datagrams: u64,
+    pub parse_errors: u64,
+    // Datagrams that filled the --max-packet-size buffer.
+    pub truncated: u64,
+    // Shreds discarded for being more than --max-slot-age slots behind.
+    pub stale: u64,
+    pub invalid_signatures: u64,
+    pub unknown_leader: u64,
+    pub duplicates: u64,
+    pub avg_duplicate_delay: i64,
+    pub dropped: u64,
+    pub bytes_received: u64,
+    // Over the current stats interval.
+    pub bytes_per_sec: f64,
+    pub wins: usize,
+    // With --reset-stats-each-tick, `wins` and `led` cover the current stats interval and
+    // this the whole run.
+    pub lifetime_wins: Option<usize>,
+    // Percentage of this source's own matched pairs it delivered first; `None` before
+    // it has any.
+    pub led: Option<f64>,
+    // Percentage of shreds matched with the reference over the current stats interval;
+    // `None` for the reference itself or when neither received anything.
+    pub match_rate: Option<f64>,
+    // Lifetime mean and standard deviation of this source's delay vs the reference.
+    pub delay_mean: i64,
+    pub delay_stddev: i64,
+    pub expired: u64,
+    pub expired_unmatched: u64,
+    // How long the unmatched evicted entries had waited, in multiples of the retention
+    // window.
+    pub eviction_ages: Vec<HistogramBucket>,
+    // With --first-seen-wins: shreds delivered before every other source, and later copies
+    // with their average lag behind the first delivery.
+    pub first_seen: u64,
+    pub seen_later: u64,
+    pub avg_seen_later: i64,
+    // Shred indices missed over the slots --slot-gaps has completed.
+    pub missing_indices: u64,
+    // With --inter-arrival.
+    pub inter_arrival: Option<InterArrivalSnapshot>,
+    // With --reorder-depth.
+    pub reordering: Option<ReorderSnapshot>,
+    // With --compare-mode, for every source but the reference.
+    pub lead_margins: Option<LeadMarginsSnapshot>,
+    // With --bloom-dedup.
+    pub bloom: Option<BloomSnapshot>,
+    // With --alert-above-ms, whether this source's alert is firing; `None` for the
+    // reference.
+    pub alerting: Option<bool>,
+    // With --baseline-offset-us: the offset taken off this source's arrival times, and for
+    // every source but the reference the mean delay without any offsets, next to the
+    // adjusted `delay_mean`.
+    pub baseline_offset: Option<i64>,
+    pub raw_delay_mean: Option<i64>,
+    // Over the current stats interval, against the reference; `None` for the reference.
+    pub overlap: Option<OverlapSnapshot>,
+    // With --reference-shreds.
+    pub truth: Option<TruthSnapshot>,
+    // With --index-loss.
+    pub index_loss: Option<IndexLossSnapshot>,
+}
+
+/// Shreds first received over a stats interval by a source and the reference: how many
+/// matched, and how many only one of the two has delivered so far. A shred whose copies
+/// straddle a tick can count as only-one on both sides of it.
+pub struct OverlapSnapshot {
+    pub only_source: u64,
+    pub only_reference: u64,
+    pub both: u64,
+}
+
+/// One source scored against --reference-shreds: how many of the expected shreds it
+/// delivered, how many others it delivered for the same slots, and with recorded
+/// receive times, how long after the recording node it got them.
+pub struct TruthSnapshot {
+    pub delivered: u64,
+    pub expected: u64,
+    pub unexpected: u64,
+    pub latency_mean: Option<i64>,
+    pub latency_stddev: Option<i64>,
+}
+
+/// One source's --index-loss estimate over the slots counted so far: data shred indices
+/// between the lowest and highest it delivered in each, and how many of those it missed.
+pub struct IndexLossSnapshot {
+    pub slots: u64,
+    pub expected: u64,
+    pub missing: u64,
+}
+
+/// One source's --bloom-dedup filter: repeats it caught, the estimated first copies it
+/// wrongly dropped, and the memory it takes against the exact entries it replaced.
+pub struct BloomSnapshot {
+    pub hits: u64,
+    pub false_positives: f64,
+    pub entries: u64,
+    pub bytes: usize,
+    pub exact_bytes: usize,
+}
+
+/// How far ahead the leader was in a candidate's pairs with the reference, kept apart for
+/// the pairs the candidate led and the ones the reference led; every delay is positive.
+pub struct LeadMarginsSnapshot {
+    pub candidate_led: DelaySnapshot,
+    pub reference_led: DelaySnapshot,
+}
+
+/// How many shreds arrived how many slots behind the highest slot the source had already
+/// delivered; the first bucket counts the ones in order.
+pub struct ReorderSnapshot {
+    pub max_depth: u64,
+    pub buckets: Vec<HistogramBucket>,
+}
+
+impl ReorderSnapshot {
+    pub fn out_of_order(&self) -> u64 {
+        self.buckets.iter().skip(1).map(|bucket| bucket.count as u64).sum()
+    }
+}
+
+/// Gaps between consecutive shreds on one source, in nanoseconds.
+pub struct InterArrivalSnapshot {
+    pub mean: i64,
+    pub stddev: i64,
+    pub max: i64,
+}
+
+pub struct DelaySnapshot {
+    pub shred_type: &'static str,
+    pub matched_pairs: usize,
+    pub samples: usize,
+    // Total of the windowed delays, for averaging across snapshots without re-rounding.
+    pub sum: i128,
+    pub avg: i64,
+    // Moving average, set by the caller; 0 until known.
+    pub ewma: i64,
+    pub min: i64,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub max: i64,
+    pub negative: usize,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+pub struct HistogramBucket {
+    pub label: String,
+    pub count: usize,
+}
+
+impl DelaySnapshot {
+    /// `sum` is the total of `delays`, which callers keep as a running total rather than
+    /// re-summing the window on every report.
+    pub fn new(
+        shred_type: &'static str,
+        matched_pairs: usize,
+        delays: impl ExactSizeIterator<Item = i64>,
+        sum: i128,
+        histogram_edges: &[i64],
+        unit: DelayUnit,
+    ) -> Self {
+        let mut sorted: Vec<i64> = delays.collect();
+        sorted.sort_unstable();
+        Self {
+            histogram: histogram(&sorted, histogram_edges, unit),
+            shred_type,
+            matched_pairs,
+            samples: sorted.len(),
+            sum,
+            avg: rounded_mean(sum, sorted.len()),
+            ewma: 0,
+            min: sorted.first().copied().unwrap_or(0),
+            p50: percentile(&sorted, 50.0),
+            p90: percentile(&sorted, 90.0),
+            p99: percentile(&sorted, 99.0),
+            max: sorted.last().copied().unwrap_or(0),
+            negative: sorted.iter().filter(|d| **d < 0).count(),
+        }
+    }
+}
+
+/// Tail percentiles of |delay| from the HdrHistogram, in nanoseconds.
+pub struct HdrSnapshot {
+    pub samples: u64,
+    pub p50: i64,
+    pub p99: i64,
+    pub p999: i64,
+    pub max: i64,
+}
+
+impl HdrSnapshot {
+    pub fn new(hdr: &Histogram<u64>) -> Self {
+        let nanos = |micros: u64| micros as i64 * 1000;
+        Self {
+            samples: hdr.len(),
+            p50: nanos(hdr.value_at_quantile(0.5)),
+            p99: nanos(hdr.value_at_quantile(0.99)),
+            p999: nanos(hdr.value_at_quantile(0.999)),
+            max: nanos(hdr.max()),
+        }
+    }
+}
+
+pub struct SlotSnapshot {
+    pub slot: u64,
+    pub avg: i64,
+    pub samples: usize,
+}
+
+/// Data shreds each source delivered for one slot, in `sources` order, out of `expected`.
+pub struct CompletenessSnapshot {
+    pub slot: u64,
+    pub expected: u32,
+    pub delivered: Vec<u32>,
+}
+
+/// Average delay of the shreds whose index within the slot falls in `label`'s range.
+pub struct IndexBucketSnapshot {
+    pub label: String,
+    pub avg: i64,
+    pub samples: u64,
+}
+
+/// With --agreement-latency: per shred, the time from its first delivery on any source
+/// until a majority of the sources had delivered it, and until all of them had.
+pub struct AgreementSnapshot {
+    pub sources: usize,
+    pub quorum_sources: usize,
+    pub quorum: DelaySnapshot,
+    pub all: DelaySnapshot,
+    // Shreds evicted before every source had delivered them.
+    pub incomplete: u64,
+}
+
+/// One of the matched pairs whose later copy trailed the furthest, for --top-laggards.
+pub struct LaggardSnapshot {
+    pub slot: u64,
+    pub index: u32,
+    pub shred_type: &'static str,
+    pub late: Arc<str>,
+    pub leader: Arc<str>,
+    pub behind: i64,
+}
+
+/// Average delay of each FEC set in one slot, and how far apart the slowest and fastest
+/// set were.
+pub struct FecSlotSnapshot {
+    pub slot: u64,
+    pub sets: Vec<FecSetSnapshot>,
+    pub spread: i64,
+}
+
+pub struct FecSetSnapshot {
+    pub fec_set_index: u32,
+    pub avg: i64,
+    pub samples: usize,
+}
+
+impl FecSlotSnapshot {
+    pub fn new(slot: u64, sets: &BTreeMap<u32, Vec<i64>>) -> Self {
+        let sets: Vec<FecSetSnapshot> = sets
+            .iter()
+            .map(|(fec_set_index, delays)| FecSetSnapshot {
+                fec_set_index: *fec_set_index,
+                avg: mean_delay(delays.iter()),
+                samples: delays.len(),
+            })
+            .collect();
+        let min = sets.iter().map(|set| set.avg).min().unwrap_or(0);
+        let max = sets.iter().map(|set| set.avg).max().unwrap_or(0);
+        Self { slot, sets, spread: max - min }
+    }
+}
+
+/// Everything a stats tick reports, computed once and rendered by whichever log format is active.
+pub struct StatsSnapshot {
+    pub reference_idx: usize,
+    pub retention: Duration,
+    // Replaces `retention` when set.
+    pub evict_after_slots: Option<u64>,
+    pub signatures_verified: bool,
+    pub matched_pairs: usize,
+    // With --reset-stats-each-tick, `matched_pairs` covers the current stats interval and
+    // this the whole run.
+    pub lifetime_matched_pairs: Option<usize>,
+    // Only tracked with --match-key slot-index.
+    pub cross_type_matches: Option<usize>,
+    pub sources: Vec<SourceSnapshot>,
+    pub delays: Vec<DelaySnapshot>,
+    pub hdr: HdrSnapshot,
+    pub per_slot: Option<Vec<SlotSnapshot>>,
+    pub per_fec_set: Option<Vec<FecSlotSnapshot>>,
+    // With --completeness-report, oldest slot first.
+    pub completeness: Option<Vec<CompletenessSnapshot>>,
+    // With --index-buckets, over every recorded delay.
+    pub per_index: Option<Vec<IndexBucketSnapshot>>,
+    // With --top-laggards, worst first.
+    pub laggards: Option<Vec<LaggardSnapshot>>,
+    pub agreement: Option<AgreementSnapshot>,
+    // Human output holds back percentiles until a window has this many samples.
+    pub min_samples: usize,
+    pub first_seen_wins: bool,
+    // Slots --slot-gaps has completed, when enabled.
+    pub gap_slots: Option<u64>,
+    pub max_slot_age: Option<u64>,
+    // Only 1 in this many shreds was matched.
+    pub sample_rate: Option<u64>,
+    pub delay_unit: DelayUnit,
+    // Wall-clock time the snapshot was taken, so every report says when it is from.
+    pub time: DateTime<Utc>,
+    pub uptime: Duration,
+    // Since startup; `None` until a pair matches.
+    pub first_match_after: Option<Duration>,
+}
+
+impl StatsSnapshot {
+    pub fn reference(&self) -> &str {
+        &self.sources[self.reference_idx].name
+    }
+}
+
+pub fn log_human(stats: &StatsSnapshot) {
+    let unit = stats.delay_unit;
+    let received = stats
+        .sources
+        .iter()
+        .map(|source| format!("Port {}: {}", source.name, source.tracked))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    let matched = match stats.lifetime_matched_pairs {
+        Some(lifetime) => format!("{} this interval, {} lifetime", stats.matched_pairs, lifetime),
+        None => stats.matched_pairs.to_string(),
+    };
+    match stats.cross_type_matches {
+        Some(cross_type) => info!(target: "shred_perf::stats", "Stats: {} | Matched: {} ({} across shred types)", received, matched, cross_type),
+        None => info!(target: "shred_perf::stats", "Stats: {} | Matched: {}", received, matched),
+    }
+    if let Some(rate) = stats.sample_rate {
+        info!(target: "shred_perf::stats", "Sampling 1 in {} shreds", rate);
+    }
+    let time = stats.time.to_rfc3339_opts(SecondsFormat::Millis, true);
+    match stats.first_match_after {
+        Some(first) => info!(
+            target: "shred_perf::stats",
+            "Time: {} | Uptime: {}s | First match after {:.1}s",
+            time,
+            stats.uptime.as_secs(),
+            first.as_secs_f64()
+        ),
+        None => info!(target: "shred_perf::stats", "Time: {} | Uptime: {}s | No matches yet", time, stats.uptime.as_secs()),
+    }
+
+    for delay in &stats.delays {
+        if delay.samples < stats.min_samples {
+            info!(
+                target: "shred_perf::stats",
+                "  {}: Matched: {} | Delay vs {}: collecting samples ({}/{})",
+                delay.shred_type,
+                delay.matched_pairs,
+                stats.reference(),
+                delay.samples,
+                stats.min_samples
+            );
+            continue;
+        }
+        info!(
+            target: "shred_perf::stats",
+            "  {}: Matched: {} | Delay vs {} (last {}): avg {} | EWMA: {} | Min: {} | p50: {} | p90: {} | p99: {} | Max: {} | Negative: {}",
+            delay.shred_type,
+            delay.matched_pairs,
+            stats.reference(),
+            delay.samples,
+            format_delay(delay.avg, unit),
+            format_delay(delay.ewma, unit),
+            format_delay(delay.min, unit),
+            format_delay(delay.p50, unit),
+            format_delay(delay.p90, unit),
+            format_delay(delay.p99, unit),
+            format_delay(delay.max, unit),
+            delay.negative
+        );
+        let histogram = delay
+            .histogram
+            .iter()
+            .map(|bucket| format!("{}: {} ({:.1}%)", bucket.label, bucket.count, pct(bucket.count as u64, delay.samples as u64)))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        info!(target: "shred_perf::stats", "    |delay| histogram: {}", histogram);
+    }
+
+    if stats.hdr.samples < stats.min_samples as u64 {
+        info!(
+            target: "shred_perf::stats",
+            "  |delay| HDR (all types): collecting samples ({}/{})",
+            stats.hdr.samples,
+            stats.min_samples
+        );
+    } else {
+        info!(
+            target: "shred_perf::stats",
+            "  |delay| HDR (all types, {} samples): p50: {} | p99: {} | p99.9: {} | Max: {}",
+            stats.hdr.samples,
+            format_delay(stats.hdr.p50, unit),
+            format_delay(stats.hdr.p99, unit),
+            format_delay(stats.hdr.p999, unit),
+            format_delay(stats.hdr.max, unit)
+        );
+    }
+
+    if let Some(agreement) = &stats.agreement {
+        let stages = [
+            (format!("quorum ({} of {} sources)", agreement.quorum_sources, agreement.sources), &agreement.quorum),
+            (format!("all {} sources", agreement.sources), &agreement.all),
+        ];
+        for (stage, latency) in stages {
+            if latency.samples < stats.min_samples {
+                info!(
+                    target: "shred_perf::stats",
+                    "  Time to {}: Shreds: {} | collecting samples ({}/{})",
+                    stage,
+                    latency.matched_pairs,
+                    latency.samples,
+                    stats.min_samples
+                );
+                continue;
+            }
+            info!(
+                target: "shred_perf::stats",
+                "  Time to {}: Shreds: {} | Since first delivery (last {}): avg {} | p50: {} | p90: {} | p99: {} | Max: {}",
+                stage,
+                latency.matched_pairs,
+                latency.samples,
+                format_delay(latency.avg, unit),
+                format_delay(latency.p50, unit),
+                format_delay(latency.p90, unit),
+                format_delay(latency.p99, unit),
+                format_delay(latency.max, unit)
+            );
+        }
+        if agreement.incomplete > 0 {
+            info!(
+                target: "shred_perf::stats",
+                "  {} shreds expired before all {} sources delivered them",
+                agreement.incomplete,
+                agreement.sources
+            );
+        }
+    }
+
+    for source in &stats.sources {
+        let Some(margins) = &source.lead_margins else {
+            continue;
+        };
+        let side = |leader: &str, margins: &DelaySnapshot| {
+            if margins.matched_pairs == 0 {
+                format!("{} never led", leader)
+            } else {
+                format!(
+                    "{} led {} by avg {} (p50: {} | p99: {} | Max: {})",
+                    leader,
+                    margins.matched_pairs,
+                    format_delay(margins.avg, unit),
+                    format_delay(margins.p50, unit),
+                    format_delay(margins.p99, unit),
+                    format_delay(margins.max, unit)
+                )
+            }
+        };
+        info!(
+            target: "shred_perf::stats",
+            "  {} vs {}: {}; {}",
+            source.name,
+            stats.reference(),
+            side(&source.name, &margins.candidate_led),
+            side(stats.reference(), &margins.reference_led)
+        );
+    }
+
+    if let Some(per_slot) = &stats.per_slot {
+        let per_slot = per_slot
+            .iter()
+            .map(|slot| format!("{}: {} ({})", slot.slot, format_delay(slot.avg, unit), slot.samples))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        info!(target: "shred_perf::stats", "Per-slot avg delay: {}", per_slot);
+    }
+    if let Some(completeness) = &stats.completeness {
+        let completeness = completeness
+            .iter()
+            .map(|slot| {
+                let delivered = stats
+                    .sources
+                    .iter()
+                    .zip(&slot.delivered)
+                    .map(|(source, delivered)| format!("{} {}/{}", source.name, delivered, slot.expected))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{}: {}", slot.slot, delivered)
+            })
+            .collect::<Vec<_>>()
+            .join(" | ");
+        info!(target: "shred_perf::stats", "Data shreds delivered per slot: {}", completeness);
+    }
+    if let Some(per_index) = &stats.per_index {
+        let per_index = per_index
+            .iter()
+            .map(|bucket| format!("{}: {} ({})", bucket.label, format_delay(bucket.avg, unit), bucket.samples))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        info!(target: "shred_perf::stats", "Avg delay by shred index: {}", per_index);
+    }
+    if let Some(laggards) = stats.laggards.as_ref().filter(|laggards| !laggards.is_empty()) {
+        info!(target: "shred_perf::stats", "Top {} laggards:", laggards.len());
+        for laggard in laggards {
+            info!(
+                target: "shred_perf::stats",
+                "  slot {} index {} ({}): {} {} behind {}",
+                laggard.slot,
+                laggard.index,
+                laggard.shred_type,
+                laggard.late,
+                format_delay(laggard.behind, unit),
+                laggard.leader
+            );
+        }
+    }
+    if let Some(per_fec_set) = &stats.per_fec_set {
+        let per_fec_set = per_fec_set
+            .iter()
+            .map(|slot| {
+                let sets = slot
+                    .sets
+                    .iter()
+                    .map(|set| format!("{}: {} ({})", set.fec_set_index, format_delay(set.avg, unit), set.samples))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{}: [{}] spread {}", slot.slot, sets, format_delay(slot.spread, unit))
+            })
+            .collect::<Vec<_>>()
+            .join(" | ");
+        info!(target: "shred_perf::stats", "Per-FEC-set avg delay: {}", per_fec_set);
+    }
+
+    let wins = stats
+        .sources
+        .iter()
+        .map(|source| {
+            let won = pct(source.wins as u64, stats.matched_pairs as u64);
+            match source.lifetime_wins {
+                Some(lifetime) => format!("Port {} won {:.0}% ({}, {} lifetime)", source.name, won, source.wins, lifetime),
+                None => format!("Port {} won {:.0}% ({})", source.name, won, source.wins),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let led = stats
+        .sources
+        .iter()
+        .map(|source| match source.led {
+            Some(led) => format!("{} {:.1}%", source.name, led),
+            None => format!("{} n/a", source.name),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let coverage = stats
+        .sources
+        .iter()
+        .enumerate()
+        .map(|(idx, source)| {
+            // The reference is compared against every other source, the rest only against it.
+            let others = stats
+                .sources
+                .iter()
+                .enumerate()
+                .filter(|(other_idx, _)| {
+                    *other_idx != idx && (idx == stats.reference_idx || *other_idx == stats.reference_idx)
+                })
+                .map(|(_, other)| other.name.as_ref())
+                .collect::<Vec<_>>()
+                .join("/");
+            format!(
+                "Port {} delivered {:.1}% of shreds {} never received ({})",
+                source.name,
+                pct(source.expired_unmatched, source.expired),
+                others,
+                source.expired_unmatched
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let parsed = stats
+        .sources
+        .iter()
+        .map(|source| {
+            let ok = source.datagrams - source.parse_errors - source.truncated;
+            format!("{} {:.1}% ({}/{})", source.name, pct(ok, source.datagrams), ok, source.datagrams)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let duplicates = stats
+        .sources
+        .iter()
+        .map(|source| {
+            format!(
+                "{} {} (avg {} after first)",
+                source.name,
+                source.duplicates,
+                format_delay(source.avg_duplicate_delay, unit)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let throughput = stats
+        .sources
+        .iter()
+        .map(|source| {
+            format!(
+                "{} {:.2} MB/s ({} bytes total)",
+                source.name,
+                source.bytes_per_sec / 1_000_000.0,
+                source.bytes_received
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let jitter = stats
+        .sources
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| *idx != stats.reference_idx)
+        .map(|(_, source)| {
+            format!(
+                "{} mean {} ± {} stddev",
+                source.name,
+                format_delay(source.delay_mean, unit),
+                format_delay(source.delay_stddev, unit)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if let Some(reference_offset) = stats.sources[stats.reference_idx].baseline_offset {
+        let baseline = stats
+            .sources
+            .iter()
+            .filter_map(|source| {
+                Some(format!(
+                    "{} {} raw, {} adjusted (offset {})",
+                    source.name,
+                    format_delay(source.raw_delay_mean?, unit),
+                    format_delay(source.delay_mean, unit),
+                    format_delay(source.baseline_offset?, unit)
+                ))
+            })
+            .collect::<Vec<_>>();
+        info!(
+            target: "shred_perf::stats",
+            "Baseline-adjusted mean delay vs {} (offset {}): {}",
+            stats.reference(),
+            format_delay(reference_offset, unit),
+            baseline.join(", ")
+        );
+    }
+    let match_rate = stats
+        .sources
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| *idx != stats.reference_idx)
+        .map(|(_, source)| match source.match_rate {
+            Some(rate) => format!("{} {:.1}%", source.name, rate),
+            None => format!("{} n/a", source.name),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    info!(target: "shred_perf::stats", "Jitter vs {}: {}", stats.reference(), jitter);
+    info!(target: "shred_perf::stats", "Match rate vs {} (this interval): {}", stats.reference(), match_rate);
+    let inter_arrival = stats
+        .sources
+        .iter()
+        .filter_map(|source| {
+            let gaps = source.inter_arrival.as_ref()?;
+            Some(format!(
+                "{} mean {} ± {} stddev, max {}",
+                source.name,
+                format_delay(gaps.mean, unit),
+                format_delay(gaps.stddev, unit),
+                format_delay(gaps.max, unit)
+            ))
+        })
+        .collect::<Vec<_>>();
+    if !inter_arrival.is_empty() {
+        info!(target: "shred_perf::stats", "Inter-arrival: {}", inter_arrival.join(", "));
+    }
+    let bloom = stats
+        .sources
+        .iter()
+        .filter_map(|source| {
+            let bloom = source.bloom.as_ref()?;
+            Some(format!(
+                "{} {} repeats caught (~{:.1} matches lost to false positives), {} KiB for {} entries (~{} KiB exact)",
+                source.name,
+                bloom.hits,
+                bloom.false_positives,
+                bloom.bytes / 1024,
+                bloom.entries,
+                bloom.exact_bytes / 1024
+            ))
+        })
+        .collect::<Vec<_>>();
+    if !bloom.is_empty() {
+        info!(target: "shred_perf::stats", "Bloom dedup: {}", bloom.join(", "));
+    }
+    let alerting = stats
+        .sources
+        .iter()
+        .filter(|source| source.alerting == Some(true))
+        .map(|source| &*source.name)
+        .collect::<Vec<_>>();
+    if !alerting.is_empty() {
+        warn!(target: "shred_perf::stats", "Alerting: {}", alerting.join(", "));
+    }
+    let reordering = stats
+        .sources
+        .iter()
+        .filter_map(|source| {
+            let reordering = source.reordering.as_ref()?;
+            let total: u64 = reordering.buckets.iter().map(|bucket| bucket.count as u64).sum();
+            let buckets = reordering
+                .buckets
+                .iter()
+                .map(|bucket| format!("{}: {:.1}%", bucket.label, pct(bucket.count as u64, total)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            Some(format!("{} max {} [{}]", source.name, reordering.max_depth, buckets))
+        })
+        .collect::<Vec<_>>();
+    if !reordering.is_empty() {
+        info!(target: "shred_perf::stats", "Reorder depth (slots behind): {}", reordering.join(" | "));
+    }
+    info!(target: "shred_perf::stats", "Wins: {}", wins);
+    info!(target: "shred_perf::stats", "Led (of own matched shreds): {}", led);
+    info!(target: "shred_perf::stats", "Coverage: {}", coverage);
+    let overlap = stats
+        .sources
+        .iter()
+        .filter_map(|source| {
+            let overlap = source.overlap.as_ref()?;
+            Some(format!(
+                "{} only on {}, {} only on {}, {} on both",
+                overlap.only_reference,
+                stats.reference(),
+                overlap.only_source,
+                source.name,
+                overlap.both
+            ))
+        })
+        .collect::<Vec<_>>();
+    if !overlap.is_empty() {
+        info!(target: "shred_perf::stats", "Seen this interval: {}", overlap.join("; "));
+    }
+    let truth = stats
+        .sources
+        .iter()
+        .filter_map(|source| {
+            let truth = source.truth.as_ref()?;
+            let mut score = format!(
+                "{} {}/{} ({:.1}%), {} unexpected",
+                source.name,
+                truth.delivered,
+                truth.expected,
+                pct(truth.delivered, truth.expected),
+                truth.unexpected
+            );
+            if let (Some(mean), Some(stddev)) = (truth.latency_mean, truth.latency_stddev) {
+                score += &format!(", {} ± {} after the recording", format_delay(mean, unit), format_delay(stddev, unit));
+            }
+            Some(score)
+        })
+        .collect::<Vec<_>>();
+    if !truth.is_empty() {
+        info!(target: "shred_perf::stats", "Vs reference shreds: {}", truth.join("; "));
+    }
+    let index_loss = stats
+        .sources
+        .iter()
+        .filter_map(|source| {
+            let loss = source.index_loss.as_ref()?;
+            Some(format!(
+                "{} {:.2}% ({} of {} over {} slots)",
+                source.name,
+                pct(loss.missing, loss.expected),
+                loss.missing,
+                loss.expected,
+                loss.slots
+            ))
+        })
+        .collect::<Vec<_>>();
+    if !index_loss.is_empty() {
+        info!(target: "shred_perf::stats", "Estimated loss from index gaps: {}", index_loss.join("; "));
+    }
+    info!(target: "shred_perf::stats", "Parsed: {}", parsed);
+    let retention = stats
+        .sources
+        .iter()
+        .map(|source| format!("{} {} tracked ({} evicted last cleanup)", source.name, source.tracked, source.last_evicted))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    info!(target: "shred_perf::stats", "Duplicates: {}", duplicates);
+    if stats.first_seen_wins {
+        let first_seen = stats
+            .sources
+            .iter()
+            .map(|source| {
+                format!(
+                    "{} first {} | later {} (avg {} after first)",
+                    source.name,
+                    source.first_seen,
+                    source.seen_later,
+                    format_delay(source.avg_seen_later, unit)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(target: "shred_perf::stats", "First seen across sources: {}", first_seen);
+    }
+    if let Some(slots) = stats.gap_slots {
+        let gaps = stats
+            .sources
+            .iter()
+            .map(|source| {
+                let per_slot = if slots > 0 { source.missing_indices as f64 / slots as f64 } else { 0.0 };
+                format!("{} {} ({:.1}/slot)", source.name, source.missing_indices, per_slot)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(target: "shred_perf::stats", "Missing indices over {} completed slots: {}", slots, gaps);
+    }
+    match stats.evict_after_slots {
+        Some(slots) => info!(target: "shred_perf::stats", "Retention ({} slots): {}", slots, retention),
+        None => info!(target: "shred_perf::stats", "Retention ({:?}): {}", stats.retention, retention),
+    }
+    if stats.sources.iter().any(|source| source.expired_unmatched > 0) {
+        let ages = stats
+            .sources
+            .iter()
+            .filter(|source| source.expired_unmatched > 0)
+            .map(|source| {
+                let buckets = source
+                    .eviction_ages
+                    .iter()
+                    .map(|bucket| format!("{}: {:.1}%", bucket.label, pct(bucket.count as u64, source.expired_unmatched)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{} [{}]", source.name, buckets)
+            })
+            .collect::<Vec<_>>()
+            .join(" | ");
+        info!(target: "shred_perf::stats", "Unmatched age at eviction (x retention): {}", ages);
+    }
+    if let Some(age) = stats.max_slot_age {
+        let stale = stats
+            .sources
+            .iter()
+            .map(|source| format!("{} {}", source.name, source.stale))
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(target: "shred_perf::stats", "Stale (over {} slots behind the tip): {}", age, stale);
+    }
+    if stats.signatures_verified {
+        let signatures = stats
+            .sources
+            .iter()
+            .map(|source| {
+                format!(
+                    "{} {} invalid, {} unknown leader",
+                    source.name, source.invalid_signatures, source.unknown_leader
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(target: "shred_perf::stats", "Rejected signatures: {}", signatures);
+    }
+    info!(target: "shred_perf::stats", "Throughput: {}", throughput);
+
+    if stats.sources.iter().any(|source| source.dropped > 0) {
+        let dropped = stats
+            .sources
+            .iter()
+            .map(|source| format!("{} {}", source.name, source.dropped))
+            .collect::<Vec<_>>()
+            .join(", ");
+        warn!(target: "shred_perf::stats", "Dropped (processor falling behind): {}", dropped);
+    }
+    if stats.sources.iter().any(|source| source.truncated > 0) {
+        let truncated = stats
+            .sources
+            .iter()
+            .map(|source| format!("{} {}", source.name, source.truncated))
+            .collect::<Vec<_>>()
+            .join(", ");
+        warn!(target: "shred_perf::stats", "Truncated (over --max-packet-size): {}", truncated);
+    }
+}
+
+pub fn log_json(stats: &StatsSnapshot) {
+    tracing::info!(
+        target: "shred_perf::stats",
+        event = "stats",
+        time = %stats.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        reference = stats.reference(),
+        matched_pairs = stats.matched_pairs,
+        lifetime_matched_pairs = stats.lifetime_matched_pairs,
+        cross_type_matches = stats.cross_type_matches,
+        retention_secs = stats.retention.as_secs(),
+        evict_after_slots = stats.evict_after_slots,
+        gap_slots = stats.gap_slots,
+        sample_rate = stats.sample_rate,
+        uptime_secs = stats.uptime.as_secs(),
+        first_match_secs = stats.first_match_after.map(|first| first.as_secs_f64()),
+    );
+    for source in &stats.sources {
+        tracing::info!(
+            target: "shred_perf::stats",
+            event = "source_stats",
+            source = %source.name,
+            tracked = source.tracked,
+            last_evicted = source.last_evicted,
+            datagrams = source.datagrams,
+            parse_errors = source.parse_errors,
+            truncated = source.truncated,
+            invalid_signatures = source.invalid_signatures,
+            unknown_leader = source.unknown_leader,
+            duplicates = source.duplicates,
+            dropped = source.dropped,
+            bytes_received = source.bytes_received,
+            bytes_per_sec = source.bytes_per_sec,
+            avg_duplicate_delay_nanos = source.avg_duplicate_delay,
+            wins = source.wins,
+            lifetime_wins = source.lifetime_wins,
+            led = source.led,
+            match_rate = source.match_rate,
+            interval_only = source.overlap.as_ref().map(|overlap| overlap.only_source),
+            interval_reference_only = source.overlap.as_ref().map(|overlap| overlap.only_reference),
+            interval_both = source.overlap.as_ref().map(|overlap| overlap.both),
+            truth_delivered = source.truth.as_ref().map(|truth| truth.delivered),
+            truth_expected = source.truth.as_ref().map(|truth| truth.expected),
+            truth_unexpected = source.truth.as_ref().map(|truth| truth.unexpected),
+            truth_latency_mean_nanos = source.truth.as_ref().and_then(|truth| truth.latency_mean),
+            index_loss_expected = source.index_loss.as_ref().map(|loss| loss.expected),
+            index_loss_missing = source.index_loss.as_ref().map(|loss| loss.missing),
+            delay_mean_nanos = source.delay_mean,
+            delay_stddev_nanos = source.delay_stddev,
+            expired = source.expired,
+            expired_unmatched = source.expired_unmatched,
+            first_seen = source.first_seen,
+            seen_later = source.seen_later,
+            avg_seen_later_nanos = source.avg_seen_later,
+            missing_indices = source.missing_indices,
+            stale = source.stale,
+            inter_arrival_mean_nanos = source.inter_arrival.as_ref().map(|gaps| gaps.mean),
+            inter_arrival_stddev_nanos = source.inter_arrival.as_ref().map(|gaps| gaps.stddev),
+            inter_arrival_max_nanos = source.inter_arrival.as_ref().map(|gaps| gaps.max),
+            reorder_max_depth = source.reordering.as_ref().map(|reordering| reordering.max_depth),
+            out_of_order = source.reordering.as_ref().map(ReorderSnapshot::out_of_order),
+            bloom_hits = source.bloom.as_ref().map(|bloom| bloom.hits),
+            bloom_false_positives = source.bloom.as_ref().map(|bloom| bloom.false_positives),
+            bloom_bytes = source.bloom.as_ref().map(|bloom| bloom.bytes),
+            bloom_exact_bytes = source.bloom.as_ref().map(|bloom| bloom.exact_bytes),
+            alerting = source.alerting,
+            baseline_offset_nanos = source.baseline_offset,
+            raw_delay_mean_nanos = source.raw_delay_mean,
+        );
+    }
+    for source in &stats.sources {
+        for bucket in source.eviction_ages.iter().filter(|_| source.expired_unmatched > 0) {
+            tracing::info!(
+                target: "shred_perf::stats",
+                event = "eviction_age",
+                source = %source.name,
+                bucket = %bucket.label,
+                count = bucket.count,
+            );
+        }
+    }
+    for source in &stats.sources {
+        let Some(margins) = &source.lead_margins else {
+            continue;
+        };
+        for (leader, margins) in [(&*source.name, &margins.candidate_led), (stats.reference(), &margins.reference_led)] {
+            tracing::info!(
+                target: "shred_perf::stats",
+                event = "lead_margins",
+                source = %source.name,
+                reference = stats.reference(),
+                leader,
+                led = margins.matched_pairs,
+                samples = margins.samples,
+                avg_nanos = margins.avg,
+                p50_nanos = margins.p50,
+                p99_nanos = margins.p99,
+                max_nanos = margins.max,
+            );
+        }
+    }
+    for delay in &stats.delays {
+        tracing::info!(
+            target: "shred_perf::stats",
+            event = "delay_stats",
+            reference = stats.reference(),
+            shred_type = delay.shred_type,
+            matched_pairs = delay.matched_pairs,
+            samples = delay.samples,
+            avg_nanos = delay.avg,
+            ewma_nanos = delay.ewma,
+            min_nanos = delay.min,
+            p50_nanos = delay.p50,
+            p90_nanos = delay.p90,
+            p99_nanos = delay.p99,
+            max_nanos = delay.max,
+            negative = delay.negative,
+        );
+        for bucket in &delay.histogram {
+            tracing::info!(
+                target: "shred_perf::stats",
+                event = "delay_histogram",
+                shred_type = delay.shred_type,
+                bucket = %bucket.label,
+                count = bucket.count,
+            );
+        }
+    }
+    tracing::info!(
+        target: "shred_perf::stats",
+        event = "delay_hdr",
+        samples = stats.hdr.samples,
+        p50_nanos = stats.hdr.p50,
+        p99_nanos = stats.hdr.p99,
+        p999_nanos = stats.hdr.p999,
+        max_nanos = stats.hdr.max,
+    );
+    if let Some(agreement) = &stats.agreement {
+        for (sources_needed, latency) in [(agreement.quorum_sources, &agreement.quorum), (agreement.sources, &agreement.all)] {
+            tracing::info!(
+                target: "shred_perf::stats",
+                event = "agreement_latency",
+                stage = latency.shred_type,
+                sources_needed,
+                sources = agreement.sources,
+                shreds = latency.matched_pairs,
+                samples = latency.samples,
+                avg_nanos = latency.avg,
+                p50_nanos = latency.p50,
+                p90_nanos = latency.p90,
+                p99_nanos = latency.p99,
+                max_nanos = latency.max,
+                incomplete = agreement.incomplete,
+            );
+        }
+    }
+    for slot in stats.per_slot.iter().flatten() {
+        tracing::info!(target: "shred_perf::stats", event = "slot_stats", slot = slot.slot, avg_nanos = slot.avg, samples = slot.samples);
+    }
+    for slot in stats.completeness.iter().flatten() {
+        for (source, delivered) in stats.sources.iter().zip(&slot.delivered) {
+            tracing::info!(
+                target: "shred_perf::stats",
+                event = "slot_completeness",
+                slot = slot.slot,
+                source = %source.name,
+                delivered,
+                expected = slot.expected,
+            );
+        }
+    }
+    for bucket in stats.per_index.iter().flatten() {
+        tracing::info!(
+            target: "shred_perf::stats",
+            event = "index_bucket_stats",
+            indices = %bucket.label,
+            avg_nanos = bucket.avg,
+            samples = bucket.samples,
+        );
+    }
+    for (rank, laggard) in stats.laggards.iter().flatten().enumerate() {
+        tracing::info!(
+            target: "shred_perf::stats",
+            event = "laggard",
+            rank = rank + 1,
+            slot = laggard.slot,
+            index = laggard.index,
+            shred_type = laggard.shred_type,
+            late = %laggard.late,
+            leader = %laggard.leader,
+            behind_nanos = laggard.behind,
+        );
+    }
+    for slot in stats.per_fec_set.iter().flatten() {
+        for set in &slot.sets {
+            tracing::info!(
+                target: "shred_perf::stats",
+                event = "fec_set_stats",
+                slot = slot.slot,
+                fec_set_index = set.fec_set_index,
+                avg_nanos = set.avg,
+                samples = set.samples,
+                slot_spread_nanos = slot.spread,
+            );
+        }
+    }
+}
+
+/// Streaming mean and variance (Welford's algorithm).
+#[derive(Clone, Default)]
+pub struct Welford {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Welford {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn stddev(&self) -> f64 {
+        if self.count > 1 {
+            (self.m2 / (self.count - 1) as f64).sqrt()
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The snapshot as one JSON object, for --json-stats-out. Fields are named as in the
+/// `--log-format json` stats events, with delays in nanoseconds; the options that aren't
+/// enabled are null.
+pub fn to_json(stats: &StatsSnapshot) -> Value {
+    let sources: Vec<Value> = stats
+        .sources
+        .iter()
+        .map(|source| {
+            json!({
+                "source": &*source.name,
+                "tracked": source.tracked,
+                "datagrams": source.datagrams,
+                "parse_errors": source.parse_errors,
+                "truncated": source.truncated,
+                "stale": source.stale,
+                "invalid_signatures": source.invalid_signatures,
+                "unknown_leader": source.unknown_leader,
+                "duplicates": source.duplicates,
+                "avg_duplicate_delay_nanos": source.avg_duplicate_delay,
+                "dropped": source.dropped,
+                "bytes_received": source.bytes_received,
+                "bytes_per_sec": source.bytes_per_sec,
+                "wins": source.wins,
+                "lifetime_wins": source.lifetime_wins,
+                "led": source.led,
+                "match_rate": source.match_rate,
+                "interval_only": source.overlap.as_ref().map(|overlap| overlap.only_source),
+                "interval_reference_only": source.overlap.as_ref().map(|overlap| overlap.only_reference),
+                "interval_both": source.overlap.as_ref().map(|overlap| overlap.both),
+                "truth_delivered": source.truth.as_ref().map(|truth| truth.delivered),
+                "truth_expected": source.truth.as_ref().map(|truth| truth.expected),
+                "truth_unexpected": source.truth.as_ref().map(|truth| truth.unexpected),
+                "truth_latency_mean_nanos": source.truth.as_ref().and_then(|truth| truth.latency_mean),
+                "index_loss_slots": source.index_loss.as_ref().map(|loss| loss.slots),
+                "index_loss_expected": source.index_loss.as_ref().map(|loss| loss.expected),
+                "index_loss_missing": source.index_loss.as_ref().map(|loss| loss.missing),
+                "delay_mean_nanos": source.delay_mean,
+                "delay_stddev_nanos": source.delay_stddev,
+                "raw_delay_mean_nanos": source.raw_delay_mean,
+                "expired": source.expired,
+                "expired_unmatched": source.expired_unmatched,
+                "first_seen": source.first_seen,
+                "seen_later": source.seen_later,
+                "missing_indices": source.missing_indices,
+                "inter_arrival_mean_nanos": source.inter_arrival.as_ref().map(|gaps| gaps.mean),
+                "reorder_max_depth": source.reordering.as_ref().map(|reordering| reordering.max_depth),
+                "bloom_hits": source.bloom.as_ref().map(|bloom| bloom.hits),
+                "alerting": source.alerting,
+            })
+        })
+        .collect();
+    let delays: Vec<Value> = stats
+        .delays
+        .iter()
+        .map(|delay| {
+            let histogram: serde_json::Map<String, Value> =
+                delay.histogram.iter().map(|bucket| (bucket.label.clone(), bucket.count.into())).collect();
+            json!({
+                "shred_type": delay.shred_type,
+                "matched_pairs": delay.matched_pairs,
+                "samples": delay.samples,
+                "avg_nanos": delay.avg,
+                "ewma_nanos": delay.ewma,
+                "min_nanos": delay.min,
+                "p50_nanos": delay.p50,
+                "p90_nanos": delay.p90,
+                "p99_nanos": delay.p99,
+                "max_nanos": delay.max,
+                "negative": delay.negative,
+                "histogram": histogram,
+            })
+        })
+        .collect();
+    let agreement = stats.agreement.as_ref().map(|agreement| {
+        let stage = |sources_needed: usize, latency: &DelaySnapshot| {
+            json!({
+                "sources_needed": sources_needed,
+                "shreds": latency.matched_pairs,
+                "samples": latency.samples,
+                "avg_nanos": latency.avg,
+                "p50_nanos": latency.p50,
+                "p90_nanos": latency.p90,
+                "p99_nanos": latency.p99,
+                "max_nanos": latency.max,
+            })
+        };
+        json!({
+            "sources": agreement.sources,
+            "quorum": stage(agreement.quorum_sources, &agreement.quorum),
+            "all": stage(agreement.sources, &agreement.all),
+            "incomplete": agreement.incomplete,
+        })
+    });
+    json!({
+        "time": stats.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "uptime_secs": stats.uptime.as_secs(),
+        "first_match_secs": stats.first_match_after.map(|first| first.as_secs_f64()),
+        "reference": stats.reference(),
+        "matched_pairs": stats.matched_pairs,
+        "lifetime_matched_pairs": stats.lifetime_matched_pairs,
+        "cross_type_matches": stats.cross_type_matches,
+        "sources": sources,
+        "delays": delays,
+        "completeness": stats.completeness.as_ref().map(|completeness| {
+            completeness
+                .iter()
+                .map(|slot| {
+                    let delivered: serde_json::Map<String, Value> =
+                        stats.sources.iter().zip(&slot.delivered).map(|(source, delivered)| (source.name.to_string(), (*delivered).into())).collect();
+                    json!({ "slot": slot.slot, "expected": slot.expected, "delivered": delivered })
+                })
+                .collect::<Vec<_>>()
+        }),
+        "agreement": agreement,
+        "hdr": {
+            "samples": stats.hdr.samples,
+            "p50_nanos": stats.hdr.p50,
+            "p99_nanos": stats.hdr.p99,
+            "p999_nanos": stats.hdr.p999,
+            "max_nanos": stats.hdr.max,
+        },
+    })
+}
+
+/// One `key=value` pair per stat, space separated, on a single stdout line.
+pub fn print_kv(stats: &StatsSnapshot) {
+    let line = flat_fields(stats)
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(" ");
+    println!("{}", line);
+}
+
+/// One CSV row per tick on stdout, preceded by a header row the first time.
+pub fn print_csv(stats: &StatsSnapshot, header: bool) {
+    let fields = flat_fields(stats);
+    if header {
+        println!("{}", fields.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>().join(","));
+    }
+    println!("{}", fields.iter().map(|(_, value)| value.as_str()).collect::<Vec<_>>().join(","));
+}
+
+// A fixed set of columns, so consecutive CSV rows line up under one header.
+fn flat_fields(stats: &StatsSnapshot) -> Vec<(String, String)> {
+    let unit = stats.delay_unit;
+    let mut fields = vec![
+        ("time".to_string(), stats.time.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        ("reference".to_string(), stats.reference().to_string()),
+        ("matched_pairs".to_string(), stats.matched_pairs.to_string()),
+        ("uptime_secs".to_string(), stats.uptime.as_secs().to_string()),
+        (
+            "first_match_secs".to_string(),
+            stats.first_match_after.map_or(String::new(), |first| format!("{:.1}", first.as_secs_f64())),
+        ),
+    ];
+    if let Some(rate) = stats.sample_rate {
+        fields.push(("sample_rate".to_string(), rate.to_string()));
+    }
+    if let Some(lifetime) = stats.lifetime_matched_pairs {
+        fields.push(("lifetime_matched_pairs".to_string(), lifetime.to_string()));
+    }
+    for source in &stats.sources {
+        let name = &source.name;
+        fields.extend([
+            (format!("{}_tracked", name), source.tracked.to_string()),
+            (format!("{}_datagrams", name), source.datagrams.to_string()),
+            (format!("{}_parse_errors", name), source.parse_errors.to_string()),
+            (format!("{}_truncated", name), source.truncated.to_string()),
+            (format!("{}_wins", name), source.wins.to_string()),
+            (format!("{}_led", name), source.led.map_or(String::new(), |led| format!("{:.1}", led))),
+            (format!("{}_match_rate", name), source.match_rate.map_or(String::new(), |rate| format!("{:.1}", rate))),
+            (format!("{}_duplicates", name), source.duplicates.to_string()),
+            (format!("{}_dropped", name), source.dropped.to_string()),
+            (format!("{}_bytes_per_sec", name), format!("{:.0}", source.bytes_per_sec)),
+            (format!("{}_delay_mean_{}", name, unit.field_suffix()), unit.value(source.delay_mean)),
+            (format!("{}_delay_stddev_{}", name, unit.field_suffix()), unit.value(source.delay_stddev)),
+        ]);
+        if let Some(lifetime) = source.lifetime_wins {
+            fields.push((format!("{}_lifetime_wins", name), lifetime.to_string()));
+        }
+        if stats.first_seen_wins {
+            fields.extend([
+                (format!("{}_first_seen", name), source.first_seen.to_string()),
+                (format!("{}_seen_later", name), source.seen_later.to_string()),
+                (format!("{}_avg_seen_later_{}", name, unit.field_suffix()), unit.value(source.avg_seen_later)),
+            ]);
+        }
+        if let Some(gaps) = &source.inter_arrival {
+            fields.extend([
+                (format!("{}_inter_arrival_mean_{}", name, unit.field_suffix()), unit.value(gaps.mean)),
+                (format!("{}_inter_arrival_stddev_{}", name, unit.field_suffix()), unit.value(gaps.stddev)),
+                (format!("{}_inter_arrival_max_{}", name, unit.field_suffix()), unit.value(gaps.max)),
+            ]);
+        }
+        if let Some(reordering) = &source.reordering {
+            fields.extend([
+                (format!("{}_reorder_max_depth", name), reordering.max_depth.to_string()),
+                (format!("{}_out_of_order", name), reordering.out_of_order().to_string()),
+            ]);
+        }
+        if let Some(bloom) = &source.bloom {
+            fields.extend([
+                (format!("{}_bloom_hits", name), bloom.hits.to_string()),
+                (format!("{}_bloom_false_positives", name), format!("{:.1}", bloom.false_positives)),
+                (format!("{}_bloom_bytes", name), bloom.bytes.to_string()),
+            ]);
+        }
+        if let Some(overlap) = &source.overlap {
+            fields.extend([
+                (format!("{}_interval_only", name), overlap.only_source.to_string()),
+                (format!("{}_interval_reference_only", name), overlap.only_reference.to_string()),
+                (format!("{}_interval_both", name), overlap.both.to_string()),
+            ]);
+        }
+        if let Some(truth) = &source.truth {
+            fields.extend([
+                (format!("{}_truth_delivered", name), truth.delivered.to_string()),
+                (format!("{}_truth_pct", name), format!("{:.1}", pct(truth.delivered, truth.expected))),
+                (format!("{}_truth_unexpected", name), truth.unexpected.to_string()),
+            ]);
+            if let Some(mean) = truth.latency_mean {
+                fields.push((format!("{}_truth_latency_mean_{}", name, unit.field_suffix()), unit.value(mean)));
+            }
+        }
+        if let Some(loss) = &source.index_loss {
+            fields.extend([
+                (format!("{}_index_loss_missing", name), loss.missing.to_string()),
+                (format!("{}_index_loss_pct", name), format!("{:.2}", pct(loss.missing, loss.expected))),
+            ]);
+        }
+        if let Some(raw) = source.raw_delay_mean {
+            fields.push((format!("{}_raw_delay_mean_{}", name, unit.field_suffix()), unit.value(raw)));
+        }
+        if let Some(alerting) = source.alerting {
+            fields.push((format!("{}_alerting", name), (alerting as u8).to_string()));
+        }
+        if let Some(margins) = &source.lead_margins {
+            fields.extend([
+                (format!("{}_lead_margin_avg_{}", name, unit.field_suffix()), unit.value(margins.candidate_led.avg)),
+                (format!("{}_trail_margin_avg_{}", name, unit.field_suffix()), unit.value(margins.reference_led.avg)),
+            ]);
+        }
+        if stats.gap_slots.is_some() {
+            fields.push((format!("{}_missing_indices", name), source.missing_indices.to_string()));
+        }
+        if stats.max_slot_age.is_some() {
+            fields.push((format!("{}_stale", name), source.stale.to_string()));
+        }
+    }
+    for delay in &stats.delays {
+        let shred_type = delay.shred_type;
+        fields.extend([
+            (format!("{}_matched_pairs", shred_type), delay.matched_pairs.to_string()),
+            (format!("{}_avg_{}", shred_type, unit.field_suffix()), unit.value(delay.avg)),
+            (format!("{}_p50_{}", shred_type, unit.field_suffix()), unit.value(delay.p50)),
+            (format!("{}_p90_{}", shred_type, unit.field_suffix()), unit.value(delay.p90)),
+            (format!("{}_p99_{}", shred_type, unit.field_suffix()), unit.value(delay.p99)),
+            (format!("{}_max_{}", shred_type, unit.field_suffix()), unit.value(delay.max)),
+        ]);
+    }
+    if let Some(agreement) = &stats.agreement {
+        for latency in [&agreement.quorum, &agreement.all] {
+            let stage = latency.shred_type;
+            fields.extend([
+                (format!("time_to_{}_shreds", stage), latency.matched_pairs.to_string()),
+                (format!("time_to_{}_avg_{}", stage, unit.field_suffix()), unit.value(latency.avg)),
+                (format!("time_to_{}_p50_{}", stage, unit.field_suffix()), unit.value(latency.p50)),
+                (format!("time_to_{}_p99_{}", stage, unit.field_suffix()), unit.value(latency.p99)),
+                (format!("time_to_{}_max_{}", stage, unit.field_suffix()), unit.value(latency.max)),
+            ]);
+        }
+        fields.push(("agreement_incomplete".to_string(), agreement.incomplete.to_string()));
+    }
+    fields
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DelayUnit {
+    /// Scale each delay to its size (`1.2ms`, `350µs`); machine-readable output uses nanoseconds.
+    Auto,
+    Ns,
+    Us,
+    Ms,
+}
+
+impl DelayUnit {
+    /// Unit suffix on machine-readable field and column names.
+    pub fn field_suffix(self) -> &'static str {
+        match self {
+            DelayUnit::Auto | DelayUnit::Ns => "nanos",
+            DelayUnit::Us => "us",
+            DelayUnit::Ms => "ms",
+        }
+    }
+
+    /// `nanos` as a bare number in this unit, to nanosecond precision.
+    pub fn value(self, nanos: i64) -> String {
+        match self {
+            DelayUnit::Auto | DelayUnit::Ns => nanos.to_string(),
+            DelayUnit::Us => format!("{:.3}", nanos as f64 / 1e3),
+            DelayUnit::Ms => format!("{:.6}", nanos as f64 / 1e6),
+        }
+    }
+}
+
+pub fn format_delay(nanos: i64, unit: DelayUnit) -> String {
+    match unit {
+        DelayUnit::Auto => {
+            let sign = if nanos < 0 { "-" } else { "" };
+            format!("{}{:?}", sign, Duration::from_nanos(nanos.unsigned_abs()))
+        }
+        DelayUnit::Ns => format!("{}ns", nanos),
+        DelayUnit::Us => format!("{:.3}us", nanos as f64 / 1e3),
+        DelayUnit::Ms => format!("{:.3}ms", nanos as f64 / 1e6),
+    }
+}
+
+pub fn mean_delay<'a>(delays: impl ExactSizeIterator<Item = &'a i64>) -> i64 {
+    let count = delays.len();
+    rounded_mean(delays.map(|d| *d as i128).sum(), count)
+}
+
+/// `sum / count` rounded to the nearest nanosecond, halves away from zero, rather than
+/// truncated towards it; 0 when empty.
+pub fn rounded_mean(sum: i128, count: usize) -> i64 {
+    if count == 0 {
+        return 0;
+    }
+    let count = count as i128;
+    let (quotient, remainder) = (sum / count, sum % count);
+    let rounded = if remainder.abs() * 2 >= count { quotient + remainder.signum() } else { quotient };
+    rounded as i64
+}
+
+/// Nearest-rank percentile of ascending `sorted`, 0 when empty.
+pub fn percentile(sorted: &[i64], pct: f64) -> i64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// Buckets delay magnitudes by `edges` (ascending upper bounds, exclusive), plus one
+// overflow bucket past the last edge.
+fn histogram(delays: &[i64], edges: &[i64], unit: DelayUnit) -> Vec<HistogramBucket> {
+    let mut counts = vec![0; edges.len() + 1];
+    for delay in delays {
+        let magnitude = delay.unsigned_abs() as i64;
+        let bucket = edges.partition_point(|edge| *edge <= magnitude);
+        counts[bucket] += 1;
+    }
+
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(idx, count)| {
+            let label = match (idx.checked_sub(1).map(|i| edges[i]), edges.get(idx)) {
+                (None, Some(upper)) => format!("<{}", format_delay(*upper, unit)),
+                (Some(lower), Some(upper)) => format!("{}-{}", format_delay(lower, unit), format_delay(*upper, unit)),
+                (Some(lower), None) => format!(">{}", format_delay(lower, unit)),
+                (None, None) => "all".to_string(),
+            };
+            HistogramBucket { label, count }
+        })
+        .collect()
+}
+
+pub fn pct(part: u64, total: u64) -> f64 {
+    if total > 0 {
+        part as f64 * 100.0 / total as f64
+    } else {
+        0.0
+    }
+}
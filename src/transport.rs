@@ -0,0 +1,192 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, TcpSocket};
+use tracing::{info, warn};
+use crate::{bind_with_retry, Forwarder, ListenerConfig};
+
+/// How a source's shreds reach us, from the scheme of its `--source` address.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scheme {
+    /// One shred per datagram, the default.
+    #[default]
+    Udp,
+    /// Length-prefixed shreds over TCP connections.
+    Tcp,
+    /// Length-prefixed shreds over QUIC unidirectional streams.
+    Quic,
+}
+
+impl Scheme {
+    /// Whether the listener takes a UDP port, which only one source can bind.
+    pub fn binds_udp(self) -> bool {
+        self != Scheme::Tcp
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "udp" => Ok(Scheme::Udp),
+            "tcp" => Ok(Scheme::Tcp),
+            "quic" => Ok(Scheme::Quic),
+            _ => Err(format!("unknown transport `{}`, expected udp, tcp or quic", s)),
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scheme::Udp => "udp",
+            Scheme::Tcp => "tcp",
+            Scheme::Quic => "quic",
+        })
+    }
+}
+
+/// Receives one source's shreds on one address. Every transport hands each shred to the
+/// source's `Forwarder` with its arrival time, so events, matching and stats don't depend
+/// on how it arrived.
+pub trait Transport {
+    /// Binds `addr` and forwards shreds until the listener fails.
+    async fn listen(self, addr: SocketAddr, config: ListenerConfig, forwarder: Forwarder) -> anyhow::Result<()>;
+}
+
+/// Accepts any number of TCP connections, each a stream of length-prefixed shreds.
+pub struct Tcp;
+
+impl Transport for Tcp {
+    async fn listen(self, addr: SocketAddr, config: ListenerConfig, forwarder: Forwarder) -> anyhow::Result<()> {
+        let listener = bind_with_retry(&forwarder.name, addr, &config, || bind_tcp(addr, &config)).await?;
+        info!(target: "shred_perf::socket", "[{}] Listening on tcp://{}", forwarder.name, addr);
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(target: "shred_perf::socket", "[{}] Failed to accept a connection: {}", forwarder.name, e);
+                    continue;
+                }
+            };
+            let forwarder = forwarder.clone();
+            tokio::spawn(async move {
+                info!(target: "shred_perf::socket", "[{}] {} connected", forwarder.name, peer);
+                match read_frames(stream, &forwarder).await {
+                    Ok(()) => info!(target: "shred_perf::socket", "[{}] {} disconnected", forwarder.name, peer),
+                    Err(e) => warn!(target: "shred_perf::socket", "[{}] {} dropped: {}", forwarder.name, peer, e),
+                }
+            });
+        }
+    }
+}
+
+pub fn bind_tcp(addr: SocketAddr, config: &ListenerConfig) -> io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    socket.set_reuseaddr(true)?;
+    socket.set_recv_buffer_size(config.rcvbuf_bytes.try_into().unwrap_or(u32::MAX))?;
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+// Reads shreds framed as a big-endian u16 length and then the shred itself, until the
+// peer closes the stream between two frames. A frame is timestamped once it has fully
+// arrived.
+pub async fn read_frames(stream: impl AsyncRead + Unpin, forwarder: &Forwarder) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut buf = vec![0u8; u16::MAX as usize];
+    loop {
+        let len = match stream.read_u16().await {
+            Ok(len) => len as usize,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        stream.read_exact(&mut buf[..len]).await?;
+        forwarder.forward(&buf[..len], Instant::now());
+    }
+}
+
+#[cfg(feature = "quic")]
+pub use quic::Quic;
+
+#[cfg(feature = "quic")]
+mod quic {
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use std::sync::Arc;
+    use anyhow::Context;
+    use quinn::rustls::pki_types::pem::PemObject;
+    use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use quinn::{Endpoint, EndpointConfig, ServerConfig, TokioRuntime};
+    use tracing::{info, warn};
+    use super::{read_frames, Transport};
+    use crate::{bind_socket, bind_with_retry, Forwarder, ListenerConfig};
+
+    /// Accepts QUIC connections and reads length-prefixed shreds from every
+    /// unidirectional stream a peer opens.
+    #[derive(Clone, Debug)]
+    pub struct Quic {
+        server_config: ServerConfig,
+    }
+
+    impl Quic {
+        /// Serves the PEM certificate chain in `cert` with the private key in `key`.
+        pub fn new(cert: &Path, key: &Path) -> anyhow::Result<Self> {
+            let certs = CertificateDer::pem_file_iter(cert)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .with_context(|| format!("failed to read certificates from {}", cert.display()))?;
+            let key = PrivateKeyDer::from_pem_file(key)
+                .with_context(|| format!("failed to read private key from {}", key.display()))?;
+            let server_config = ServerConfig::with_single_cert(certs, key).context("invalid QUIC certificate or key")?;
+            Ok(Self { server_config })
+        }
+    }
+
+    impl Transport for Quic {
+        async fn listen(self, addr: SocketAddr, config: ListenerConfig, forwarder: Forwarder) -> anyhow::Result<()> {
+            // Bound like a UDP source, so --rcvbuf-bytes applies.
+            let (socket, _) = bind_with_retry(&forwarder.name, addr, &config, || bind_socket(addr, &config)).await?;
+            let endpoint = Endpoint::new(
+                EndpointConfig::default(),
+                Some(self.server_config),
+                socket.into_std()?,
+                Arc::new(TokioRuntime),
+            )?;
+            info!(target: "shred_perf::socket", "[{}] Listening on quic://{}", forwarder.name, addr);
+            while let Some(incoming) = endpoint.accept().await {
+                let forwarder = forwarder.clone();
+                tokio::spawn(async move {
+                    let peer = incoming.remote_address();
+                    let connection = match incoming.await {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            warn!(target: "shred_perf::socket", "[{}] QUIC handshake with {} failed: {}", forwarder.name, peer, e);
+                            return;
+                        }
+                    };
+                    info!(target: "shred_perf::socket", "[{}] {} connected", forwarder.name, peer);
+                    loop {
+                        let stream = match connection.accept_uni().await {
+                            Ok(stream) => stream,
+                            Err(e) => {
+                                info!(target: "shred_perf::socket", "[{}] {} disconnected: {}", forwarder.name, peer, e);
+                                return;
+                            }
+                        };
+                        let forwarder = forwarder.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = read_frames(stream, &forwarder).await {
+                                warn!(target: "shred_perf::socket", "[{}] Stream from {} dropped: {}", forwarder.name, peer, e);
+                            }
+                        });
+                    }
+                });
+            }
+            Ok(())
+        }
+    }
+}
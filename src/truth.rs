@@ -0,0 +1,46 @@
+use std::fs;
+use std::path::Path;
+use anyhow::Context;
+use solana_ledger::shred::{ShredId, ShredType};
+
+/// A trusted record of the shreds a slot range holds, for --reference-shreds, with when
+/// the recording node received each one if known.
+pub struct ReferenceShreds {
+    shreds: Vec<(ShredId, Option<u64>)>,
+}
+
+impl ReferenceShreds {
+    pub fn new(shreds: Vec<(ShredId, Option<u64>)>) -> Self {
+        Self { shreds }
+    }
+
+    /// Reads `<slot> <index> <data|code> [<unix nanos>]` lines. Blank lines and lines
+    /// starting with `#` are skipped.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut shreds = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let context = || format!("{}:{}: expected `<slot> <index> <data|code> [<unix nanos>]`", path.display(), line_no + 1);
+            let mut fields = line.split_whitespace();
+            let slot = fields.next().and_then(|f| f.parse().ok()).with_context(context)?;
+            let index = fields.next().and_then(|f| f.parse().ok()).with_context(context)?;
+            let shred_type = match fields.next() {
+                Some("data") => ShredType::Data,
+                Some("code") => ShredType::Code,
+                _ => return Err(anyhow::anyhow!(context())),
+            };
+            let received = fields.next().map(|f| f.parse()).transpose().ok().with_context(context)?;
+            shreds.push((ShredId::new(slot, index, shred_type), received));
+        }
+        Ok(Self { shreds })
+    }
+
+    /// Each expected shred, with its unix receive time in nanoseconds if recorded.
+    pub fn shreds(&self) -> &[(ShredId, Option<u64>)] {
+        &self.shreds
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::mpsc;
+use tracing_subscriber::fmt::MakeWriter;
+use crate::stats::{format_delay, pct, rounded_mean, StatsSnapshot};
+
+// Log lines the log pane keeps, and stats reports of delay history for the sparkline.
+const LOG_LINES: usize = 500;
+const HISTORY: usize = 1000;
+
+/// A tracing writer for --tui. While a [`Dashboard`] is open, log lines go to its log
+/// pane rather than over the screen; before and after, to stdout or stderr as usual.
+#[derive(Clone)]
+pub struct LogPane {
+    to_stdout: bool,
+    // `None` while nothing is capturing.
+    lines: Arc<Mutex<Option<VecDeque<String>>>>,
+}
+
+impl LogPane {
+    /// Writes through to stdout with `to_stdout`, otherwise stderr, until captured.
+    pub fn new(to_stdout: bool) -> Self {
+        Self {
+            to_stdout,
+            lines: Arc::default(),
+        }
+    }
+
+    fn capture(&self, capture: bool) {
+        *self.lines.lock().unwrap() = capture.then(VecDeque::new);
+    }
+
+    // The most recent `count` lines, oldest first.
+    fn tail(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        lines.as_ref().map_or(Vec::new(), |lines| lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect())
+    }
+}
+
+impl Write for LogPane {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut lines = self.lines.lock().unwrap();
+        let Some(lines) = lines.as_mut() else {
+            return if self.to_stdout { io::stdout().write(buf) } else { io::stderr().write(buf) };
+        };
+        for line in String::from_utf8_lossy(buf).lines() {
+            if lines.len() == LOG_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.to_stdout {
+            io::stdout().flush()
+        } else {
+            io::stderr().flush()
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogPane {
+    type Writer = LogPane;
+
+    fn make_writer(&'a self) -> LogPane {
+        self.clone()
+    }
+}
+
+/// The --tui view: every stats report drawn in place on the terminal, with a sparkline of
+/// the delay over recent reports and the latest log lines. Dropping it gives the
+/// terminal back.
+pub struct Dashboard {
+    terminal: DefaultTerminal,
+    logs: LogPane,
+    // |average delay| over the delay window at each report, in microseconds.
+    history: VecDeque<u64>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Dashboard {
+    /// Takes over the terminal and starts capturing `logs`. Raw mode swallows Ctrl-C, so
+    /// the returned channel gets a message instead when Ctrl-C, q or Esc is pressed.
+    pub fn open(logs: LogPane) -> io::Result<(Self, mpsc::UnboundedReceiver<()>)> {
+        let terminal = ratatui::try_init()?;
+        logs.capture(true);
+        let closed = Arc::new(AtomicBool::new(false));
+        let (quit_tx, quit_rx) = mpsc::unbounded_channel();
+        let stop = Arc::clone(&closed);
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                // Polled, so the thread notices the dashboard closing.
+                if !event::poll(Duration::from_millis(100)).unwrap_or(false) {
+                    continue;
+                }
+                let Ok(Event::Key(key)) = event::read() else {
+                    continue;
+                };
+                let ctrl_c = key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+                if key.kind == KeyEventKind::Press && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)) {
+                    quit_tx.send(()).ok();
+                }
+            }
+        });
+        let dashboard = Self {
+            terminal,
+            logs,
+            history: VecDeque::new(),
+            closed,
+        };
+        Ok((dashboard, quit_rx))
+    }
+
+    /// Redraws the dashboard from one stats report.
+    pub fn draw(&mut self, stats: &StatsSnapshot) -> io::Result<()> {
+        let (sum, samples) = stats
+            .delays
+            .iter()
+            .fold((0i128, 0usize), |(sum, samples), delay| (sum + delay.sum, samples + delay.samples));
+        let avg = rounded_mean(sum, samples);
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(avg.unsigned_abs() / 1000);
+        let (history, logs) = (&self.history, &self.logs);
+        self.terminal.draw(|frame| render(frame, stats, avg, history, logs))?;
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        ratatui::restore();
+        self.logs.capture(false);
+    }
+}
+
+fn render(frame: &mut Frame, stats: &StatsSnapshot, avg: i64, history: &VecDeque<u64>, logs: &LogPane) {
+    let unit = stats.delay_unit;
+    let [header, sources, delays, sparkline, log] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(stats.sources.len() as u16 + 3),
+        Constraint::Length(stats.delays.len() as u16 + 3),
+        Constraint::Length(6),
+        Constraint::Min(3),
+    ])
+    .areas(frame.area());
+
+    let first_match = match stats.first_match_after {
+        Some(first) => format!("first match after {:.1}s", first.as_secs_f64()),
+        None => "no matches yet".to_string(),
+    };
+    let summary = format!(
+        " Matched: {} | Reference: {} | Uptime: {}s, {} | q to quit",
+        stats.matched_pairs,
+        stats.reference(),
+        stats.uptime.as_secs(),
+        first_match
+    );
+    frame.render_widget(Paragraph::new(summary).bold(), header);
+
+    let rows = stats.sources.iter().enumerate().map(|(idx, source)| {
+        let candidate = idx != stats.reference_idx;
+        Row::new(vec![
+            if candidate { source.name.to_string() } else { format!("{} (ref)", source.name) },
+            source.datagrams.to_string(),
+            source.tracked.to_string(),
+            source.match_rate.map_or("-".to_string(), |rate| format!("{:.1}%", rate)),
+            format!("{} ({:.0}%)", source.wins, pct(source.wins as u64, stats.matched_pairs as u64)),
+            source.led.map_or("-".to_string(), |led| format!("{:.1}%", led)),
+            if candidate { format_delay(source.delay_mean, unit) } else { "-".to_string() },
+            source.dropped.to_string(),
+        ])
+    });
+    let header_row = ["Source", "Received", "Tracked", "Match rate", "Wins", "Led", "Mean delay", "Dropped"];
+    frame.render_widget(table(header_row, rows, " Sources "), sources);
+
+    let rows = stats.delays.iter().map(|delay| {
+        Row::new(vec![
+            delay.shred_type.to_string(),
+            delay.matched_pairs.to_string(),
+            delay.samples.to_string(),
+            format_delay(delay.avg, unit),
+            format_delay(delay.p50, unit),
+            format_delay(delay.p90, unit),
+            format_delay(delay.p99, unit),
+            format_delay(delay.max, unit),
+        ])
+    });
+    let header_row = ["Type", "Matched", "Window", "Avg", "p50", "p90", "p99", "Max"];
+    let title = format!(" Delay vs {} ", stats.reference());
+    frame.render_widget(table(header_row, rows, &title), delays);
+
+    // The newest reports that fit, right-aligned on the latest.
+    let width = sparkline.width.saturating_sub(2) as usize;
+    let title = format!(" |avg delay| per report (now {}) ", format_delay(avg, unit));
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title(title))
+            .data(history.iter().skip(history.len().saturating_sub(width))),
+        sparkline,
+    );
+
+    let lines = logs.tail(log.height.saturating_sub(2) as usize);
+    frame.render_widget(Paragraph::new(lines.into_iter().map(Line::from).collect::<Vec<_>>()).block(Block::bordered().title(" Log ")), log);
+}
+
+fn table<'a>(header: [&'a str; 8], rows: impl Iterator<Item = Row<'a>>, title: &'a str) -> Table<'a> {
+    Table::new(rows, [Constraint::Fill(1); 8])
+        .header(Row::new(header).style(Style::new().bold()))
+        .block(Block::bordered().title(title))
+}